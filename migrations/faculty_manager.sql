@@ -3,9 +3,14 @@
 
 CREATE TABLE IF NOT EXISTS verified_users (
     user_id BIGINT PRIMARY KEY NOT NULL,
-    user_email TEXT NOT NULL
+    user_email TEXT NOT NULL,
+    last_verified_at TIMESTAMPTZ DEFAULT CURRENT_TIMESTAMP
 );
 
+-- existing rows keep NULL, they were verified before we started tracking it
+ALTER TABLE verified_users ADD COLUMN IF NOT EXISTS last_verified_at TIMESTAMPTZ;
+ALTER TABLE verified_users ALTER COLUMN last_verified_at SET DEFAULT CURRENT_TIMESTAMP;
+
 CREATE TABLE IF NOT EXISTS user_xp (
     user_id BIGINT PRIMARY KEY NOT NULL,
     user_xp FLOAT8 NOT NULL DEFAULT 0.0,
@@ -21,9 +26,87 @@ CREATE TABLE IF NOT EXISTS voice_channels (
 );
 
 CREATE TABLE IF NOT EXISTS mensaplan (
-    date TEXT PRIMARY KEY NOT NULL,
+    date TEXT NOT NULL,
+    guild_id BIGINT NOT NULL DEFAULT 0,
+    canteen TEXT NOT NULL DEFAULT '',
     posted BOOL NOT NULL DEFAULT FALSE,
-    plan_hash TEXT NOT NULL DEFAULT ''
+    plan_hash TEXT NOT NULL DEFAULT '',
+    channel_id BIGINT,
+    message_id BIGINT,
+    week_start DATE,
+    PRIMARY KEY (date, guild_id, canteen)
+);
+
+-- plans used to be tracked for a single server and canteen only
+ALTER TABLE mensaplan ADD COLUMN IF NOT EXISTS guild_id BIGINT NOT NULL DEFAULT 0;
+ALTER TABLE mensaplan ADD COLUMN IF NOT EXISTS canteen TEXT NOT NULL DEFAULT '';
+ALTER TABLE mensaplan DROP CONSTRAINT IF EXISTS mensaplan_pkey;
+ALTER TABLE mensaplan ADD PRIMARY KEY (date, guild_id, canteen);
+-- the single canteen of old configs is called "Mensa" now, the guild of old plans is filled in by
+-- the bot on startup since it isn't known here
+UPDATE mensaplan SET canteen = 'Mensa' WHERE canteen = ''
+    AND NOT EXISTS (SELECT 1 FROM mensaplan m WHERE m.date = mensaplan.date AND m.guild_id = mensaplan.guild_id AND m.canteen = 'Mensa');
+
+-- the posted message is kept so corrected plans can be announced as a reply
+ALTER TABLE mensaplan ADD COLUMN IF NOT EXISTS channel_id BIGINT;
+ALTER TABLE mensaplan ADD COLUMN IF NOT EXISTS message_id BIGINT;
+
+-- the week the posted plan covers, so the plan of the next week isn't announced as a correction
+ALTER TABLE mensaplan ADD COLUMN IF NOT EXISTS week_start DATE;
+
+-- dishes parsed from a mensaplan PDF, identified by the hash of the PDF
+CREATE TABLE IF NOT EXISTS mensaplan_dishes (
+    dish_id SERIAL PRIMARY KEY,
+    plan_hash TEXT NOT NULL,
+    day DATE NOT NULL,
+    position INTEGER NOT NULL,
+    name TEXT NOT NULL,
+    prices DOUBLE PRECISION[] NOT NULL DEFAULT '{}',
+    allergens TEXT[] NOT NULL DEFAULT '{}',
+    vegetarian BOOL NOT NULL DEFAULT FALSE,
+    vegan BOOL NOT NULL DEFAULT FALSE
+);
+
+CREATE INDEX IF NOT EXISTS mensaplan_dishes_plan_hash ON mensaplan_dishes (plan_hash);
+
+-- the plan last fetched for a week, so weeks the current PDF doesn't cover anymore can be shown
+CREATE TABLE IF NOT EXISTS mensaplan_weeks (
+    url TEXT NOT NULL,
+    week_start DATE NOT NULL,
+    plan_hash TEXT NOT NULL,
+    fetched_at TIMESTAMPTZ NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    PRIMARY KEY (url, week_start)
+);
+
+-- the plan last downloaded from an url, so commands don't download the PDF on every use
+CREATE TABLE IF NOT EXISTS mensaplan_fetches (
+    url TEXT PRIMARY KEY NOT NULL,
+    plan_hash TEXT NOT NULL,
+    fetched_at TIMESTAMPTZ NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+-- users getting the dishes of the day as a DM
+CREATE TABLE IF NOT EXISTS mensa_subscriptions (
+    user_id BIGINT PRIMARY KEY NOT NULL,
+    guild_id BIGINT,
+    notify_at TIME NOT NULL,
+    last_sent DATE
+);
+
+-- users getting a DM when a dish matching the keyword is on the plan
+CREATE TABLE IF NOT EXISTS mensa_keywords (
+    user_id BIGINT NOT NULL,
+    keyword TEXT NOT NULL,
+    guild_id BIGINT,
+    PRIMARY KEY (user_id, keyword)
+);
+
+-- dishes a user was already alerted about
+CREATE TABLE IF NOT EXISTS mensa_alerts (
+    user_id BIGINT NOT NULL,
+    day DATE NOT NULL,
+    dish TEXT NOT NULL,
+    PRIMARY KEY (user_id, day, dish)
 );
 
 CREATE TABLE IF NOT EXISTS ads (
@@ -45,3 +128,90 @@ CREATE TABLE IF NOT EXISTS posted_rss (
     rss_title TEXT NOT NULL,
     channel_id BIGINT NOT NULL
 );
+
+-- what was seen of every feed posted in a channel, so only new items get posted
+CREATE TABLE IF NOT EXISTS rss_feeds (
+    channel_id BIGINT NOT NULL,
+    url TEXT NOT NULL,
+    last_guid TEXT,
+    last_pub_date TIMESTAMPTZ,
+    etag TEXT,
+    last_modified TEXT,
+    checked_at TIMESTAMPTZ,
+    guild_id BIGINT,
+    template TEXT,
+    mention_role BIGINT,
+    PRIMARY KEY (channel_id, url)
+);
+
+-- feeds can be subscribed with /feeds, each with its own message template and role to mention
+ALTER TABLE rss_feeds ADD COLUMN IF NOT EXISTS guild_id BIGINT;
+ALTER TABLE rss_feeds ADD COLUMN IF NOT EXISTS template TEXT;
+ALTER TABLE rss_feeds ADD COLUMN IF NOT EXISTS mention_role BIGINT;
+
+CREATE TABLE IF NOT EXISTS voice_sessions (
+    session_id SERIAL PRIMARY KEY,
+    guild_id BIGINT NOT NULL,
+    user_id BIGINT NOT NULL,
+    channel_id BIGINT NOT NULL,
+    started_at TIMESTAMPTZ NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    last_seen_at TIMESTAMPTZ NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    ended_at TIMESTAMPTZ,
+    -- minutes that earned xp, i.e. unmuted with someone else in the channel
+    active_minutes FLOAT8 NOT NULL DEFAULT 0.0,
+    xp FLOAT8 NOT NULL DEFAULT 0.0
+);
+
+CREATE INDEX IF NOT EXISTS voice_sessions_open ON voice_sessions (guild_id) WHERE ended_at IS NULL;
+
+CREATE TABLE IF NOT EXISTS podcast_episodes (
+    guild_id BIGINT NOT NULL,
+    feed_url TEXT NOT NULL,
+    episode_key TEXT NOT NULL,
+    title TEXT,
+    message_id BIGINT,
+    posted_at TIMESTAMPTZ NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    PRIMARY KEY (guild_id, feed_url, episode_key)
+);
+
+
+CREATE TABLE IF NOT EXISTS pending_verifications (
+    user_id BIGINT PRIMARY KEY NOT NULL,
+    user_email TEXT NOT NULL,
+    email_hash TEXT NOT NULL,
+    code_hash TEXT NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    attempts INTEGER NOT NULL DEFAULT 0
+);
+
+CREATE TABLE IF NOT EXISTS reverification_campaigns (
+    campaign_id SERIAL PRIMARY KEY,
+    guild_id BIGINT NOT NULL,
+    cutoff DATE NOT NULL,
+    deadline TIMESTAMPTZ NOT NULL,
+    remove_role BOOL NOT NULL DEFAULT FALSE,
+    reminder_sent BOOL NOT NULL DEFAULT FALSE,
+    finished BOOL NOT NULL DEFAULT FALSE,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE TABLE IF NOT EXISTS reverification_requests (
+    campaign_id INTEGER NOT NULL REFERENCES reverification_campaigns (campaign_id) ON DELETE CASCADE,
+    user_id BIGINT NOT NULL,
+    completed_at TIMESTAMPTZ,
+    PRIMARY KEY (campaign_id, user_id)
+);
+-- members that couldn't be DMed are asked to reverify all the same
+ALTER TABLE reverification_requests ADD COLUMN IF NOT EXISTS dm_failed BOOLEAN NOT NULL DEFAULT FALSE;
+
+CREATE TABLE IF NOT EXISTS guild_settings (
+    guild_id BIGINT PRIMARY KEY NOT NULL,
+    settings JSONB NOT NULL
+);
+
+CREATE TABLE IF NOT EXISTS scheduled_tasks (
+    name TEXT PRIMARY KEY NOT NULL,
+    last_run TIMESTAMPTZ,
+    failures INTEGER NOT NULL DEFAULT 0,
+    last_error TEXT
+);
@@ -1,14 +1,21 @@
-use crate::{prelude::Error, structs, utils, Context};
+use super::levelroles::MEMBERS_PER_PAGE;
+use super::mensa::{canteen_autocomplete, selected_canteen};
+use crate::{
+    mensaplan,
+    prelude::{translations::Lang, Error},
+    structs, utils, verification, xp, Context,
+};
 use poise::serenity_prelude::{self as serenity, Permissions};
 
-async fn executor_is_dev_or_admin(ctx: Context<'_>) -> Result<bool, Error> {
+pub async fn executor_is_dev_or_admin(ctx: Context<'_>) -> Result<bool, Error> {
     let _db = &ctx.data().db;
     let member = ctx.author_member().await.unwrap();
+    let config = ctx.data().config.resolve(ctx.guild_id()).await?;
 
     let has_perms = member
         .roles
-        .contains(&ctx.data().config.roles.semestermodrole)
-        || member.roles.contains(&ctx.data().config.roles.staffrole);
+        .contains(&config.roles.semestermodrole)
+        || member.roles.contains(&config.roles.staffrole);
 
     if has_perms {
         Ok(true)
@@ -129,6 +136,10 @@ pub async fn set_xp(
 ) -> Result<(), Error> {
     let pool = &ctx.data().db;
     let uid = user.id.0 as i64;
+    let guild_id = ctx.guild_id().unwrap();
+    let config = ctx.data().config.get(guild_id).await?;
+    let level = ctx.data().config.leveling().level_for_xp(xp as f64);
+
     let db_user = sqlx::query_as::<sqlx::Postgres, structs::UserXP>(
         "SELECT * FROM user_xp WHERE user_id = $1",
     )
@@ -139,7 +150,7 @@ pub async fn set_xp(
 
     if let Some(mut db_usr) = db_user {
         db_usr.user_xp = xp as f64;
-        db_usr.user_level = (db_usr.user_xp / 100.0).floor() as i32;
+        db_usr.user_level = level;
 
         sqlx::query("UPDATE user_xp SET user_xp = $1, user_level = $2 WHERE user_id = $3")
             .bind(db_usr.user_xp)
@@ -152,7 +163,7 @@ pub async fn set_xp(
         let new_user = structs::UserXP {
             user_id: uid,
             user_xp: xp as f64,
-            user_level: (xp as f64 / 100.0).floor() as i32,
+            user_level: level,
         };
 
         sqlx::query("INSERT INTO user_xp (user_id, user_xp, user_level) VALUES ($1, $2, $3)")
@@ -164,6 +175,21 @@ pub async fn set_xp(
             .map_err(Error::Database)?;
     }
 
+    // the level roles follow the new level right away
+    if !config.level_roles.roles.is_empty() {
+        let mut member = guild_id
+            .member(ctx.serenity_context(), user.id)
+            .await
+            .map_err(Error::Serenity)?;
+        xp::sync_level_roles(
+            ctx.serenity_context(),
+            &mut member,
+            level,
+            &config.level_roles,
+        )
+        .await?;
+    }
+
     ctx.say(&format!("Set XP of {} to {}", user.tag(), xp))
         .await
         .map_err(Error::Serenity)?;
@@ -171,6 +197,60 @@ pub async fn set_xp(
     Ok(())
 }
 
+/// Recompute the levels of all users, e.g. after changing the level curve
+#[poise::command(
+    slash_command,
+    prefix_command,
+    rename = "recompute-levels",
+    name_localized("de", "level-neu-berechnen"),
+    description_localized("de", "Berechne die Level aller Nutzer neu, z.B. nach Ändern der Levelkurve"),
+    check = "executor_is_dev_or_admin",
+    guild_only
+)]
+pub async fn recompute_levels(ctx: Context<'_>) -> Result<(), Error> {
+    let pool = &ctx.data().db;
+    let config = ctx.data().config.get(ctx.guild_id().unwrap()).await?;
+    let leveling = ctx.data().config.leveling();
+
+    ctx.defer().await.map_err(Error::Serenity)?;
+
+    let users = sqlx::query_as::<sqlx::Postgres, structs::UserXP>("SELECT * FROM user_xp")
+        .fetch_all(pool)
+        .await
+        .map_err(Error::Database)?;
+
+    // all levels or none change, a half-migrated table would mix two curves
+    let mut transaction = pool.begin().await.map_err(Error::Database)?;
+    let mut changed = 0;
+    for user in &users {
+        let level = leveling.level_for_xp(user.user_xp);
+        if level == user.user_level {
+            continue;
+        }
+        sqlx::query("UPDATE user_xp SET user_level = $1 WHERE user_id = $2")
+            .bind(level)
+            .bind(user.user_id)
+            .execute(&mut transaction)
+            .await
+            .map_err(Error::Database)?;
+        changed += 1;
+    }
+    transaction.commit().await.map_err(Error::Database)?;
+
+    let mut message = format!(
+        "Recomputed the levels of {} users with the {:?} curve, {} changed",
+        users.len(),
+        leveling.curve.name(),
+        changed
+    );
+    if changed > 0 && !config.level_roles.roles.is_empty() {
+        message.push_str("\nRun `/levelroles resync` to update the level roles");
+    }
+
+    ctx.say(message).await.map_err(Error::Serenity)?;
+    Ok(())
+}
+
 /// Force-Post mensaplan
 #[poise::command(
     slash_command,
@@ -181,13 +261,34 @@ pub async fn set_xp(
     required_permissions = "MANAGE_GUILD",
     guild_only
 )]
-pub async fn force_post_mensaplan(ctx: Context<'_>) -> Result<(), Error> {
-    let _pool = &ctx.data().db;
-    let mensaplan_url = &ctx.data().config.mealplan.url;
-    let mensaplan_channel = &ctx.data().config.channels.mealplan;
-    let _mention_role = &ctx.data().config.roles.mealplannotify;
-
-    let mp_bytestream = utils::fetch_mensaplan(mensaplan_url).await?;
+pub async fn force_post_mensaplan(
+    ctx: Context<'_>,
+    #[description = "The canteen to post the plan of, defaults to the first one"]
+    #[description_localized("de", "Die Mensa, deren Plan gepostet wird, standardmäßig die erste")]
+    #[autocomplete = "canteen_autocomplete"]
+    canteen: Option<String>,
+) -> Result<(), Error> {
+    let guild_id = ctx.guild_id().unwrap();
+    let lang = match ctx.locale() {
+        Some("de") => Lang::De,
+        Some("ja") => Lang::Ja,
+        _ => Lang::En,
+    };
+    let config = ctx.data().config.get(guild_id).await?;
+    let canteen = selected_canteen(ctx, &lang, canteen).await?;
+    let mensaplan_channel = &canteen.channel;
+
+    let mp_pdf = utils::download_mensaplan(&canteen.url).await?;
+    let mp_pages = utils::render_mensaplan(&mp_pdf, &config.mealplan.imgsettings).await?;
+    let mp_hash = utils::mensaplan_hash(&mp_pdf);
+    let menus = match mensaplan::menus(&ctx.data().db, &canteen.url, &mp_pdf, &mp_hash).await {
+        Ok(menus) => menus,
+        Err(why) => {
+            tracing::error!("Failed to parse mensaplan: {}", why);
+            Vec::new()
+        }
+    };
+    let week = menus.first().map(|menu| mensaplan::week_start(menu.date));
 
     let now = chrono::Local::now();
 
@@ -195,25 +296,35 @@ pub async fn force_post_mensaplan(ctx: Context<'_>) -> Result<(), Error> {
 
     let force_post = mensaplan_channel
         .send_message(&ctx, |msg| {
-            msg.add_file(serenity::AttachmentType::Bytes {
-                data: std::borrow::Cow::Borrowed(&mp_bytestream),
-                filename: "mensaplan.png".to_string(),
-            })
+            if !menus.is_empty() {
+                msg.embed(|e| mensaplan::create_embed(e, &menus).title(&canteen.name));
+            }
+            msg.add_files(utils::mensaplan_attachments(&mp_pages))
         })
         .await
         .map_err(Error::Serenity)?;
 
     force_post.crosspost(&ctx).await.map_err(Error::Serenity)?;
 
-    // Update last posted date
-    sqlx::query("INSERT INTO mensaplan (date, posted) VALUES ($1, $2) ON CONFLICT DO NOTHING")
-        .bind(&today)
-        .bind(true)
-        .execute(&ctx.data().db)
-        .await
-        .map_err(Error::Database)?;
+    // Update last posted date, the hash lets the mensaplan task notice later corrections
+    sqlx::query(
+        "INSERT INTO mensaplan (date, guild_id, canteen, posted, plan_hash, channel_id, message_id, week_start)
+        VALUES ($1, $2, $3, $4, $5, $6, $7, $8)
+        ON CONFLICT (date, guild_id, canteen) DO UPDATE SET posted = $4, plan_hash = $5, channel_id = $6, message_id = $7, week_start = $8",
+    )
+    .bind(&today)
+    .bind(guild_id.0 as i64)
+    .bind(&canteen.name)
+    .bind(true)
+    .bind(&mp_hash)
+    .bind(mensaplan_channel.0 as i64)
+    .bind(force_post.id.0 as i64)
+    .bind(week)
+    .execute(&ctx.data().db)
+    .await
+    .map_err(Error::Database)?;
 
-    ctx.say(&format!("Mensaplan für {} gepostet", today))
+    ctx.say(&format!("Mensaplan ({}) für {} gepostet", canteen.name, today))
         .await
         .map_err(Error::Serenity)?;
 
@@ -469,7 +580,14 @@ pub async fn post(
 
     let rules_channel = match channel {
         Some(channel) => channel.id,
-        None => ctx.data().config.channels.rules,
+        None => {
+            ctx.data()
+                .config
+                .resolve(ctx.guild_id())
+                .await?
+                .channels
+                .rules
+        }
     };
 
     rules_channel
@@ -486,6 +604,118 @@ pub async fn post(
     Ok(())
 }
 
+/// Base command for bot configuration
+#[poise::command(
+    slash_command,
+    prefix_command,
+    rename = "config",
+    name_localized("de", "config"),
+    description_localized("de", "Befehle zur Konfiguration des Bots"),
+    owners_only,
+    subcommands("reload")
+)]
+pub async fn config_command(ctx: Context<'_>) -> Result<(), Error> {
+    ctx.say("Use one of the subcommands, e.g. `config reload`")
+        .await
+        .map_err(Error::Serenity)?;
+    Ok(())
+}
+
+/// Reload config.json without restarting the bot
+#[poise::command(
+    slash_command,
+    prefix_command,
+    rename = "reload",
+    name_localized("de", "reload"),
+    description_localized("de", "Lade die config.json neu, ohne den Bot neu zu starten"),
+    owners_only,
+    ephemeral
+)]
+pub async fn reload(ctx: Context<'_>) -> Result<(), Error> {
+    match ctx.data().config.reload().await {
+        Ok(updated) => {
+            ctx.send(|m| {
+                m.embed(|e| {
+                    e.title("Config reloaded");
+                    e.description(format!(
+                        "config.json was reloaded, the settings of {} servers were updated. Servers with customised settings were left alone.",
+                        updated
+                    ));
+                    e
+                })
+            })
+            .await
+            .map_err(Error::Serenity)?;
+        }
+        Err(why) => {
+            ctx.send(|m| {
+                m.embed(|e| {
+                    e.title("Config not reloaded");
+                    e.description(why.to_string());
+                    e
+                })
+            })
+            .await
+            .map_err(Error::Serenity)?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Show the state of the bot's background tasks
+#[poise::command(
+    slash_command,
+    prefix_command,
+    rename = "status",
+    name_localized("de", "status"),
+    description_localized("de", "Zeigt den Zustand der Hintergrundaufgaben des Bots"),
+    guild_only,
+    check = "executor_is_dev_or_admin",
+    ephemeral
+)]
+pub async fn status(ctx: Context<'_>) -> Result<(), Error> {
+    let tasks = ctx.data().tasks.list();
+    let timestamp = |t: Option<chrono::DateTime<chrono::Utc>>| {
+        t.map(|t| format!("<t:{}:R>", t.timestamp()))
+            .unwrap_or_else(|| "never".to_string())
+    };
+
+    ctx.send(|m| {
+        m.embed(|e| {
+            e.title("Background tasks");
+            if tasks.is_empty() {
+                e.description("No tasks are running yet");
+            }
+            for task in &tasks {
+                let mut value = format!(
+                    "**{}** · {}\nLast success: {}\nNext run: {}",
+                    task.state,
+                    task.schedule,
+                    timestamp(task.last_success),
+                    timestamp(task.next_run)
+                );
+                if task.failures > 0 {
+                    value.push_str(&format!("\nFailed runs in a row: {}", task.failures));
+                }
+                if task.restarts > 0 {
+                    value.push_str(&format!("\nRestarts: {}", task.restarts));
+                }
+                if let Some(error) = &task.last_error {
+                    let error = error.chars().take(300).collect::<String>();
+                    value.push_str(&format!("\nLast error: {}", error));
+                }
+                e.field(task.name, value, false);
+            }
+            e
+        })
+    })
+    .await
+    .map_err(Error::Serenity)?;
+
+    Ok(())
+}
+
 /// Reverification command
 #[poise::command(
     slash_command,
@@ -501,58 +731,82 @@ pub async fn reverify(
     ctx: Context<'_>, 
     // format 2021-01-01
     #[description = "Cutoff date for re-verification (YYYY-MM-DD)"]
-    cutoff_date: String
+    cutoff_date: String,
+    #[description = "Days members have to reverify (default 14)"]
+    deadline_days: Option<i64>,
+    #[description = "Remove the verified role from members who don't respond in time"]
+    remove_role: Option<bool>,
 ) -> Result<(), Error> {
-    let _pool = &ctx.data().db;
+    let pool = &ctx.data().db;
     let cutoff_date = chrono::NaiveDate::parse_from_str(&cutoff_date, "%Y-%m-%d").map_err(|_| Error::WithMessage(format!("Invalid date format {}. Use YYYY-MM-DD", cutoff_date)))?;
-
-    // get all users that have joined before the cutoff date
-    let users = ctx.guild().unwrap().members(&ctx, None, None).await.map_err(Error::Serenity)?;
-    println!("{:?}", users);
-    let users_to_reverify = users.iter().filter_map(|m| {
-        if m.joined_at.unwrap().date_naive() < cutoff_date && !m.user.bot {
-            Some(m)
-        } else {
-            None
+    let deadline = chrono::Utc::now() + chrono::Duration::days(deadline_days.unwrap_or(14));
+    let remove_role = remove_role.unwrap_or(false);
+    let guild_id = ctx.guild_id().unwrap();
+
+    // get all users that have joined before the cutoff date, Discord returns the members in pages
+    let mut users_to_reverify = Vec::new();
+    let mut after = None;
+    loop {
+        let members = guild_id.members(ctx.serenity_context(), Some(MEMBERS_PER_PAGE), after).await.map_err(Error::Serenity)?;
+        after = members.last().map(|m| m.user.id);
+        let page_len = members.len() as u64;
+
+        users_to_reverify.extend(members.into_iter().filter(|m| {
+            m.joined_at.is_some_and(|joined_at| joined_at.date_naive() < cutoff_date) && !m.user.bot
+        }));
+
+        if page_len < MEMBERS_PER_PAGE {
+            break;
         }
-    }).collect::<Vec<_>>();
+    }
+
+    let (campaign_id,) = sqlx::query_as::<sqlx::Postgres, (i32,)>(
+        "INSERT INTO reverification_campaigns (guild_id, cutoff, deadline, remove_role) VALUES ($1, $2, $3, $4) RETURNING campaign_id",
+    )
+    .bind(guild_id.0 as i64)
+    .bind(cutoff_date)
+    .bind(deadline)
+    .bind(remove_role)
+    .fetch_one(pool)
+    .await
+    .map_err(Error::Database)?;
 
     ctx.send(|m| {
         m.content("Reverification broadcast started")
         .embed(|e| {
             e.title("Reverification Broadcast");
-            e.description(format!("Reverification broadcast started for users that joined before {}, messaginng {} users", cutoff_date, users_to_reverify.len()));
+            e.description(format!("Reverification broadcast started for users that joined before {}, messaginng {} users. Deadline is <t:{}:F>", cutoff_date, users_to_reverify.len(), deadline.timestamp()));
             e
         })
     }).await.map_err(Error::Serenity)?;
 
-    // send a message to all users that need to reverify
-    for user in  users_to_reverify {
-        user.user
-            .dm(&ctx, |message| {
-                message
-                    .content("🎓 **Reverification Required**")
-                    .embed(|embed| {
-                        embed
-                            .title("Student Status Verification")
-                            .description(
-                                "Hello! To confirm that you're still enrolled, please complete the reverification process. This helps us keep the server up to date for active students.",
-                            )
-                    })
-                    .components(|c| {
-                        c.create_action_row(|a| {
-                            a.create_button(|b| {
-                                b.style(serenity::ButtonStyle::Success)
-                                    .label("Reverify")
-                                    .custom_id("reverify")
-                            })
-                        })
-                    })
-            })
+    // send a message to all users that need to reverify, members we can't DM still have to
+    // reverify by the deadline
+    let mut failed = 0;
+    for user in users_to_reverify {
+        let dm_failed = match verification::send_reverification_dm(ctx.serenity_context(), user.user.id, &deadline, false).await {
+            Ok(_) => false,
+            Err(why) => {
+                tracing::warn!("Could not DM {} for reverification: {}", user.user.id, why);
+                failed += 1;
+                true
+            }
+        };
+
+        sqlx::query("INSERT INTO reverification_requests (campaign_id, user_id, dm_failed) VALUES ($1, $2, $3) ON CONFLICT DO NOTHING")
+            .bind(campaign_id)
+            .bind(user.user.id.0 as i64)
+            .bind(dm_failed)
+            .execute(pool)
+            .await
+            .map_err(Error::Database)?;
+    }
+
+    if failed > 0 {
+        ctx.say(format!("Could not DM {} users, they probably have DMs disabled", failed))
             .await
             .map_err(Error::Serenity)?;
     }
+
     Ok(())
 }
-
-
@@ -0,0 +1,336 @@
+use poise::serenity_prelude::{self as serenity, Mentionable};
+
+use super::administration::executor_is_dev_or_admin;
+use crate::{feeds, prelude::Error, Context};
+
+/// Templates longer than this would leave too little room for the title and link
+const MAX_TEMPLATE_LENGTH: usize = 500;
+
+/// Autocompletes the feed urls subscribed on the current server
+async fn feed_autocomplete(ctx: Context<'_>, partial: &str) -> Vec<String> {
+    let guild_id = match ctx.guild_id() {
+        Some(guild_id) => guild_id,
+        None => return Vec::new(),
+    };
+
+    let mut urls = feeds::guild_feeds(&ctx.data().db, guild_id)
+        .await
+        .unwrap_or_default()
+        .into_iter()
+        .map(|feed| feed.url)
+        .filter(|url| url.contains(partial))
+        .collect::<Vec<_>>();
+    urls.sort();
+    urls.dedup();
+    urls
+}
+
+/// Downloads and parses a feed to make sure it can be posted
+async fn fetch_feed(url: &str) -> Result<feeds::FetchedFeed, Error> {
+    match feeds::fetch(url, None).await {
+        Ok(Some(feed)) => Ok(feed),
+        // only happens for a server answering "not modified" without being asked
+        Ok(None) => Err(Error::WithMessage(format!(
+            "`{}` did not return a feed",
+            url
+        ))),
+        Err(why) => Err(Error::WithMessage(format!(
+            "`{}` could not be loaded as an RSS, Atom or JSON feed: {}",
+            url, why
+        ))),
+    }
+}
+
+fn check_template(template: &Option<String>) -> Result<(), Error> {
+    match template {
+        Some(template) if template.chars().count() > MAX_TEMPLATE_LENGTH => {
+            Err(Error::WithMessage(format!(
+                "The template must not be longer than {} characters",
+                MAX_TEMPLATE_LENGTH
+            )))
+        }
+        _ => Ok(()),
+    }
+}
+
+/// Manage the RSS feeds posted on this server
+#[poise::command(
+    slash_command,
+    prefix_command,
+    rename = "feeds",
+    name_localized("de", "feeds"),
+    description_localized("de", "Verwalte die RSS-Feeds, die auf diesem Server gepostet werden"),
+    guild_only,
+    check = "executor_is_dev_or_admin",
+    subcommands("add", "remove", "list", "test")
+)]
+pub async fn feeds(ctx: Context<'_>) -> Result<(), Error> {
+    ctx.say("Use one of the subcommands, e.g. `feeds list`")
+        .await
+        .map_err(Error::Serenity)?;
+    Ok(())
+}
+
+/// Post a feed in a channel, or change the template and role of a feed posted already
+#[poise::command(
+    slash_command,
+    prefix_command,
+    description_localized(
+        "de",
+        "Poste einen Feed in einem Kanal oder ändere Vorlage und Rolle eines Feeds"
+    ),
+    guild_only,
+    check = "executor_is_dev_or_admin",
+    ephemeral
+)]
+pub async fn add(
+    ctx: Context<'_>,
+    #[description = "URL of the feed"]
+    #[description_localized("de", "URL des Feeds")]
+    url: String,
+    #[description = "Channel to post in, defaults to this one"]
+    #[description_localized("de", "Kanal, in dem gepostet wird, standardmäßig dieser")]
+    channel: Option<serenity::GuildChannel>,
+    #[description = "Message of new items, may use {title}, {link} and {feed}"]
+    #[description_localized("de", "Nachricht zu neuen Einträgen, kann {title}, {link} und {feed} enthalten")]
+    template: Option<String>,
+    #[description = "Role to mention on new items"]
+    #[description_localized("de", "Rolle, die bei neuen Einträgen erwähnt wird")]
+    mention: Option<serenity::Role>,
+    #[description = "Go back to the default message and no mention, unless given again"]
+    #[description_localized("de", "Zurück zur Standardnachricht ohne Erwähnung, außer sie werden neu angegeben")]
+    reset: Option<bool>,
+) -> Result<(), Error> {
+    let guild_id = ctx.guild_id().unwrap();
+    let channel_id = channel
+        .as_ref()
+        .map(|c| c.id)
+        .unwrap_or_else(|| ctx.channel_id());
+    if channel.is_some_and(|c| c.guild_id != guild_id) {
+        return Err(Error::WithMessage(
+            "The channel must be on this server".to_string(),
+        ));
+    }
+    check_template(&template)?;
+
+    let feed = fetch_feed(&url).await?;
+    // options that aren't given keep their current value, unless they are reset
+    let reset = reset.unwrap_or(false);
+    let template = match template.as_deref() {
+        Some(template) => Some(Some(template)),
+        None if reset => Some(None),
+        None => None,
+    };
+    let mention = match mention {
+        Some(role) => Some(Some(role.id)),
+        None if reset => Some(None),
+        None => None,
+    };
+    let created = feeds::subscribe(
+        &ctx.data().db,
+        guild_id,
+        channel_id,
+        &url,
+        template,
+        mention,
+        &feed,
+    )
+    .await?;
+
+    let mut message = if created {
+        format!(
+            "{} now gets the new items of **{}**, the {} current ones are not posted",
+            channel_id.mention(),
+            feed.feed.title,
+            feed.feed.items.len()
+        )
+    } else {
+        format!(
+            "Updated the template and role of **{}** in {}",
+            feed.feed.title,
+            channel_id.mention()
+        )
+    };
+
+    let config = ctx.data().config.get(guild_id).await?;
+    if !config.rss_settings.post_rss {
+        message.push_str(
+            "\nPosting RSS feeds is disabled on this server, enable it with `/settings rss enabled: True`",
+        );
+    }
+
+    ctx.say(message).await.map_err(Error::Serenity)?;
+    Ok(())
+}
+
+/// Stop posting a feed
+#[poise::command(
+    slash_command,
+    prefix_command,
+    description_localized("de", "Poste einen Feed nicht mehr"),
+    guild_only,
+    check = "executor_is_dev_or_admin",
+    ephemeral
+)]
+pub async fn remove(
+    ctx: Context<'_>,
+    #[description = "URL of the feed"]
+    #[description_localized("de", "URL des Feeds")]
+    #[autocomplete = "feed_autocomplete"]
+    url: String,
+    #[description = "Only stop posting in this channel"]
+    #[description_localized("de", "Nur in diesem Kanal nicht mehr posten")]
+    channel: Option<serenity::GuildChannel>,
+) -> Result<(), Error> {
+    let guild_id = ctx.guild_id().unwrap();
+    let channel_id = channel.map(|c| c.id);
+
+    let removed = feeds::unsubscribe(&ctx.data().db, guild_id, channel_id, &url).await?;
+
+    // feeds from the settings would be subscribed again with the next check
+    let mut config = ctx.data().config.get(guild_id).await?;
+    let configured = config.rss_settings.rss_feed_data.len();
+    config
+        .rss_settings
+        .rss_feed_data
+        .retain(|c, u| *u != url || channel_id.is_some_and(|channel_id| channel_id != *c));
+    let configured_removed = config.rss_settings.rss_feed_data.len() < configured;
+    if configured_removed {
+        ctx.data().config.set(guild_id, config).await?;
+    }
+
+    if removed == 0 && !configured_removed {
+        return Err(Error::WithMessage(format!("`{}` is not posted here", url)));
+    }
+
+    ctx.say(format!("`{}` is not posted anymore", url))
+        .await
+        .map_err(Error::Serenity)?;
+    Ok(())
+}
+
+/// List the feeds posted on this server
+#[poise::command(
+    slash_command,
+    prefix_command,
+    description_localized("de", "Zeige die Feeds, die auf diesem Server gepostet werden"),
+    guild_only,
+    check = "executor_is_dev_or_admin",
+    ephemeral
+)]
+pub async fn list(ctx: Context<'_>) -> Result<(), Error> {
+    let guild_id = ctx.guild_id().unwrap();
+    let subscribed = feeds::guild_feeds(&ctx.data().db, guild_id).await?;
+    let config = ctx.data().config.get(guild_id).await?;
+
+    let mut lines = subscribed
+        .iter()
+        .map(|feed| {
+            let mut line = format!(
+                "{} · {}",
+                serenity::ChannelId(feed.channel_id as u64).mention(),
+                feed.url
+            );
+            if let Some(role) = feed.mention_role {
+                line.push_str(&format!(" · {}", serenity::RoleId(role as u64).mention()));
+            }
+            if let Some(template) = &feed.template {
+                line.push_str(&format!("\n  `{}`", template));
+            }
+            if let Some(checked_at) = feed.checked_at {
+                line.push_str(&format!(
+                    "\n  last checked <t:{}:R>",
+                    checked_at.timestamp()
+                ));
+            }
+            line
+        })
+        .collect::<Vec<_>>();
+
+    // feeds from the settings that weren't checked yet
+    for (channel_id, url) in &config.rss_settings.rss_feed_data {
+        if !subscribed
+            .iter()
+            .any(|f| f.channel_id == channel_id.0 as i64 && f.url == *url)
+        {
+            lines.push(format!(
+                "{} · {}\n  not checked yet",
+                channel_id.mention(),
+                url
+            ));
+        }
+    }
+
+    let mut description = if lines.is_empty() {
+        "No feeds are posted on this server, add one with `/feeds add`".to_string()
+    } else {
+        lines.join("\n")
+    };
+    if !config.rss_settings.post_rss {
+        description.insert_str(0, "⚠️ Posting RSS feeds is disabled on this server\n\n");
+    }
+    // embed descriptions are limited to 4096 characters
+    let description = description.chars().take(4096).collect::<String>();
+
+    ctx.send(|m| m.embed(|e| e.title("RSS feeds").description(description)))
+        .await
+        .map_err(Error::Serenity)?;
+    Ok(())
+}
+
+/// Show how the newest item of a feed would be posted
+#[poise::command(
+    slash_command,
+    prefix_command,
+    description_localized("de", "Zeige, wie der neueste Eintrag eines Feeds gepostet würde"),
+    guild_only,
+    check = "executor_is_dev_or_admin",
+    ephemeral
+)]
+pub async fn test(
+    ctx: Context<'_>,
+    #[description = "URL of the feed"]
+    #[description_localized("de", "URL des Feeds")]
+    url: String,
+    #[description = "Message of new items, may use {title}, {link} and {feed}"]
+    #[description_localized("de", "Nachricht zu neuen Einträgen, kann {title}, {link} und {feed} enthalten")]
+    template: Option<String>,
+) -> Result<(), Error> {
+    check_template(&template)?;
+    let feed = fetch_feed(&url).await?;
+    // feeds usually list their newest item first, but not all of them
+    let item = feed
+        .feed
+        .items
+        .iter()
+        .max_by_key(|item| item.date())
+        .ok_or_else(|| Error::WithMessage(format!("`{}` has no items yet", url)))?;
+
+    let title = item.title.as_deref().unwrap_or_default();
+    let link = item.link.as_deref().unwrap_or_default();
+    let date = item
+        .date()
+        .map(|date| date.with_timezone(&chrono::Local))
+        .unwrap_or_else(chrono::Local::now);
+    let clean_regex = feeds::clean_regex()?;
+
+    let content = feeds::render_template(
+        template.as_deref().unwrap_or(feeds::DEFAULT_TEMPLATE),
+        &feed.feed.title,
+        title,
+        link,
+    );
+
+    ctx.send(|m| {
+        m.content(format!(
+            "**{}** has {} items, the newest one would be posted like this:\n\n{}",
+            feed.feed.title,
+            feed.feed.items.len(),
+            content
+        ))
+        .embed(|e| feeds::create_embed(e, &clean_regex, item, &date))
+    })
+    .await
+    .map_err(Error::Serenity)?;
+    Ok(())
+}
@@ -0,0 +1,261 @@
+use std::collections::HashMap;
+
+use poise::serenity_prelude::{self as serenity, Mentionable};
+
+use super::administration::executor_is_dev_or_admin;
+use crate::{
+    config::{FacultyManagerConfig, LevelRole},
+    prelude::Error,
+    structs, xp, Context,
+};
+
+/// Members are fetched from Discord in pages of this size
+pub(crate) const MEMBERS_PER_PAGE: u64 = 1000;
+
+/// Applies a change to the level roles of the current guild, validates and stores it and shows
+/// the new mapping
+async fn update_level_roles(
+    ctx: Context<'_>,
+    change: impl FnOnce(&mut FacultyManagerConfig) -> Result<(), Error>,
+) -> Result<(), Error> {
+    let guild_id = ctx.guild_id().unwrap();
+    let mut config = ctx.data().config.get(guild_id).await?;
+
+    change(&mut config)?;
+    config
+        .validate()
+        .map_err(|problems| Error::WithMessage(problems.join(", ")))?;
+
+    ctx.data().config.set(guild_id, config.clone()).await?;
+
+    show_level_roles(ctx, &config, "Level roles updated").await
+}
+
+async fn show_level_roles(
+    ctx: Context<'_>,
+    config: &FacultyManagerConfig,
+    title: &str,
+) -> Result<(), Error> {
+    let mut roles = config.level_roles.roles.clone();
+    roles.sort_by_key(|level_role| level_role.level);
+
+    let mut description = if roles.is_empty() {
+        "No level roles yet, add one with `/levelroles add`".to_string()
+    } else {
+        roles
+            .iter()
+            .map(|level_role| format!("Level {} · {}", level_role.level, level_role.role.mention()))
+            .collect::<Vec<_>>()
+            .join("\n")
+    };
+    if config.level_roles.remove_lower {
+        description.push_str("\n\nMembers only keep the role of their highest level");
+    }
+
+    ctx.send(|m| m.embed(|e| e.title(title).description(description)))
+        .await
+        .map_err(Error::Serenity)?;
+    Ok(())
+}
+
+/// Manage the roles members get on reaching a level
+#[poise::command(
+    slash_command,
+    prefix_command,
+    rename = "levelroles",
+    name_localized("de", "levelrollen"),
+    description_localized("de", "Verwalte die Rollen, die Mitglieder mit einem Level bekommen"),
+    guild_only,
+    check = "executor_is_dev_or_admin",
+    subcommands("add", "remove", "list", "removelower", "resync")
+)]
+pub async fn levelroles(ctx: Context<'_>) -> Result<(), Error> {
+    ctx.say("Use one of the subcommands, e.g. `levelroles list`")
+        .await
+        .map_err(Error::Serenity)?;
+    Ok(())
+}
+
+/// Grant a role on reaching a level
+#[poise::command(
+    slash_command,
+    prefix_command,
+    description_localized("de", "Vergib eine Rolle ab einem Level"),
+    guild_only,
+    check = "executor_is_dev_or_admin",
+    ephemeral
+)]
+pub async fn add(
+    ctx: Context<'_>,
+    #[description = "Level the role is granted at"]
+    #[description_localized("de", "Level, ab dem die Rolle vergeben wird")]
+    level: u32,
+    #[description = "The role to grant"]
+    #[description_localized("de", "Die Rolle, die vergeben wird")]
+    role: serenity::Role,
+) -> Result<(), Error> {
+    // a level has one role and a role one level
+    update_level_roles(ctx, |config| {
+        let roles = &mut config.level_roles.roles;
+        roles.retain(|level_role| level_role.level != level as i32 && level_role.role != role.id);
+        roles.push(LevelRole {
+            level: level as i32,
+            role: role.id,
+        });
+        Ok(())
+    })
+    .await
+}
+
+/// Stop granting a role on reaching a level
+#[poise::command(
+    slash_command,
+    prefix_command,
+    description_localized("de", "Vergib eine Rolle nicht mehr mit einem Level"),
+    guild_only,
+    check = "executor_is_dev_or_admin",
+    ephemeral
+)]
+pub async fn remove(
+    ctx: Context<'_>,
+    #[description = "The role to stop granting, members keep it until the next resync"]
+    #[description_localized("de", "Die Rolle, die nicht mehr vergeben wird, Mitglieder behalten sie")]
+    role: serenity::Role,
+) -> Result<(), Error> {
+    update_level_roles(ctx, |config| {
+        let roles = &mut config.level_roles.roles;
+        let count = roles.len();
+        roles.retain(|level_role| level_role.role != role.id);
+        if roles.len() == count {
+            return Err(Error::WithMessage(format!(
+                "{} is not a level role",
+                role.mention()
+            )));
+        }
+        Ok(())
+    })
+    .await
+}
+
+/// List the level roles of this server
+#[poise::command(
+    slash_command,
+    prefix_command,
+    description_localized("de", "Zeige die Levelrollen dieses Servers"),
+    guild_only,
+    check = "executor_is_dev_or_admin",
+    ephemeral
+)]
+pub async fn list(ctx: Context<'_>) -> Result<(), Error> {
+    let config = ctx.data().config.get(ctx.guild_id().unwrap()).await?;
+    show_level_roles(ctx, &config, "Level roles").await
+}
+
+/// Choose whether members keep the roles of lower levels
+#[poise::command(
+    slash_command,
+    prefix_command,
+    description_localized("de", "Wähle, ob Mitglieder die Rollen niedrigerer Level behalten"),
+    guild_only,
+    check = "executor_is_dev_or_admin",
+    ephemeral
+)]
+pub async fn removelower(
+    ctx: Context<'_>,
+    #[description = "Only keep the role of the highest level reached"]
+    #[description_localized("de", "Nur die Rolle des höchsten erreichten Levels behalten")]
+    enabled: bool,
+) -> Result<(), Error> {
+    update_level_roles(ctx, |config| {
+        config.level_roles.remove_lower = enabled;
+        Ok(())
+    })
+    .await
+}
+
+/// Give every member the level roles of their current level, e.g. after `set-xp`
+#[poise::command(
+    slash_command,
+    prefix_command,
+    description_localized("de", "Gib allen Mitgliedern die Levelrollen ihres aktuellen Levels"),
+    guild_only,
+    check = "executor_is_dev_or_admin",
+    ephemeral
+)]
+pub async fn resync(ctx: Context<'_>) -> Result<(), Error> {
+    let guild_id = ctx.guild_id().unwrap();
+    let config = ctx.data().config.get(guild_id).await?;
+    if config.level_roles.roles.is_empty() {
+        return Err(Error::WithMessage(
+            "There are no level roles, add one with `/levelroles add`".to_string(),
+        ));
+    }
+
+    // going through every member takes a while
+    ctx.defer_ephemeral().await.map_err(Error::Serenity)?;
+
+    let levels = sqlx::query_as::<sqlx::Postgres, structs::UserXP>("SELECT * FROM user_xp")
+        .fetch_all(&ctx.data().db)
+        .await
+        .map_err(Error::Database)?
+        .into_iter()
+        .map(|user| (user.user_id, user.user_level))
+        .collect::<HashMap<_, _>>();
+
+    let mut checked = 0;
+    let mut changed = 0;
+    let mut failed = Vec::new();
+    let mut after = None;
+    loop {
+        let members = guild_id
+            .members(ctx.serenity_context(), Some(MEMBERS_PER_PAGE), after)
+            .await
+            .map_err(Error::Serenity)?;
+
+        for mut member in members.iter().cloned() {
+            if member.user.bot {
+                continue;
+            }
+            checked += 1;
+            let level = levels
+                .get(&(member.user.id.0 as i64))
+                .copied()
+                .unwrap_or_default();
+            match xp::sync_level_roles(
+                ctx.serenity_context(),
+                &mut member,
+                level,
+                &config.level_roles,
+            )
+            .await
+            {
+                Ok(true) => changed += 1,
+                Ok(false) => {}
+                Err(why) => {
+                    tracing::warn!("Failed to sync level roles of {}: {}", member.user.name, why);
+                    failed.push(member.user.tag());
+                }
+            }
+        }
+
+        if (members.len() as u64) < MEMBERS_PER_PAGE {
+            break;
+        }
+        after = members.last().map(|member| member.user.id);
+    }
+
+    let mut message = format!(
+        "Checked {} members, updated the roles of {}",
+        checked, changed
+    );
+    if !failed.is_empty() {
+        message.push_str(&format!(
+            "\nFailed for {} members, is the bot's role above the level roles? {}",
+            failed.len(),
+            failed.into_iter().take(10).collect::<Vec<_>>().join(", ")
+        ));
+    }
+
+    ctx.say(message).await.map_err(Error::Serenity)?;
+    Ok(())
+}
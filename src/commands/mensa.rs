@@ -0,0 +1,415 @@
+use chrono::Datelike;
+
+use super::settings::Weekday;
+use crate::{
+    config::Canteen,
+    mensaplan::{self, DailyMenu, DishFilter},
+    prelude::{translations::Lang, Error},
+    Context,
+};
+
+#[derive(Debug, poise::ChoiceParameter)]
+pub enum Diet {
+    Vegetarian,
+    Vegan,
+}
+
+fn dish_filter(diet: Option<Diet>, exclude_allergens: Option<String>) -> DishFilter {
+    DishFilter {
+        vegetarian: matches!(diet, Some(Diet::Vegetarian)),
+        vegan: matches!(diet, Some(Diet::Vegan)),
+        exclude_allergens: exclude_allergens
+            .unwrap_or_default()
+            .split(|c: char| c == ',' || c.is_whitespace())
+            .filter(|a| !a.is_empty())
+            .map(|a| a.to_string())
+            .collect(),
+    }
+}
+
+/// The monday of the week students are most likely interested in
+fn current_week(now: chrono::NaiveDate) -> chrono::NaiveDate {
+    // on the weekend the coming week is more interesting than the one that just ended
+    if now.weekday().num_days_from_monday() >= 5 {
+        mensaplan::week_start(now) + chrono::Duration::days(7)
+    } else {
+        mensaplan::week_start(now)
+    }
+}
+
+/// Autocompletes the canteens of the current server
+pub async fn canteen_autocomplete(ctx: Context<'_>, partial: &str) -> Vec<String> {
+    let partial = partial.to_lowercase();
+    match ctx.data().config.resolve(ctx.guild_id()).await {
+        Ok(config) => config
+            .canteens()
+            .into_iter()
+            .map(|c| c.name)
+            .filter(|name| name.to_lowercase().contains(&partial))
+            .collect(),
+        Err(_) => Vec::new(),
+    }
+}
+
+/// The canteen picked by the user, the first one if they didn't pick any
+pub(super) async fn selected_canteen(
+    ctx: Context<'_>,
+    lang: &Lang,
+    name: Option<String>,
+) -> Result<Canteen, Error> {
+    let config = ctx.data().config.resolve(ctx.guild_id()).await?;
+    config.canteen(name.as_deref()).ok_or_else(|| {
+        Error::WithMessage(
+            lang.mensa_unknown_canteen(
+                config
+                    .canteens()
+                    .iter()
+                    .map(|c| c.name.as_str())
+                    .collect::<Vec<_>>()
+                    .join(", "),
+            ),
+        )
+    })
+}
+
+async fn send_menus(
+    ctx: Context<'_>,
+    lang: &Lang,
+    canteen: &Canteen,
+    menus: &[DailyMenu],
+    filter: &DishFilter,
+) -> Result<(), Error> {
+    let filtered = filter.apply(menus.to_vec());
+    if filtered.iter().all(|menu| menu.dishes.is_empty()) {
+        return Err(Error::WithMessage(lang.mensa_no_matching_dishes().into()));
+    }
+
+    ctx.send(|m| {
+        m.embed(|e| {
+            e.title(&canteen.name)
+                .description(lang.mensa_legend())
+                .fields(mensaplan::embed_fields(&filtered))
+        })
+    })
+    .await
+    .map_err(Error::Serenity)?;
+
+    Ok(())
+}
+
+/// Base command for the mensaplan
+#[poise::command(
+    slash_command,
+    prefix_command,
+    track_edits,
+    rename = "mensa",
+    name_localized("de", "mensa"),
+    description_localized("de", "Zeige die Gerichte der Mensa"),
+    subcommands("today", "week", "subscribe", "unsubscribe", "alerts")
+)]
+#[allow(unused_variables)]
+pub async fn mensa(ctx: Context<'_>) -> Result<(), Error> {
+    Ok(())
+}
+
+/// Show the dishes of today or another day of this week
+#[poise::command(
+    slash_command,
+    prefix_command,
+    track_edits,
+    name_localized("de", "heute"),
+    description_localized(
+        "de",
+        "Zeige die Gerichte von heute oder einem anderen Tag dieser Woche"
+    )
+)]
+pub async fn today(
+    ctx: Context<'_>,
+    #[description = "Show another day of this week"]
+    #[description_localized("de", "Zeige einen anderen Tag dieser Woche")]
+    day: Option<Weekday>,
+    #[description = "Only show vegetarian or vegan dishes"]
+    #[description_localized("de", "Zeige nur vegetarische oder vegane Gerichte")]
+    diet: Option<Diet>,
+    #[description = "Allergens to leave out, e.g. \"A, G\""]
+    #[description_localized("de", "Allergene, die ausgelassen werden sollen, z.B. \"A, G\"")]
+    exclude_allergens: Option<String>,
+    #[description = "The canteen, defaults to the first one"]
+    #[description_localized("de", "Die Mensa, standardmäßig die erste")]
+    #[autocomplete = "canteen_autocomplete"]
+    canteen: Option<String>,
+) -> Result<(), Error> {
+    let lang = match ctx.locale() {
+        Some("de") => Lang::De,
+        Some("ja") => Lang::Ja,
+        _ => Lang::En,
+    };
+
+    let now = chrono::Local::now().date_naive();
+    let date = match day {
+        Some(day) => {
+            current_week(now)
+                + chrono::Duration::days(chrono::Weekday::from(day).num_days_from_monday() as i64)
+        }
+        None => now,
+    };
+
+    let canteen = selected_canteen(ctx, &lang, canteen).await?;
+    let menus = mensaplan::week_menus(&ctx.data().db, &canteen.url, date)
+        .await?
+        .into_iter()
+        .filter(|menu| menu.date == date && !menu.dishes.is_empty())
+        .collect::<Vec<_>>();
+
+    if menus.is_empty() {
+        return Err(Error::WithMessage(lang.mensa_no_dishes(
+            format!(
+                "{}, {}",
+                mensaplan::weekday_name(date.weekday()),
+                date.format("%d.%m.")
+            ),
+            &canteen.url,
+        )));
+    }
+
+    send_menus(
+        ctx,
+        &lang,
+        &canteen,
+        &menus,
+        &dish_filter(diet, exclude_allergens),
+    )
+    .await
+}
+
+/// Show the dishes of the whole week
+#[poise::command(
+    slash_command,
+    prefix_command,
+    track_edits,
+    name_localized("de", "woche"),
+    description_localized("de", "Zeige die Gerichte der ganzen Woche")
+)]
+pub async fn week(
+    ctx: Context<'_>,
+    #[description = "Only show vegetarian or vegan dishes"]
+    #[description_localized("de", "Zeige nur vegetarische oder vegane Gerichte")]
+    diet: Option<Diet>,
+    #[description = "Allergens to leave out, e.g. \"A, G\""]
+    #[description_localized("de", "Allergene, die ausgelassen werden sollen, z.B. \"A, G\"")]
+    exclude_allergens: Option<String>,
+    #[description = "The canteen, defaults to the first one"]
+    #[description_localized("de", "Die Mensa, standardmäßig die erste")]
+    #[autocomplete = "canteen_autocomplete"]
+    canteen: Option<String>,
+) -> Result<(), Error> {
+    let lang = match ctx.locale() {
+        Some("de") => Lang::De,
+        Some("ja") => Lang::Ja,
+        _ => Lang::En,
+    };
+
+    let week = current_week(chrono::Local::now().date_naive());
+    let canteen = selected_canteen(ctx, &lang, canteen).await?;
+    let menus = mensaplan::week_menus(&ctx.data().db, &canteen.url, week).await?;
+
+    if menus.iter().all(|menu| menu.dishes.is_empty()) {
+        return Err(Error::WithMessage(lang.mensa_no_dishes(
+            format!(
+                "{} - {}",
+                week.format("%d.%m."),
+                (week + chrono::Duration::days(4)).format("%d.%m.")
+            ),
+            &canteen.url,
+        )));
+    }
+
+    send_menus(
+        ctx,
+        &lang,
+        &canteen,
+        &menus,
+        &dish_filter(diet, exclude_allergens),
+    )
+    .await
+}
+
+/// Get the dishes of the day via DM
+#[poise::command(
+    slash_command,
+    prefix_command,
+    name_localized("de", "abonnieren"),
+    description_localized("de", "Erhalte die Gerichte des Tages per DM"),
+    ephemeral
+)]
+pub async fn subscribe(
+    ctx: Context<'_>,
+    #[description = "Time to send the DM at (HH:MM), defaults to 11:00"]
+    #[description_localized("de", "Uhrzeit für die DM (HH:MM), standardmäßig 11:00")]
+    time: Option<String>,
+) -> Result<(), Error> {
+    let lang = match ctx.locale() {
+        Some("de") => Lang::De,
+        Some("ja") => Lang::Ja,
+        _ => Lang::En,
+    };
+
+    let time = time.unwrap_or_else(|| "11:00".to_string());
+    let notify_at = chrono::NaiveTime::parse_from_str(&time, "%H:%M")
+        .map_err(|_| Error::WithMessage(lang.mensa_invalid_time(&time)))?;
+
+    mensaplan::subscribe(&ctx.data().db, ctx.author().id, ctx.guild_id(), notify_at).await?;
+
+    ctx.say(lang.mensa_subscribed(notify_at.format("%H:%M")))
+        .await
+        .map_err(Error::Serenity)?;
+    Ok(())
+}
+
+/// Stop getting the dishes of the day via DM
+#[poise::command(
+    slash_command,
+    prefix_command,
+    name_localized("de", "deabonnieren"),
+    description_localized("de", "Erhalte die Gerichte des Tages nicht mehr per DM"),
+    ephemeral
+)]
+pub async fn unsubscribe(ctx: Context<'_>) -> Result<(), Error> {
+    let lang = match ctx.locale() {
+        Some("de") => Lang::De,
+        Some("ja") => Lang::Ja,
+        _ => Lang::En,
+    };
+
+    if !mensaplan::unsubscribe(&ctx.data().db, ctx.author().id).await? {
+        return Err(Error::WithMessage(lang.mensa_not_subscribed().into()));
+    }
+
+    ctx.say(lang.mensa_unsubscribed())
+        .await
+        .map_err(Error::Serenity)?;
+    Ok(())
+}
+
+/// Base command for favourite dish alerts
+#[poise::command(
+    slash_command,
+    prefix_command,
+    name_localized("de", "alarme"),
+    description_localized(
+        "de",
+        "Werde benachrichtigt, wenn dein Lieblingsgericht auf dem Plan steht"
+    ),
+    subcommands("alert_add", "alert_remove", "alert_list")
+)]
+#[allow(unused_variables)]
+pub async fn alerts(ctx: Context<'_>) -> Result<(), Error> {
+    Ok(())
+}
+
+async fn keyword_autocomplete(ctx: Context<'_>, partial: &str) -> Vec<String> {
+    let partial = partial.to_lowercase();
+    mensaplan::keywords(&ctx.data().db, ctx.author().id)
+        .await
+        .unwrap_or_default()
+        .into_iter()
+        .filter(|k| k.to_lowercase().starts_with(&partial))
+        .collect()
+}
+
+/// Get a DM when a dish containing the keyword is on the plan
+#[poise::command(
+    slash_command,
+    prefix_command,
+    rename = "add",
+    name_localized("de", "hinzufügen"),
+    description_localized(
+        "de",
+        "Erhalte eine DM, wenn ein Gericht mit diesem Stichwort auf dem Plan steht"
+    ),
+    ephemeral
+)]
+pub async fn alert_add(
+    ctx: Context<'_>,
+    #[description = "Part of the dish name, e.g. \"Schnitzel\""]
+    #[description_localized("de", "Teil des Gerichtnamens, z.B. \"Schnitzel\"")]
+    keyword: String,
+) -> Result<(), Error> {
+    let lang = match ctx.locale() {
+        Some("de") => Lang::De,
+        Some("ja") => Lang::Ja,
+        _ => Lang::En,
+    };
+
+    // keywords end up in button labels and ids, which are limited in length
+    let keyword = keyword.trim();
+    if keyword.is_empty() || keyword.chars().count() > 64 {
+        return Err(Error::WithMessage(lang.mensa_invalid_keyword().into()));
+    }
+
+    mensaplan::add_keyword(&ctx.data().db, ctx.author().id, ctx.guild_id(), keyword).await?;
+
+    ctx.say(lang.mensa_alert_added(keyword))
+        .await
+        .map_err(Error::Serenity)?;
+    Ok(())
+}
+
+/// Stop getting DMs for a keyword
+#[poise::command(
+    slash_command,
+    prefix_command,
+    rename = "remove",
+    name_localized("de", "entfernen"),
+    description_localized("de", "Erhalte keine DMs mehr für ein Stichwort"),
+    ephemeral
+)]
+pub async fn alert_remove(
+    ctx: Context<'_>,
+    #[description = "The keyword to remove"]
+    #[description_localized("de", "Das Stichwort, das entfernt werden soll")]
+    #[autocomplete = "keyword_autocomplete"]
+    keyword: String,
+) -> Result<(), Error> {
+    let lang = match ctx.locale() {
+        Some("de") => Lang::De,
+        Some("ja") => Lang::Ja,
+        _ => Lang::En,
+    };
+
+    if !mensaplan::remove_keyword(&ctx.data().db, ctx.author().id, &keyword).await? {
+        return Err(Error::WithMessage(lang.mensa_alert_not_found(&keyword)));
+    }
+
+    ctx.say(lang.mensa_alert_removed(&keyword))
+        .await
+        .map_err(Error::Serenity)?;
+    Ok(())
+}
+
+/// List the keywords you get DMs for
+#[poise::command(
+    slash_command,
+    prefix_command,
+    rename = "list",
+    name_localized("de", "liste"),
+    description_localized("de", "Zeige die Stichwörter, für die du DMs erhältst"),
+    ephemeral
+)]
+pub async fn alert_list(ctx: Context<'_>) -> Result<(), Error> {
+    let lang = match ctx.locale() {
+        Some("de") => Lang::De,
+        Some("ja") => Lang::Ja,
+        _ => Lang::En,
+    };
+
+    let keywords = mensaplan::keywords(&ctx.data().db, ctx.author().id).await?;
+    let content = if keywords.is_empty() {
+        lang.mensa_alert_list_empty().to_string()
+    } else {
+        lang.mensa_alert_list(keywords.join(", "))
+    };
+
+    ctx.say(content).await.map_err(Error::Serenity)?;
+    Ok(())
+}
@@ -3,7 +3,11 @@ use poise::serenity_prelude::{self as serenity};
 use crate::{prelude::Error, Context, Data};
 
 pub mod administration;
+pub mod feeds;
+pub mod levelroles;
+pub mod mensa;
 pub mod moderation;
+pub mod settings;
 pub mod user;
 
 /// Shows a list of all commands
@@ -0,0 +1,532 @@
+use poise::serenity_prelude as serenity;
+
+use super::mensa::canteen_autocomplete;
+use crate::{config::FacultyManagerConfig, prelude::Error, Context};
+
+const CHANNEL_KEYS: [&str; 7] = ["xp", "rules", "news", "logs", "ads", "mealplan", "createChannel"];
+const ROLE_KEYS: [&str; 4] = ["staffrole", "semestermodrole", "verified", "mealplannotify"];
+
+#[derive(Debug, poise::ChoiceParameter)]
+pub enum Weekday {
+    Monday,
+    Tuesday,
+    Wednesday,
+    Thursday,
+    Friday,
+    Saturday,
+    Sunday,
+}
+
+impl From<Weekday> for chrono::Weekday {
+    fn from(day: Weekday) -> Self {
+        match day {
+            Weekday::Monday => chrono::Weekday::Mon,
+            Weekday::Tuesday => chrono::Weekday::Tue,
+            Weekday::Wednesday => chrono::Weekday::Wed,
+            Weekday::Thursday => chrono::Weekday::Thu,
+            Weekday::Friday => chrono::Weekday::Fri,
+            Weekday::Saturday => chrono::Weekday::Sat,
+            Weekday::Sunday => chrono::Weekday::Sun,
+        }
+    }
+}
+
+/// Applies a change to the settings of the current guild, validates and stores them and shows the changed section
+async fn update_settings(
+    ctx: Context<'_>,
+    section: &str,
+    change: impl FnOnce(&mut FacultyManagerConfig) -> Result<(), Error>,
+) -> Result<(), Error> {
+    let guild_id = ctx.guild_id().unwrap();
+    let mut config = ctx.data().config.get(guild_id).await?;
+
+    change(&mut config)?;
+    let channels = guild_id.channels(ctx).await.map_err(Error::Serenity)?;
+    config
+        .validate_in_guild(&channels)
+        .map_err(|problems| Error::WithMessage(problems.join(", ")))?;
+
+    ctx.data().config.set(guild_id, config.clone()).await?;
+
+    show_section(ctx, &config, section, "Settings updated").await
+}
+
+async fn show_section(
+    ctx: Context<'_>,
+    config: &FacultyManagerConfig,
+    section: &str,
+    title: &str,
+) -> Result<(), Error> {
+    let value = serde_json::to_value(config).map_err(Error::Serde)?;
+    let section_value = value
+        .get(section)
+        .ok_or_else(|| Error::WithMessage(format!("There is no settings section `{}`", section)))?;
+
+    let mut pretty = serde_json::to_string_pretty(section_value).map_err(Error::Serde)?;
+    if pretty.len() > 4000 {
+        let mut end = 4000;
+        while !pretty.is_char_boundary(end) {
+            end -= 1;
+        }
+        pretty.truncate(end);
+        pretty.push_str("\n...");
+    }
+
+    ctx.send(|m| {
+        m.embed(|e| {
+            e.title(format!("{} · {}", title, section));
+            e.description(format!("```json\n{}\n```", pretty));
+            e
+        })
+    })
+    .await
+    .map_err(Error::Serenity)?;
+
+    Ok(())
+}
+
+async fn section_autocomplete(ctx: Context<'_>, partial: &str) -> Vec<String> {
+    let config = match ctx.data().config.resolve(ctx.guild_id()).await {
+        Ok(config) => config,
+        Err(_) => return Vec::new(),
+    };
+
+    match serde_json::to_value(config) {
+        Ok(serde_json::Value::Object(sections)) => sections
+            .into_iter()
+            .filter(|(_, v)| v.is_object())
+            .map(|(k, _)| k)
+            .filter(|k| k.starts_with(partial))
+            .collect(),
+        _ => Vec::new(),
+    }
+}
+
+async fn channel_key_autocomplete(_ctx: Context<'_>, partial: &str) -> Vec<String> {
+    CHANNEL_KEYS
+        .iter()
+        .filter(|k| k.starts_with(partial))
+        .map(|k| k.to_string())
+        .collect()
+}
+
+async fn role_key_autocomplete(_ctx: Context<'_>, partial: &str) -> Vec<String> {
+    ROLE_KEYS
+        .iter()
+        .filter(|k| k.starts_with(partial))
+        .map(|k| k.to_string())
+        .collect()
+}
+
+/// View and change the bot settings of this server
+#[poise::command(
+    slash_command,
+    prefix_command,
+    rename = "settings",
+    name_localized("de", "einstellungen"),
+    description_localized("de", "Zeige und ändere die Einstellungen des Bots für diesen Server"),
+    required_permissions = "MANAGE_GUILD",
+    default_member_permissions = "MANAGE_GUILD",
+    guild_only,
+    subcommands(
+        "show",
+        "prefix",
+        "channel",
+        "role",
+        "general",
+        "xp",
+        "voicexp",
+        "xpmultiplier",
+        "mealplan",
+        "rss",
+        "verification"
+    )
+)]
+pub async fn settings(ctx: Context<'_>) -> Result<(), Error> {
+    ctx.say("Use one of the subcommands, e.g. `settings show general`")
+        .await
+        .map_err(Error::Serenity)?;
+    Ok(())
+}
+
+/// Show a section of the settings
+#[poise::command(
+    slash_command,
+    prefix_command,
+    description_localized("de", "Zeige einen Abschnitt der Einstellungen"),
+    required_permissions = "MANAGE_GUILD",
+    guild_only,
+    ephemeral
+)]
+pub async fn show(
+    ctx: Context<'_>,
+    #[description = "Settings section"]
+    #[autocomplete = "section_autocomplete"]
+    section: String,
+) -> Result<(), Error> {
+    let config = ctx.data().config.get(ctx.guild_id().unwrap()).await?;
+    show_section(ctx, &config, &section, "Settings").await
+}
+
+/// Change the prefix for prefix commands
+#[poise::command(
+    slash_command,
+    prefix_command,
+    description_localized("de", "Ändere das Prefix für Prefix-Befehle"),
+    required_permissions = "MANAGE_GUILD",
+    guild_only
+)]
+pub async fn prefix(
+    ctx: Context<'_>,
+    #[description = "New prefix"] prefix: String,
+) -> Result<(), Error> {
+    let guild_id = ctx.guild_id().unwrap();
+    let mut config = ctx.data().config.get(guild_id).await?;
+    config.prefix = prefix;
+    config
+        .validate()
+        .map_err(|problems| Error::WithMessage(problems.join(", ")))?;
+    ctx.data().config.set(guild_id, config.clone()).await?;
+
+    ctx.say(format!("Prefix changed to `{}`", config.prefix))
+        .await
+        .map_err(Error::Serenity)?;
+    Ok(())
+}
+
+/// Change one of the channels the bot uses
+#[poise::command(
+    slash_command,
+    prefix_command,
+    description_localized("de", "Ändere einen der Channel, die der Bot benutzt"),
+    required_permissions = "MANAGE_GUILD",
+    guild_only
+)]
+pub async fn channel(
+    ctx: Context<'_>,
+    #[description = "Which channel to change"]
+    #[autocomplete = "channel_key_autocomplete"]
+    key: String,
+    #[description = "The new channel"] channel: serenity::GuildChannel,
+) -> Result<(), Error> {
+    update_settings(ctx, "channels", |config| {
+        let channels = &mut config.channels;
+        match key.as_str() {
+            "xp" => channels.xp = channel.id,
+            "rules" => channels.rules = channel.id,
+            "news" => channels.news = channel.id,
+            "logs" => channels.logs = channel.id,
+            "ads" => channels.ads = channel.id,
+            "mealplan" => channels.mealplan = channel.id,
+            // temporary voice channels are created when joining a channel with this name
+            "createChannel" => channels.create_channel = channel.name.clone(),
+            _ => return Err(Error::WithMessage(format!("Unknown channel `{}`", key))),
+        }
+        Ok(())
+    })
+    .await
+}
+
+/// Change one of the roles the bot uses
+#[poise::command(
+    slash_command,
+    prefix_command,
+    description_localized("de", "Ändere eine der Rollen, die der Bot benutzt"),
+    required_permissions = "MANAGE_GUILD",
+    guild_only
+)]
+pub async fn role(
+    ctx: Context<'_>,
+    #[description = "Which role to change"]
+    #[autocomplete = "role_key_autocomplete"]
+    key: String,
+    #[description = "The new role"] role: serenity::Role,
+) -> Result<(), Error> {
+    update_settings(ctx, "roles", |config| {
+        let roles = &mut config.roles;
+        match key.as_str() {
+            "staffrole" => roles.staffrole = role.id,
+            "semestermodrole" => roles.semestermodrole = role.id,
+            "verified" => roles.verified = role.id,
+            "mealplannotify" => roles.mealplannotify = role.id,
+            _ => return Err(Error::WithMessage(format!("Unknown role `{}`", key))),
+        }
+        Ok(())
+    })
+    .await
+}
+
+/// Change the general settings (xp and ads)
+#[poise::command(
+    slash_command,
+    prefix_command,
+    description_localized("de", "Ändere die allgemeinen Einstellungen (XP und Werbung)"),
+    required_permissions = "MANAGE_GUILD",
+    guild_only
+)]
+pub async fn general(
+    ctx: Context<'_>,
+    #[description = "Characters in a message that equal one XP"] chars_for_level: Option<i64>,
+    #[description = "How much slower XP is gained on higher levels"] xp_scaling_factor: Option<f64>,
+    #[description = "Time in milliseconds before an ad gets deleted"] adstimeout: Option<i64>,
+) -> Result<(), Error> {
+    update_settings(ctx, "general", |config| {
+        let general = &mut config.general;
+        if let Some(chars_for_level) = chars_for_level {
+            general.chars_for_level = chars_for_level;
+        }
+        if let Some(xp_scaling_factor) = xp_scaling_factor {
+            general.xp_scaling_factor = xp_scaling_factor;
+        }
+        if let Some(adstimeout) = adstimeout {
+            general.adstimeout = adstimeout;
+        }
+        Ok(())
+    })
+    .await
+}
+
+/// Change how messages earn XP
+#[poise::command(
+    slash_command,
+    prefix_command,
+    description_localized("de", "Ändere, wie Nachrichten XP bringen"),
+    required_permissions = "MANAGE_GUILD",
+    guild_only
+)]
+pub async fn xp(
+    ctx: Context<'_>,
+    #[description = "Seconds after earning XP before messages earn XP again"] xp_cooldown_seconds: Option<u64>,
+    #[description = "Most XP a single message can earn"] max_xp_per_message: Option<f64>,
+    #[description = "XP multiplier for every repetition of a recent message (0 to 1)"] xp_repeat_factor: Option<f64>,
+    #[description = "Count the characters in code blocks"] xp_count_code_blocks: Option<bool>,
+    #[description = "Count the characters of links"] xp_count_links: Option<bool>,
+) -> Result<(), Error> {
+    // the xp settings are stored in the general section
+    update_settings(ctx, "general", |config| {
+        let general = &mut config.general;
+        if let Some(xp_cooldown_seconds) = xp_cooldown_seconds {
+            general.xp_cooldown_seconds = xp_cooldown_seconds;
+        }
+        if let Some(max_xp_per_message) = max_xp_per_message {
+            general.max_xp_per_message = max_xp_per_message;
+        }
+        if let Some(xp_repeat_factor) = xp_repeat_factor {
+            general.xp_repeat_factor = xp_repeat_factor;
+        }
+        if let Some(xp_count_code_blocks) = xp_count_code_blocks {
+            general.xp_count_code_blocks = xp_count_code_blocks;
+        }
+        if let Some(xp_count_links) = xp_count_links {
+            general.xp_count_links = xp_count_links;
+        }
+        Ok(())
+    })
+    .await
+}
+
+/// Change how time in voice channels earns XP
+#[poise::command(
+    slash_command,
+    prefix_command,
+    description_localized("de", "Ändere, wie Zeit in Sprachkanälen XP bringt"),
+    required_permissions = "MANAGE_GUILD",
+    guild_only
+)]
+pub async fn voicexp(
+    ctx: Context<'_>,
+    #[description = "XP per minute in voice, 0 disables voice XP"] voice_xp_per_minute: Option<f64>,
+    #[description = "Unmuted members a voice channel needs to earn XP"] voice_xp_min_members: Option<u64>,
+) -> Result<(), Error> {
+    // the voice xp settings are stored in the general section
+    update_settings(ctx, "general", |config| {
+        let general = &mut config.general;
+        if let Some(voice_xp_per_minute) = voice_xp_per_minute {
+            general.voice_xp_per_minute = voice_xp_per_minute;
+        }
+        if let Some(voice_xp_min_members) = voice_xp_min_members {
+            general.voice_xp_min_members = voice_xp_min_members as usize;
+        }
+        Ok(())
+    })
+    .await
+}
+
+/// Change the XP multiplier of a channel, category or role
+#[poise::command(
+    slash_command,
+    prefix_command,
+    description_localized("de", "Ändere den XP-Multiplikator eines Kanals, einer Kategorie oder Rolle"),
+    required_permissions = "MANAGE_GUILD",
+    guild_only
+)]
+pub async fn xpmultiplier(
+    ctx: Context<'_>,
+    #[description = "Channel or category"] channel: Option<serenity::GuildChannel>,
+    #[description = "Role, e.g. boosters"] role: Option<serenity::Role>,
+    #[description = "Multiplier, 0 disables XP, leave empty to remove it"] multiplier: Option<f64>,
+) -> Result<(), Error> {
+    update_settings(ctx, "general", |config| {
+        let general = &mut config.general;
+        match (channel, role) {
+            (Some(channel), None) => {
+                let multipliers = if channel.kind == serenity::ChannelType::Category {
+                    &mut general.xp_category_multipliers
+                } else {
+                    &mut general.xp_channel_multipliers
+                };
+                match multiplier {
+                    Some(multiplier) => multipliers.insert(channel.id, multiplier),
+                    None => multipliers.remove(&channel.id),
+                };
+            }
+            (None, Some(role)) => {
+                match multiplier {
+                    Some(multiplier) => general.xp_role_multipliers.insert(role.id, multiplier),
+                    None => general.xp_role_multipliers.remove(&role.id),
+                };
+            }
+            _ => {
+                return Err(Error::WithMessage(
+                    "Give either a channel or a role".to_string(),
+                ))
+            }
+        }
+        Ok(())
+    })
+    .await
+}
+
+/// Change when and where the mensaplan is posted
+///
+/// Once canteens are configured the url, day and time belong to the picked canteen
+#[poise::command(
+    slash_command,
+    prefix_command,
+    description_localized("de", "Ändere wann und woher der Mensaplan gepostet wird"),
+    required_permissions = "MANAGE_GUILD",
+    guild_only
+)]
+pub async fn mealplan(
+    ctx: Context<'_>,
+    #[description = "Post the mensaplan at all"] enabled: Option<bool>,
+    #[description = "URL of the mensaplan PDF"] url: Option<String>,
+    #[description = "Day to post the plan on"] day: Option<Weekday>,
+    #[description = "Time to post the plan at (HH:MM)"] hour: Option<String>,
+    #[description = "Minutes between checks"] check: Option<u64>,
+    #[description = "Canteen whose url, day and time to change"]
+    #[autocomplete = "canteen_autocomplete"]
+    canteen: Option<String>,
+) -> Result<(), Error> {
+    let hour = match hour {
+        Some(hour) => Some(
+            chrono::NaiveTime::parse_from_str(&hour, "%H:%M").map_err(|_| {
+                Error::WithMessage(format!("Invalid time {}. Use HH:MM", hour))
+            })?,
+        ),
+        None => None,
+    };
+
+    update_settings(ctx, "mealplan", |config| {
+        let mealplan = &mut config.mealplan;
+        if let Some(enabled) = enabled {
+            mealplan.post_mealplan = enabled;
+        }
+        if mealplan.canteens.is_empty() {
+            if let Some(url) = url {
+                mealplan.url = url;
+            }
+            if let Some(day) = day {
+                mealplan.post_on_day = day.into();
+            }
+            if let Some(hour) = hour {
+                mealplan.post_at_hour = hour;
+            }
+        } else if url.is_some() || day.is_some() || hour.is_some() {
+            // the url and schedule above are only used without canteens
+            let canteen = match (canteen, mealplan.canteens.len()) {
+                (Some(name), _) => mealplan
+                    .canteens
+                    .iter_mut()
+                    .find(|c| c.name.eq_ignore_ascii_case(name.trim()))
+                    .ok_or_else(|| Error::WithMessage(format!("Unknown canteen {}", name)))?,
+                (None, 1) => &mut mealplan.canteens[0],
+                (None, _) => {
+                    return Err(Error::WithMessage(
+                        "Pick the canteen whose url, day or time to change".to_string(),
+                    ))
+                }
+            };
+            if let Some(url) = url {
+                canteen.url = url;
+            }
+            if let Some(day) = day {
+                canteen.post_on_day = day.into();
+            }
+            if let Some(hour) = hour {
+                canteen.post_at_hour = hour;
+            }
+        }
+        if let Some(check) = check {
+            mealplan.check = check;
+        }
+        Ok(())
+    })
+    .await
+}
+
+/// Change the RSS settings
+#[poise::command(
+    slash_command,
+    prefix_command,
+    description_localized("de", "Ändere die RSS Einstellungen"),
+    required_permissions = "MANAGE_GUILD",
+    guild_only
+)]
+pub async fn rss(
+    ctx: Context<'_>,
+    #[description = "Post RSS items at all"] enabled: Option<bool>,
+    #[description = "Hours between feed checks"] check_interval_hours: Option<u64>,
+) -> Result<(), Error> {
+    update_settings(ctx, "rssSettings", |config| {
+        let rss = &mut config.rss_settings;
+        if let Some(enabled) = enabled {
+            rss.post_rss = enabled;
+        }
+        if let Some(check_interval_hours) = check_interval_hours {
+            rss.rss_check_interval_hours = check_interval_hours;
+        }
+        Ok(())
+    })
+    .await
+}
+
+/// Change the verification settings
+#[poise::command(
+    slash_command,
+    prefix_command,
+    description_localized("de", "Ändere die Einstellungen der Verifizierung"),
+    required_permissions = "MANAGE_GUILD",
+    guild_only
+)]
+pub async fn verification(
+    ctx: Context<'_>,
+    #[description = "Minutes a verification code stays valid"] code_ttl_minutes: Option<i64>,
+    #[description = "Wrong codes allowed before a new one has to be requested"] max_attempts: Option<i32>,
+    #[description = "Hours before a reverification deadline to remind members"] reminder_hours: Option<i64>,
+) -> Result<(), Error> {
+    update_settings(ctx, "verification", |config| {
+        let verification = &mut config.verification;
+        if let Some(code_ttl_minutes) = code_ttl_minutes {
+            verification.code_ttl_minutes = code_ttl_minutes;
+        }
+        if let Some(max_attempts) = max_attempts {
+            verification.max_attempts = max_attempts;
+        }
+        if let Some(reminder_hours) = reminder_hours {
+            verification.reminder_hours_before_deadline = reminder_hours;
+        }
+        Ok(())
+    })
+    .await
+}
@@ -1,8 +1,9 @@
 use crate::{
     prelude::{translations::Lang, Error},
-    structs::{self, CodeEmailPair},
+    structs,
     utils::CurrentEmail,
-    Context,
+    verification::{self, CodeCheck},
+    xp, Context,
 };
 use poise::serenity_prelude as serenity;
 
@@ -35,10 +36,10 @@ pub async fn verify(ctx: Context<'_>) -> Result<(), Error> {
 )]
 pub async fn init(
     ctx: Context<'_>,
-    #[description = "Your student email address (must be ending in @stud.hs-kempten.de)"]
+    #[description = "Your university email address (e.g. ending in @stud.hs-kempten.de)"]
     #[description_localized(
         "de",
-        "Deine Studierenden E-Mail Adresse (muss mit @stud.hs-kempten.de enden)"
+        "Deine Hochschul E-Mail Adresse (z.B. endend auf @stud.hs-kempten.de)"
     )]
     #[name_localized("de", "email-adresse")]
     #[rename = "email"]
@@ -51,9 +52,12 @@ pub async fn init(
         _ => Lang::En,
     };
 
-    // check if email is valid
-    if !email_used.ends_with("@stud.hs-kempten.de") {
-        return Err(Error::WithMessage(lang.invalid_email().into()));
+    // check if email belongs to one of the accepted domains
+    let config = ctx.data().config.resolve(ctx.guild_id()).await?;
+    if config.verification_domain(&email_used).is_none() {
+        return Err(Error::WithMessage(
+            lang.invalid_email_domain(config.verification_domain_list()),
+        ));
     }
 
     // check if email is already in use
@@ -70,10 +74,21 @@ pub async fn init(
         return Err(Error::WithMessage(lang.err_already_verified().into()));
     }
 
+    let user_id = ctx.author().id;
+    if verification::email_pending_for_other_user(pool, user_id, &email_used).await? {
+        return Err(Error::WithMessage(lang.err_email_pending().into()));
+    }
+
     let code = crate::utils::generate_verification_code();
 
-    let user_id = ctx.author().id;
-    ctx.data().email_codes.insert(user_id, CodeEmailPair { code: code.clone(), email: email_used.clone() });
+    verification::create_pending(
+        pool,
+        &config.verification,
+        user_id,
+        &email_used,
+        &code,
+    )
+    .await?;
 
     let emilia = ctx.data().email_task.clone();
 
@@ -136,21 +151,30 @@ pub async fn code(
 
     let user_id = ctx.author().id;
     let pool = &ctx.data().db;
+    let config = ctx.data().config.resolve(ctx.guild_id()).await?;
 
-    let code = ctx.data().email_codes.get(&user_id);
-
-    if code.is_none() {
-        return Err(Error::WithMessage(lang.err_already_verified().into()));
-    }
-
-    let code_key = code.unwrap();
-
-
-    let actual_code = code_key.code == supplied_code;
-
-    if !actual_code {
-        return Err(Error::WithMessage(lang.err_invalid_code().into()));
-    }
+    let email = match verification::check_code(
+        pool,
+        &config.verification,
+        user_id,
+        &supplied_code,
+    )
+    .await?
+    {
+        CodeCheck::Valid(email) => email,
+        CodeCheck::Invalid(attempts_left) => {
+            return Err(Error::WithMessage(
+                lang.err_invalid_code_attempts(attempts_left),
+            ))
+        }
+        CodeCheck::NotPending => {
+            return Err(Error::WithMessage(lang.err_no_pending_code().into()))
+        }
+        CodeCheck::Expired => return Err(Error::WithMessage(lang.err_code_expired().into())),
+        CodeCheck::TooManyAttempts => {
+            return Err(Error::WithMessage(lang.err_too_many_attempts().into()))
+        }
+    };
 
     let user = sqlx::query_as::<sqlx::Postgres, structs::VerifiedUsers>(
         "SELECT * FROM verified_users WHERE user_id = $1",
@@ -160,27 +184,33 @@ pub async fn code(
     .await
     .map_err(Error::Database)?;
 
-    if user.is_some() {
-        return Err(Error::WithMessage(lang.err_already_verified().into()));
-    }
-
-    sqlx::query("INSERT INTO verified_users (user_id, user_email) VALUES ($1, $2)")
-        .bind(user_id.0 as i64)
-        .bind(code_key.email.clone())
-        .execute(pool)
-        .await
-        .map_err(Error::Database)?;
+    // already verified users entering a code are reverifying
+    let message = if user.is_some() {
+        verification::complete_reverification(pool, user_id, &email).await?;
+        lang.reverification_successful()
+    } else {
+        sqlx::query("INSERT INTO verified_users (user_id, user_email) VALUES ($1, $2)")
+            .bind(user_id.0 as i64)
+            .bind(&email)
+            .execute(pool)
+            .await
+            .map_err(Error::Database)?;
+        lang.verification_successful()
+    };
 
     ctx.send(|msg| {
         msg.embed(|embed| {
-            embed.description(lang.verification_successful())
+            embed.description(message)
         })
     })
     .await
     .map_err(Error::Serenity)?;
 
-    // give them the verified role
-    let verified_role = ctx.data().config.roles.verified;
+    // give them the role of the domain they verified with
+    let verified_role = config
+        .verification_domain(&email)
+        .map(|d| d.role)
+        .unwrap_or(config.roles.verified);
 
     let mem = ctx.author_member().await.unwrap();
     mem.into_owned()
@@ -189,16 +219,7 @@ pub async fn code(
         .map_err(Error::Serenity)?;
 
 
-    // remove the code from the hashmap
-    // this deadlocks the bot ?? What the fuck ?? fix ur code acri
-    let dashmap = ctx.data().email_codes.clone();
-    // delete user_id from dashmap
-    if let Some (_skrr) = dashmap.remove(&user_id) {
-        tracing::debug!("deleted successfully!")
-    } else {
-        tracing::error!(":hmm:")
-    }
-
+    verification::remove_pending(pool, user_id).await?;
 
     Ok(())
 }
@@ -275,23 +296,64 @@ pub async fn xp(ctx: Context<'_>) -> Result<(), Error> {
     .await
     .map_err(Error::Database)?;
 
-    if let Some(user) = user {
-        ctx.send(|f| {
-            f.embed(|e| {
-                e.description(lang.xp_msg(user.user_level, user.user_xp))
-            });
-            f
-        })
-        .await
-        .map_err(Error::Serenity)?;
-    } else {
-        ctx.send(|f| {
-            f.embed(|e| e.description(lang.xp_msg_none()));
-            f
-        })
-        .await
-        .map_err(Error::Serenity)?;
+    let config = ctx.data().config.resolve(ctx.guild_id()).await?;
+    let mut description = match user {
+        Some(user) => {
+            let progress = ctx.data().config.leveling().progress(user.user_xp);
+            format!(
+                "{}\n{}",
+                lang.xp_msg(progress.level, user.user_xp.floor()),
+                lang.xp_progress(
+                    progress.level + 1,
+                    progress.xp_for_next_level.ceil(),
+                    progress.xp_into_level.floor()
+                )
+            )
+        }
+        None => lang.xp_msg_none().to_string(),
+    };
+
+    // multipliers only apply on servers
+    if ctx.guild_id().is_some() {
+        let roles = ctx
+            .author_member()
+            .await
+            .map(|member| member.roles.clone())
+            .unwrap_or_default();
+        let multipliers = xp::Multipliers::resolve(
+            ctx.serenity_context(),
+            ctx.channel_id(),
+            &roles,
+            &config.general,
+        )
+        .await;
+
+        // 1.5 instead of 1.50
+        let format = |multiplier: f64| ((multiplier * 100.0).round() / 100.0).to_string();
+        description.push_str("\n\n");
+        if multipliers.total() <= 0.0 {
+            description.push_str(lang.xp_multiplier_off());
+        } else {
+            description.push_str(&lang.xp_multiplier(format(multipliers.total())));
+        }
+        if !multipliers.events.is_empty() {
+            let events = multipliers
+                .events
+                .iter()
+                .map(|(name, multiplier)| format!("{} ×{}", name, format(*multiplier)))
+                .collect::<Vec<_>>()
+                .join(", ");
+            description.push('\n');
+            description.push_str(&lang.xp_events(events));
+        }
     }
 
+    ctx.send(|f| {
+        f.embed(|e| e.description(description));
+        f
+    })
+    .await
+    .map_err(Error::Serenity)?;
+
     Ok(())
 }
@@ -1,5 +1,9 @@
-use std::collections::HashMap;
+use std::{
+    collections::HashMap,
+    sync::{Arc, RwLock},
+};
 
+use dashmap::DashMap;
 use serde::{Deserialize, Serialize};
 
 use poise::serenity_prelude as serenity;
@@ -19,6 +23,10 @@ pub struct FacultyManagerConfig {
     pub rss_settings: FacultyManagerRssConfig,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub podcast_settings: Option<FacultyManagerPodcastConfig>,
+    #[serde(default = "default_verification_config")]
+    pub verification: FacultyManagerVerificationConfig,
+    #[serde(default)]
+    pub level_roles: FacultyManagerLevelRolesConfig,
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
@@ -57,6 +65,80 @@ pub struct FacultyManagerGeneralConfig {
     pub adstimeout: i64,
     pub chars_for_level: i64,
     pub xp_scaling_factor: f64,
+    /// How much XP each level needs, see `leveling::LevelCurve`
+    ///
+    /// XP is shared by all guilds, so only the value in `config.json` is used
+    #[serde(default)]
+    pub level_curve: crate::leveling::LevelCurve,
+    /// XP of the first level for the linear and quadratic curves, only read from `config.json`
+    #[serde(default = "default_xp_per_level")]
+    pub xp_per_level: f64,
+    /// Seconds after earning XP before a user's messages earn XP again
+    #[serde(default = "default_xp_cooldown_seconds")]
+    pub xp_cooldown_seconds: u64,
+    /// Most XP a single message can earn
+    #[serde(default = "default_max_xp_per_message")]
+    pub max_xp_per_message: f64,
+    /// How many of a user's last messages new ones are compared with
+    #[serde(default = "default_xp_repeat_window")]
+    pub xp_repeat_window: usize,
+    /// From which similarity (0 to 1) a message counts as a repeat of an earlier one
+    #[serde(default = "default_xp_repeat_similarity")]
+    pub xp_repeat_similarity: f64,
+    /// The XP of a message is multiplied by this for every earlier message it repeats
+    #[serde(default = "default_xp_repeat_factor")]
+    pub xp_repeat_factor: f64,
+    /// Count the characters in code blocks
+    #[serde(default)]
+    pub xp_count_code_blocks: bool,
+    /// Count the characters of links
+    #[serde(default)]
+    pub xp_count_links: bool,
+    /// XP multiplier of single channels, 0 disables XP, threads use the one of their channel
+    #[serde(default)]
+    pub xp_channel_multipliers: HashMap<serenity::ChannelId, f64>,
+    /// XP multiplier of all channels in a category, unless the channel has its own
+    #[serde(default)]
+    pub xp_category_multipliers: HashMap<serenity::ChannelId, f64>,
+    /// XP multiplier of members with a role, e.g. boosters, the highest one of a member counts
+    #[serde(default)]
+    pub xp_role_multipliers: HashMap<serenity::RoleId, f64>,
+    /// Time-limited multipliers, e.g. on weekends or during the exam period
+    #[serde(default)]
+    pub xp_events: Vec<XpEvent>,
+    /// XP per minute in a voice channel, 0 disables voice XP
+    #[serde(default = "default_voice_xp_per_minute")]
+    pub voice_xp_per_minute: f64,
+    /// Unmuted members a voice channel needs before any of them earns XP
+    #[serde(default = "default_voice_xp_min_members")]
+    pub voice_xp_min_members: usize,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct XpEvent {
+    /// Shown in `/xp`, e.g. "Klausurphase"
+    pub name: String,
+    pub multiplier: f64,
+    /// First day of the event, runs since forever if missing
+    #[serde(default)]
+    pub start: Option<chrono::NaiveDate>,
+    /// Last day of the event, runs forever if missing
+    #[serde(default)]
+    pub end: Option<chrono::NaiveDate>,
+    /// Only on these days of the week, e.g. `["Sat", "Sun"]` for weekends
+    #[serde(default)]
+    pub weekdays: Vec<chrono::Weekday>,
+}
+
+impl XpEvent {
+    pub fn is_active(&self, date: chrono::NaiveDate) -> bool {
+        use chrono::Datelike;
+
+        self.start.is_none_or(|start| start <= date)
+            && self.end.is_none_or(|end| date <= end)
+            && (self.weekdays.is_empty() || self.weekdays.contains(&date.weekday()))
+    }
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
@@ -68,6 +150,21 @@ pub struct FacultyManagerMealplanConfig {
     pub post_at_hour: chrono::NaiveTime,
     pub imgsettings: MealplanImageSettings,
     pub check: u64,
+    /// Canteens and cafeterias whose plans are posted, replaces `url` and the schedule above
+    #[serde(default)]
+    pub canteens: Vec<Canteen>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct Canteen {
+    /// Name shown in messages and used to pick the canteen in commands, e.g. "Mensa Kempten"
+    pub name: String,
+    pub url: String,
+    pub post_on_day: chrono::Weekday,
+    pub post_at_hour: chrono::NaiveTime,
+    pub channel: serenity::ChannelId,
+    pub notify_role: serenity::RoleId,
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
@@ -102,9 +199,437 @@ pub struct FacultyManagerRssConfig {
 #[serde(rename_all = "camelCase")]
 pub struct FacultyManagerPodcastConfig {
     pub post_podcast: bool,
+    /// Hours between checks of the feed
     pub podcast_check_interval: u64,
+    /// Podcast or YouTube channel feed, e.g. `https://www.youtube.com/feeds/videos.xml?channel_id=...`
     pub podcast_url: String,
     pub podcast_channel: serenity::ChannelId,
+    /// Mentioned with every new episode
+    #[serde(default)]
+    pub mention_role: Option<serenity::RoleId>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct FacultyManagerVerificationConfig {
+    /// How long a verification code stays valid, in minutes
+    pub code_ttl_minutes: i64,
+    /// How many wrong codes a user may enter before having to request a new one
+    pub max_attempts: i32,
+    /// E-Mail domains accepted for verification, each granting its own role
+    #[serde(default)]
+    pub domains: Vec<VerificationDomain>,
+    /// How long before a reverification deadline members who haven't responded get reminded, in hours
+    #[serde(default = "default_reminder_hours")]
+    pub reminder_hours_before_deadline: i64,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct FacultyManagerLevelRolesConfig {
+    /// Roles granted on reaching a level
+    #[serde(default)]
+    pub roles: Vec<LevelRole>,
+    /// Members only keep the role of the highest level they reached
+    #[serde(default)]
+    pub remove_lower: bool,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct LevelRole {
+    pub level: i32,
+    pub role: serenity::RoleId,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct VerificationDomain {
+    /// What this group of users is called, e.g. "Students" or "Staff"
+    pub name: String,
+    /// Everything after the @, e.g. `stud.hs-kempten.de`
+    pub domain: String,
+    /// Role granted to users verifying with an address of this domain
+    pub role: serenity::RoleId,
+}
+
+fn default_verification_config() -> FacultyManagerVerificationConfig {
+    FacultyManagerVerificationConfig {
+        code_ttl_minutes: 30,
+        max_attempts: 5,
+        domains: Vec::new(),
+        reminder_hours_before_deadline: default_reminder_hours(),
+    }
+}
+
+fn default_reminder_hours() -> i64 {
+    48
+}
+
+fn default_xp_per_level() -> f64 {
+    100.0
+}
+
+fn default_xp_cooldown_seconds() -> u64 {
+    30
+}
+
+fn default_max_xp_per_message() -> f64 {
+    5.0
+}
+
+fn default_xp_repeat_window() -> usize {
+    5
+}
+
+fn default_xp_repeat_similarity() -> f64 {
+    0.8
+}
+
+fn default_xp_repeat_factor() -> f64 {
+    0.5
+}
+
+fn default_voice_xp_per_minute() -> f64 {
+    0.5
+}
+
+fn default_voice_xp_min_members() -> usize {
+    2
+}
+
+impl FacultyManagerConfig {
+    /// All domains accepted for verification
+    ///
+    /// Configs without a `verification.domains` list fall back to student addresses and the verified role
+    pub fn verification_domains(&self) -> Vec<VerificationDomain> {
+        if self.verification.domains.is_empty() {
+            vec![VerificationDomain {
+                name: "Students".to_string(),
+                domain: "stud.hs-kempten.de".to_string(),
+                role: self.roles.verified,
+            }]
+        } else {
+            self.verification.domains.clone()
+        }
+    }
+
+    /// Finds the accepted domain an e-mail address belongs to, `None` if the address is not accepted
+    pub fn verification_domain(&self, email: &str) -> Option<VerificationDomain> {
+        let local_part_regex = regex::Regex::new(r"^[a-zA-Z0-9_.+-]+$").unwrap();
+        let (local_part, domain) = email.trim().rsplit_once('@')?;
+
+        if !local_part_regex.is_match(local_part) {
+            return None;
+        }
+
+        self.verification_domains()
+            .into_iter()
+            .find(|d| d.domain.eq_ignore_ascii_case(domain))
+    }
+
+    /// Comma separated list of accepted domains, for error messages
+    pub fn verification_domain_list(&self) -> String {
+        self.verification_domains()
+            .iter()
+            .map(|d| format!("@{}", d.domain))
+            .collect::<Vec<_>>()
+            .join(", ")
+    }
+}
+
+impl FacultyManagerConfig {
+    /// All canteens whose plans are posted
+    ///
+    /// Configs without a `mealplan.canteens` list fall back to a single canteen built from
+    /// `mealplan.url`, the mealplan channel and the notify role
+    pub fn canteens(&self) -> Vec<Canteen> {
+        if self.mealplan.canteens.is_empty() {
+            vec![Canteen {
+                name: "Mensa".to_string(),
+                url: self.mealplan.url.clone(),
+                post_on_day: self.mealplan.post_on_day,
+                post_at_hour: self.mealplan.post_at_hour,
+                channel: self.channels.mealplan,
+                notify_role: self.roles.mealplannotify,
+            }]
+        } else {
+            self.mealplan.canteens.clone()
+        }
+    }
+
+    /// Finds a canteen by name, ignoring case, `None` picks the first one
+    pub fn canteen(&self, name: Option<&str>) -> Option<Canteen> {
+        let canteens = self.canteens();
+        match name {
+            Some(name) => canteens
+                .into_iter()
+                .find(|c| c.name.eq_ignore_ascii_case(name.trim())),
+            None => canteens.into_iter().next(),
+        }
+    }
+}
+
+impl FacultyManagerConfig {
+    /// Checks the values serde can't check for us, returns every problem found
+    pub fn validate(&self) -> Result<(), Vec<String>> {
+        let mut problems = Vec::new();
+
+        if self.prefix.trim().is_empty() {
+            problems.push("`prefix` must not be empty".to_string());
+        }
+        if self.general.chars_for_level <= 0 {
+            problems.push("`general.charsForLevel` must be greater than 0".to_string());
+        }
+        if self.general.xp_scaling_factor < 0.0 {
+            problems.push("`general.xpScalingFactor` must not be negative".to_string());
+        }
+        if self.general.xp_per_level <= 0.0 {
+            problems.push("`general.xpPerLevel` must be greater than 0".to_string());
+        }
+        if self.general.max_xp_per_message <= 0.0 {
+            problems.push("`general.maxXpPerMessage` must be greater than 0".to_string());
+        }
+        if !(0.0..=1.0).contains(&self.general.xp_repeat_similarity) {
+            problems.push("`general.xpRepeatSimilarity` must be between 0 and 1".to_string());
+        }
+        if !(0.0..=1.0).contains(&self.general.xp_repeat_factor) {
+            problems.push("`general.xpRepeatFactor` must be between 0 and 1".to_string());
+        }
+        if self.general.voice_xp_per_minute < 0.0 {
+            problems.push("`general.voiceXpPerMinute` must not be negative".to_string());
+        }
+        let multipliers = self
+            .general
+            .xp_channel_multipliers
+            .values()
+            .chain(self.general.xp_category_multipliers.values())
+            .chain(self.general.xp_role_multipliers.values());
+        for multiplier in multipliers {
+            if *multiplier < 0.0 {
+                problems.push(format!("xp multiplier {} must not be negative", multiplier));
+            }
+        }
+        for event in &self.general.xp_events {
+            if event.name.trim().is_empty() {
+                problems.push("xp events need a name".to_string());
+            }
+            if event.multiplier < 0.0 {
+                problems.push(format!("xp event `{}` must not have a negative multiplier", event.name));
+            }
+            if let (Some(start), Some(end)) = (event.start, event.end) {
+                if end < start {
+                    problems.push(format!("xp event `{}` ends before it starts", event.name));
+                }
+            }
+        }
+        if self.mealplan.check == 0 {
+            problems.push("`mealplan.check` must be greater than 0".to_string());
+        }
+        if reqwest::Url::parse(&self.mealplan.url).is_err() {
+            problems.push(format!("`mealplan.url` is not a valid url: {}", self.mealplan.url));
+        }
+        for (i, canteen) in self.mealplan.canteens.iter().enumerate() {
+            if canteen.name.trim().is_empty() {
+                problems.push(format!("canteen {} needs a name", i + 1));
+            }
+            if self.mealplan.canteens[..i]
+                .iter()
+                .any(|c| c.name.eq_ignore_ascii_case(&canteen.name))
+            {
+                problems.push(format!("canteen `{}` is listed twice", canteen.name));
+            }
+            if reqwest::Url::parse(&canteen.url).is_err() {
+                problems.push(format!(
+                    "url of canteen `{}` is not a valid url: {}",
+                    canteen.name, canteen.url
+                ));
+            }
+        }
+        if self.rss_settings.rss_check_interval_hours == 0 {
+            problems.push("`rssSettings.rssCheckIntervalHours` must be greater than 0".to_string());
+        }
+        for (channel, url) in &self.rss_settings.rss_feed_data {
+            if reqwest::Url::parse(url).is_err() {
+                problems.push(format!("feed url for channel {} is not a valid url: {}", channel, url));
+            }
+        }
+        if let Some(podcast) = &self.podcast_settings {
+            if podcast.podcast_check_interval == 0 {
+                problems.push(
+                    "`podcastSettings.podcastCheckInterval` must be greater than 0".to_string(),
+                );
+            }
+            if reqwest::Url::parse(&podcast.podcast_url).is_err() {
+                problems.push(format!(
+                    "`podcastSettings.podcastUrl` is not a valid url: {}",
+                    podcast.podcast_url
+                ));
+            }
+        }
+        if self.verification.code_ttl_minutes <= 0 {
+            problems.push("`verification.codeTtlMinutes` must be greater than 0".to_string());
+        }
+        if self.verification.max_attempts <= 0 {
+            problems.push("`verification.maxAttempts` must be greater than 0".to_string());
+        }
+        for (i, level_role) in self.level_roles.roles.iter().enumerate() {
+            if level_role.level < 0 {
+                problems.push(format!("level role {} must not have a negative level", level_role.role));
+            }
+            if self.level_roles.roles[..i]
+                .iter()
+                .any(|other| other.level == level_role.level)
+            {
+                problems.push(format!("level {} has more than one role", level_role.level));
+            }
+        }
+        for domain in &self.verification.domains {
+            if domain.domain.is_empty() || domain.domain.contains('@') {
+                problems.push(format!(
+                    "verification domain `{}` must be given without the @",
+                    domain.domain
+                ));
+            }
+        }
+
+        if problems.is_empty() {
+            Ok(())
+        } else {
+            Err(problems)
+        }
+    }
+}
+
+impl FacultyManagerConfig {
+    /// Validates the settings of a guild, which may in addition only use the guild's own channels
+    pub fn validate_in_guild(
+        &self,
+        guild_channels: &HashMap<serenity::ChannelId, serenity::GuildChannel>,
+    ) -> Result<(), Vec<String>> {
+        let mut problems = self.validate().err().unwrap_or_default();
+
+        for channel in self.channel_ids() {
+            if !guild_channels.contains_key(&channel) {
+                problems.push(format!("channel {} is not a channel of this server", channel.0));
+            }
+        }
+
+        if problems.is_empty() {
+            Ok(())
+        } else {
+            Err(problems)
+        }
+    }
+
+    /// Every channel these settings use, channels that aren't set yet (`0`) are left out
+    fn channel_ids(&self) -> Vec<serenity::ChannelId> {
+        let mut channels = vec![
+            self.channels.xp,
+            self.channels.rules,
+            self.channels.news,
+            self.channels.logs,
+            self.channels.ads,
+            self.channels.mealplan,
+        ];
+        channels.extend(self.mealplan.canteens.iter().map(|canteen| canteen.channel));
+        channels.extend(self.rss_settings.rss_feed_data.keys());
+        channels.extend(self.podcast_settings.iter().map(|podcast| podcast.podcast_channel));
+        channels.extend(self.general.xp_channel_multipliers.keys());
+        channels.extend(self.general.xp_category_multipliers.keys());
+
+        channels.sort();
+        channels.dedup();
+        channels.retain(|channel| channel.0 != 0);
+        channels
+    }
+
+    /// The seed for guilds other than the main one
+    ///
+    /// The channels and roles of `config.json` belong to the main guild, so they start empty and
+    /// nothing is posted until the guild's admins set them up
+    fn for_other_guild(&self) -> FacultyManagerConfig {
+        let mut config = self.clone();
+
+        config.channels = FacultyManagerChannelConfig {
+            xp: serenity::ChannelId(0),
+            rules: serenity::ChannelId(0),
+            news: serenity::ChannelId(0),
+            logs: serenity::ChannelId(0),
+            ads: serenity::ChannelId(0),
+            create_channel: config.channels.create_channel,
+            mealplan: serenity::ChannelId(0),
+        };
+        config.roles = FacultyManagerRoleConfig {
+            staffrole: serenity::RoleId(0),
+            semestermodrole: serenity::RoleId(0),
+            verified: serenity::RoleId(0),
+            mealplannotify: serenity::RoleId(0),
+        };
+
+        config.general.xp_channel_multipliers.clear();
+        config.general.xp_category_multipliers.clear();
+        config.general.xp_role_multipliers.clear();
+        config.mealplan.post_mealplan = false;
+        for canteen in &mut config.mealplan.canteens {
+            canteen.channel = serenity::ChannelId(0);
+            canteen.notify_role = serenity::RoleId(0);
+        }
+        config.rss_settings.post_rss = false;
+        config.rss_settings.rss_feed_data.clear();
+        if let Some(podcast) = &mut config.podcast_settings {
+            podcast.post_podcast = false;
+            podcast.podcast_channel = serenity::ChannelId(0);
+            podcast.mention_role = None;
+        }
+        config.verification.domains.clear();
+        config.level_roles.roles.clear();
+
+        config
+    }
+}
+
+impl FacultyManagerConfig {
+    /// Applies a partial settings document on top of these settings and validates the result
+    ///
+    /// Objects are merged key by key, every other value replaces the current one
+    pub fn patched(&self, patch: serde_json::Value) -> Result<FacultyManagerConfig, Error> {
+        // the url and schedule of the mealplan are only used without canteens
+        if !self.mealplan.canteens.is_empty() {
+            let current = serde_json::to_value(&self.mealplan).map_err(Error::Serde)?;
+            let mealplan = patch.get("mealplan");
+            for key in ["url", "postOnDay", "postAtHour"] {
+                let changed = mealplan.and_then(|mealplan| mealplan.get(key));
+                if changed.is_some_and(|value| Some(value) != current.get(key)) {
+                    return Err(Error::WithMessage(format!(
+                        "`mealplan.{}` has no effect while canteens are configured, change `mealplan.canteens` instead",
+                        key
+                    )));
+                }
+            }
+        }
+
+        let mut current = serde_json::to_value(self).map_err(Error::Serde)?;
+        merge_json(&mut current, patch);
+
+        let patched: FacultyManagerConfig = serde_json::from_value(current).map_err(Error::Serde)?;
+        patched
+            .validate()
+            .map_err(|problems| Error::WithMessage(problems.join(", ")))?;
+
+        Ok(patched)
+    }
+}
+
+fn merge_json(target: &mut serde_json::Value, patch: serde_json::Value) {
+    match (target, patch) {
+        (serde_json::Value::Object(target), serde_json::Value::Object(patch)) => {
+            for (key, value) in patch {
+                merge_json(target.entry(key).or_insert(serde_json::Value::Null), value);
+            }
+        }
+        (target, patch) => *target = patch,
+    }
 }
 
 pub fn read_config() -> Result<FacultyManagerConfig, prelude::Error> {
@@ -112,3 +637,158 @@ pub fn read_config() -> Result<FacultyManagerConfig, prelude::Error> {
     let config: FacultyManagerConfig = serde_json::from_str(&config).map_err(Error::Serde)?;
     Ok(config)
 }
+
+/// Settings of every guild the bot is in, stored in the `guild_settings` table
+///
+/// `config.json` is only used as the seed for guilds that don't have settings yet, only the main
+/// guild (`DISCORD_SERVER_ID`) gets its channels and roles
+///
+/// Cloning is cheap, all clones share the same seed and cache so a reload is seen everywhere
+#[derive(Clone)]
+pub struct ConfigStore {
+    db: sqlx::PgPool,
+    main_guild: serenity::GuildId,
+    seed: Arc<RwLock<FacultyManagerConfig>>,
+    cache: Arc<DashMap<serenity::GuildId, FacultyManagerConfig>>,
+}
+
+impl ConfigStore {
+    pub fn new(
+        db: sqlx::PgPool,
+        main_guild: serenity::GuildId,
+        seed: FacultyManagerConfig,
+    ) -> Self {
+        Self {
+            db,
+            main_guild,
+            seed: Arc::new(RwLock::new(seed)),
+            cache: Arc::new(DashMap::new()),
+        }
+    }
+
+    /// The config read from `config.json`
+    pub fn seed(&self) -> FacultyManagerConfig {
+        self.seed.read().expect("config lock poisoned").clone()
+    }
+
+    /// The level curve of `config.json`, the same for every guild since XP is global
+    pub fn leveling(&self) -> crate::leveling::Leveling {
+        crate::leveling::Leveling::new(&self.seed.read().expect("config lock poisoned").general)
+    }
+
+    /// Re-reads `config.json` and swaps it in if it is valid
+    ///
+    /// Guilds whose settings still equal the old file are updated as well, guilds that were
+    /// customised keep their settings. Returns how many guilds were updated.
+    pub async fn reload(&self) -> Result<u64, Error> {
+        let new = read_config()?;
+        if let Err(problems) = new.validate() {
+            return Err(Error::WithMessage(format!(
+                "config.json is invalid, keeping the old config:\n- {}",
+                problems.join("\n- ")
+            )));
+        }
+
+        let old = self.seed();
+        let updated = sqlx::query("UPDATE guild_settings SET settings = $1 WHERE settings = $2")
+            .bind(sqlx::types::Json(&new))
+            .bind(sqlx::types::Json(&old))
+            .execute(&self.db)
+            .await
+            .map_err(Error::Database)?
+            .rows_affected();
+
+        *self.seed.write().expect("config lock poisoned") = new;
+        // settings are re-read from the database on next use
+        self.cache.clear();
+
+        tracing::info!("Reloaded config.json, updated settings of {} guilds", updated);
+        Ok(updated)
+    }
+
+    /// Gets the settings of a guild, seeding them from `config.json` if the guild has none yet
+    ///
+    /// Guilds other than the main one get the seed without its channels and roles
+    pub async fn get(&self, guild_id: serenity::GuildId) -> Result<FacultyManagerConfig, Error> {
+        if let Some(config) = self.cache.get(&guild_id) {
+            return Ok(config.clone());
+        }
+
+        let stored = sqlx::query_as::<sqlx::Postgres, (sqlx::types::Json<FacultyManagerConfig>,)>(
+            "SELECT settings FROM guild_settings WHERE guild_id = $1",
+        )
+        .bind(guild_id.0 as i64)
+        .fetch_optional(&self.db)
+        .await
+        .map_err(Error::Database)?;
+
+        let config = match stored {
+            Some((sqlx::types::Json(config),)) => config,
+            None => {
+                tracing::info!("Seeding settings for guild {} from config.json", guild_id);
+                let seed = if guild_id == self.main_guild {
+                    self.seed()
+                } else {
+                    self.seed().for_other_guild()
+                };
+                sqlx::query(
+                    "INSERT INTO guild_settings (guild_id, settings) VALUES ($1, $2) ON CONFLICT DO NOTHING",
+                )
+                .bind(guild_id.0 as i64)
+                .bind(sqlx::types::Json(&seed))
+                .execute(&self.db)
+                .await
+                .map_err(Error::Database)?;
+
+                seed
+            }
+        };
+
+        self.cache.insert(guild_id, config.clone());
+        Ok(config)
+    }
+
+    /// Gets the settings of a guild, or the seed when there is no guild (e.g. in DMs)
+    pub async fn resolve(
+        &self,
+        guild_id: Option<serenity::GuildId>,
+    ) -> Result<FacultyManagerConfig, Error> {
+        match guild_id {
+            Some(guild_id) => self.get(guild_id).await,
+            None => Ok(self.seed()),
+        }
+    }
+
+    /// Stores new settings for a guild
+    pub async fn set(
+        &self,
+        guild_id: serenity::GuildId,
+        config: FacultyManagerConfig,
+    ) -> Result<(), Error> {
+        sqlx::query(
+            "INSERT INTO guild_settings (guild_id, settings) VALUES ($1, $2)
+            ON CONFLICT (guild_id) DO UPDATE SET settings = $2",
+        )
+        .bind(guild_id.0 as i64)
+        .bind(sqlx::types::Json(&config))
+        .execute(&self.db)
+        .await
+        .map_err(Error::Database)?;
+
+        self.cache.insert(guild_id, config);
+        Ok(())
+    }
+
+    /// All guilds that have settings stored
+    pub async fn guilds(&self) -> Result<Vec<serenity::GuildId>, Error> {
+        let guilds = sqlx::query_as::<sqlx::Postgres, (i64,)>("SELECT guild_id FROM guild_settings")
+            .fetch_all(&self.db)
+            .await
+            .map_err(Error::Database)?;
+
+        Ok(guilds
+            .into_iter()
+            .map(|(guild_id,)| serenity::GuildId(guild_id as u64))
+            .collect())
+    }
+}
@@ -1,12 +1,16 @@
 use std::sync::Arc;
 
 use crate::{
+    mensaplan,
     prelude::Error,
+    scheduler::{Schedule, Scheduler},
     structs::{self},
-    tasks, utils::{self, generate_verification_code}, Data,
+    tasks, utils::{self, generate_verification_code},
+    verification::{self, CodeCheck},
+    xp, Data,
 };
 
-use poise::serenity_prelude::{self as serenity, AttachmentType, Mentionable};
+use poise::serenity_prelude as serenity;
 use tracing::{debug, info};
 
 
@@ -19,130 +23,148 @@ pub async fn event_listener(
     match event {
         poise::Event::Ready { data_about_bot } => {
             info!("Ready! Logged in as {}", data_about_bot.user.name);
-            info!("Prefix: {:?}", fw.options.prefix_options.prefix.as_ref());
-
-            // start email task
-
-            // start mensa task & rss task if enabled so they can run in parallel
-            if data.config.mealplan.post_mealplan {
-                info!("Mensaplan task started");
-                let context = ctx.clone();
-                let d = data.clone();
-                tokio::spawn(async move {
-                    tasks::post_mensaplan(context, d).await.unwrap();
-                });
+
+            // make sure every guild has settings, new ones get seeded from config.json
+            for guild in &data_about_bot.guilds {
+                let config = data.config.get(guild.id).await?;
+                info!("Prefix for {}: {:?}", guild.id, config.prefix);
             }
 
-            if data.config.rss_settings.post_rss {
-                info!("RSS task started");
-                let context = ctx.clone();
-                let d = data.clone();
-                tokio::spawn(async move {
-                    tasks::post_rss(context, d).await.unwrap();
-                });
+            // plans posted before there were several guilds belong to the only guild there was
+            if let [guild] = data_about_bot.guilds.as_slice() {
+                let adopted = crate::mensaplan::adopt_legacy_plans(&data.db, guild.id).await?;
+                if adopted > 0 {
+                    info!("Assigned {} old mensaplan posts to {}", adopted, guild.id);
+                }
             }
 
-            // start logger task
-            info!("Logger task started");
-            let context = ctx.clone();
-            let fw = fw.shard_manager.clone();
-            let d = data.influx.clone();
-            tokio::spawn(async move {
-                tasks::log_latency_to_influx(&context, fw, &d).await.unwrap();
-            });
+            // the tasks check per guild if posting is enabled and their interval has passed,
+            // tasks that survived a reconnect keep running
+            let shard_manager = fw.shard_manager.clone();
+            let config_modified = Arc::new(std::sync::Mutex::new(tasks::config_modified()));
+            Scheduler::new()
+                .task(
+                    "mensaplan",
+                    Schedule::Every(chrono::Duration::minutes(1)),
+                    tasks::post_mensaplan,
+                )
+                .task(
+                    "rss",
+                    Schedule::Every(chrono::Duration::minutes(1)),
+                    tasks::post_rss,
+                )
+                .task(
+                    "podcast",
+                    Schedule::Every(chrono::Duration::minutes(1)),
+                    tasks::post_podcast,
+                )
+                .task(
+                    "voice_xp",
+                    Schedule::Every(chrono::Duration::minutes(1)),
+                    tasks::voice_xp,
+                )
+                .task(
+                    "mensa_daily_menus",
+                    Schedule::Every(chrono::Duration::minutes(1)),
+                    tasks::mensa_daily_menus,
+                )
+                .task(
+                    "mensa_dish_alerts",
+                    Schedule::Every(chrono::Duration::minutes(15)),
+                    tasks::mensa_dish_alerts,
+                )
+                .task(
+                    "reverification_deadlines",
+                    Schedule::Every(chrono::Duration::hours(1)),
+                    tasks::reverification_deadlines,
+                )
+                .task(
+                    "influx_latency",
+                    Schedule::Every(chrono::Duration::minutes(1)),
+                    move |context, d| {
+                        let shard_manager = shard_manager.clone();
+                        async move {
+                            tasks::log_latency_to_influx(&context, shard_manager, &d.influx).await
+                        }
+                    },
+                )
+                .task(
+                    "config_watcher",
+                    Schedule::Every(chrono::Duration::seconds(10)),
+                    move |context, d| tasks::watch_config(context, d, config_modified.clone()),
+                )
+                .start(ctx.clone(), data.clone());
+        }
+
+        poise::Event::GuildCreate { guild, .. } => {
+            // seed settings for guilds the bot joins while running
+            data.config.get(guild.id).await?;
         }
 
         poise::Event::Message { new_message } => {
+            // xp is only awarded on servers
+            let guild_id = match new_message.guild_id {
+                Some(guild_id) => guild_id,
+                None => return Ok(()),
+            };
+            let config = data.config.get(guild_id).await?;
+
             // skip bots and messages starting with the prefix
-            if new_message.author.bot
-                || new_message
-                    .content
-                    .starts_with(fw.options.prefix_options.prefix.as_deref().unwrap_or_default())
-            {
+            if new_message.author.bot || new_message.content.starts_with(&config.prefix) {
                 return Ok(());
             }
         
-            let user_id = i64::from(new_message.author.id);
-            let content_len = new_message.content.chars().count();
-        
-            let mut pool = data.db.acquire().await.map_err(Error::Database)?;
-        
+            // excluded channels and roles don't count towards the cooldown
+            let roles = new_message
+                .member
+                .as_ref()
+                .map(|member| member.roles.clone())
+                .unwrap_or_default();
+            let multipliers = xp::Multipliers::resolve(
+                ctx,
+                new_message.channel_id,
+                &roles,
+                &config.general,
+            )
+            .await;
+            if multipliers.total() <= 0.0 {
+                return Ok(());
+            }
+
             // fetch user data or create defaults
             let user_data = sqlx::query_as::<_, structs::UserXP>(
                 "SELECT * FROM user_xp WHERE user_id = $1"
             )
-            .bind(user_id)
-            .fetch_optional(&mut pool)
+            .bind(i64::from(new_message.author.id))
+            .fetch_optional(&data.db)
             .await
             .map_err(Error::Database)?
             .unwrap_or_else(|| structs::UserXP {
-                user_id,
+                user_id: i64::from(new_message.author.id),
                 user_xp: 0.0,
                 user_level: 0,
             });
         
             debug!("{}: {}", new_message.author.name, user_data.user_xp);
         
-            // scaling formula: xp = base_xp / (1 + scale_factor * level) (logarithmic)
-            let scaling_factor = data.config.general.xp_scaling_factor;
-            let base_xp = content_len as f64 / data.config.general.chars_for_level as f64;
-            let xp_to_add = base_xp / (1.0 + scaling_factor * (user_data.user_level as f64).ln());
-        
-            // update xp and save to db
-            let new_xp = user_data.user_xp + xp_to_add;
-            sqlx::query(
-                "INSERT INTO user_xp (user_id, user_xp) VALUES ($1, $2)
-                ON CONFLICT (user_id) DO UPDATE SET user_xp = $2"
-            )
-            .bind(user_id)
-            .bind(new_xp)
-            .execute(&mut pool)
-            .await
-            .map_err(Error::Database)?;
-        
-            debug!(
-                "{}: {} -> {} | Level: {}",
-                new_message.author.name,
-                user_data.user_xp,
-                new_xp,
-                user_data.user_level
-            );
-        
-            // determine if level-up occurred
-            let new_level = (new_xp / 100.0).floor() as i32;
-            if new_level > user_data.user_level {
-                // update level and save
-                sqlx::query(
-                    "INSERT INTO user_xp (user_id, user_level) VALUES ($1, $2)
-                    ON CONFLICT (user_id) DO UPDATE SET user_level = $2"
-                )
-                .bind(user_id)
-                .bind(new_level)
-                .execute(&mut pool)
-                .await
-                .map_err(Error::Database)?;
-        
-                // generate level-up message
-                let img = utils::show_levelup_image(&new_message.author, new_level as u16).await?;
-                data.config.channels.xp
-                    .send_message(&ctx, |f| {
-                        f.content(format!(
-                            "congrats {}! you've levelled up to {}!",
-                            new_message.author.mention(),
-                            new_level
-                        ))
-                        .add_file(AttachmentType::Bytes {
-                            data: std::borrow::Cow::Borrowed(&img),
-                            filename: "levelup.png".to_string(),
-                        })
-                    })
-                    .await
-                    .map_err(Error::Serenity)?;
-            }
-        
+            // cooldown and repeated messages are handled by the tracker, the cap per message
+            // applies to the multiplied XP so events and roles can't exceed it
+            let xp_to_add = match data.xp.message_xp(
+                new_message.author.id,
+                &new_message.content,
+                user_data.user_level,
+                &config.general,
+            ) {
+                Some(xp) => (xp * multipliers.total()).min(config.general.max_xp_per_message),
+                None => return Ok(()),
+            };
+
+            xp::award(ctx, data, guild_id, &config, &new_message.author, xp_to_add).await?;
         }
         
         poise::Event::VoiceStateUpdate { old, new } => {
+            let config = data.config.resolve(new.guild_id).await?;
+
             let created_channels = sqlx::query_as::<sqlx::Postgres, structs::VoiceChannels>(
                 "SELECT * FROM voice_channels",
             )
@@ -164,7 +186,7 @@ pub async fn event_listener(
                     .await
                     .map_err(Error::Serenity)?;
                 if let serenity::Channel::Guild(channel) = channel {
-                    if channel.name() == data.config.channels.create_channel {
+                    if channel.name() == config.channels.create_channel {
                         return Ok(()); // don't delete the create channel
                     }
 
@@ -204,7 +226,7 @@ pub async fn event_listener(
                 _ => return Ok(()),
             };
 
-            if &new_channel.name() == &data.config.channels.create_channel {
+            if &new_channel.name() == &config.channels.create_channel {
                 let category = new_channel.parent_id;
 
                 let cc = new
@@ -256,51 +278,12 @@ pub async fn event_listener(
                     "mensaplan_notify_button" => {
                         give_user_mensaplan_role(ctx, button, data).await?
                     },
-                    "reverify" => {
-                        let data = 
-                            poise::execute_modal_on_component_interaction::<ReverificationModal>(
-                                Arc::new(ctx.clone()),
-                                Arc::new(interaction.as_message_component().expect("Button interaction is always a message component").clone()),
-                                None, 
-                                None
-                            ).await.map_err(Error::Serenity)?;
-                        println!("Got data: {:?}", data);
-
-                        // verify that email is a student email
-                        
-                        let modal = data.unwrap_or(ReverificationModal {
-                            email: "".to_string()
-                        });
-
-                        
-                        if !modal.email.ends_with("stud.hs-kempten.de") {
-                            button
-                                .create_followup_message(&ctx, |f| {
-                                    f.flags(serenity::model::application::interaction::MessageFlags::EPHEMERAL)
-                                    .content("Das ist keine gültige Studenten Email, bitte gib eine Studenten Email der Hochschule Kempten an.")
-                                })
-                                .await
-                                .map_err(Error::Serenity)?;
-                            return Ok(());
-                        }
-
-                        // send email to user
-                        let code = generate_verification_code();
-                        let email = modal.email.clone();
-                        let user_id = button.user.id;
-                        let email = utils::CurrentEmail::new(email, user_id, button.user.name.clone(), code.clone());
-                        email.send().await?;
-
-                        // send message to user
-                        button
-                            .create_followup_message(&ctx, |f| {
-                                f.flags(serenity::model::application::interaction::MessageFlags::EPHEMERAL)
-                                .content(format!("Eine Email wurde an {} gesendet, bitte überprüfe deinen Posteingang und folge dann den Anweisungen in der email :)", modal.email))
-                            })
-                            .await
-                            .map_err(Error::Serenity)?;
-
-
+                    "reverify" => reverification_email(ctx, button, data).await?,
+                    "reverify_code" => reverification_code(ctx, button, data).await?,
+                    "mensa_subscribe" => mensa_subscribe(ctx, button, data).await?,
+                    "mensa_unsubscribe" => mensa_unsubscribe(ctx, button, data).await?,
+                    id if id.starts_with("mensa_alert_remove:") => {
+                        mensa_alert_remove(ctx, button, data).await?
                     }
                     _ => not_implemented(ctx, button).await?,
                 }
@@ -320,12 +303,157 @@ pub struct ReverificationModal {
     email: String,
 }
 
+#[derive(poise::Modal, Clone, Debug)]
+#[name = "Enter Your Verification Code"]
+pub struct ReverificationCodeModal {
+    #[name = "The code you received via email"]
+    code: String,
+}
+
+/// Asks for the users email and sends them a reverification code
+async fn reverification_email(
+    ctx: &serenity::Context,
+    button: &serenity::model::application::interaction::message_component::MessageComponentInteraction,
+    data: &Data,
+) -> Result<(), Error> {
+    let modal_data = poise::execute_modal_on_component_interaction::<ReverificationModal>(
+        Arc::new(ctx.clone()),
+        Arc::new(button.clone()),
+        None,
+        None,
+    )
+    .await
+    .map_err(Error::Serenity)?;
+
+    let modal = match modal_data {
+        Some(modal) => modal,
+        None => return Ok(()), // timed out
+    };
+
+    // reverification happens in DMs, so use the settings of the server that asked for it
+    let guild_id = verification::reverification_guild(&data.db, button.user.id).await?;
+    let config = data.config.resolve(guild_id.or(button.guild_id)).await?;
+
+    // verify that email belongs to one of the accepted domains
+    if config.verification_domain(&modal.email).is_none() {
+        button
+            .create_followup_message(&ctx, |f| {
+                f.flags(serenity::model::application::interaction::MessageFlags::EPHEMERAL)
+                .content(format!("Das ist keine gültige Hochschul Email, bitte gib eine Adresse mit einer dieser Endungen an: {}", config.verification_domain_list()))
+            })
+            .await
+            .map_err(Error::Serenity)?;
+        return Ok(());
+    }
+
+    let user_id = button.user.id;
+    if verification::email_pending_for_other_user(&data.db, user_id, &modal.email).await? {
+        button
+            .create_followup_message(&ctx, |f| {
+                f.flags(serenity::model::application::interaction::MessageFlags::EPHEMERAL)
+                .content("An diese Email wurde bereits ein Code für einen anderen Account gesendet.")
+            })
+            .await
+            .map_err(Error::Serenity)?;
+        return Ok(());
+    }
+
+    // send email to user
+    let code = generate_verification_code();
+    verification::create_pending(&data.db, &config.verification, user_id, &modal.email, &code).await?;
+
+    let email = utils::CurrentEmail::new(modal.email.clone(), user_id, button.user.name.clone(), code);
+    if let Err(why) = data.email_task.send(email).await {
+        tracing::error!("Failed to enqueue reverification email: {}", why);
+    }
+
+    // send message to user
+    button
+        .create_followup_message(&ctx, |f| {
+            f.flags(serenity::model::application::interaction::MessageFlags::EPHEMERAL)
+            .content(format!("Eine Email wurde an {} gesendet, bitte überprüfe deinen Posteingang und gib den Code über den \"Enter Code\" Button ein :)", modal.email))
+        })
+        .await
+        .map_err(Error::Serenity)?;
+
+    Ok(())
+}
+
+/// Checks the reverification code and completes the users open reverification requests
+async fn reverification_code(
+    ctx: &serenity::Context,
+    button: &serenity::model::application::interaction::message_component::MessageComponentInteraction,
+    data: &Data,
+) -> Result<(), Error> {
+    let modal_data = poise::execute_modal_on_component_interaction::<ReverificationCodeModal>(
+        Arc::new(ctx.clone()),
+        Arc::new(button.clone()),
+        None,
+        None,
+    )
+    .await
+    .map_err(Error::Serenity)?;
+
+    let modal = match modal_data {
+        Some(modal) => modal,
+        None => return Ok(()), // timed out
+    };
+
+    let user_id = button.user.id;
+    let guild_id = verification::reverification_guild(&data.db, user_id).await?;
+    let config = data.config.resolve(guild_id.or(button.guild_id)).await?;
+
+    let response = match verification::check_code(&data.db, &config.verification, user_id, &modal.code).await? {
+        CodeCheck::Valid(email) => {
+            let guilds = verification::complete_reverification(&data.db, user_id, &email).await?;
+
+            // hand back the role in case it was removed after the deadline
+            for guild_id in guilds {
+                let config = data.config.get(guild_id).await?;
+                let role = config
+                    .verification_domain(&email)
+                    .map(|d| d.role)
+                    .unwrap_or(config.roles.verified);
+                if let Err(why) = ctx
+                    .http
+                    .add_member_role(guild_id.0, user_id.0, role.0, Some("Reverified"))
+                    .await
+                {
+                    tracing::warn!("Could not give {} their role back in {}: {}", user_id, guild_id, why);
+                }
+            }
+
+            verification::remove_pending(&data.db, user_id).await?;
+            "Danke, du wurdest erfolgreich reverifiziert!".to_string()
+        }
+        CodeCheck::Invalid(attempts_left) => format!("Ungültiger Code, du hast noch {} Versuche.", attempts_left),
+        CodeCheck::NotPending => "Bitte fordere zuerst über den \"Reverify\" Button einen Code an.".to_string(),
+        CodeCheck::Expired => "Dein Code ist abgelaufen, bitte fordere einen neuen an.".to_string(),
+        CodeCheck::TooManyAttempts => "Zu viele ungültige Versuche, bitte fordere einen neuen Code an.".to_string(),
+    };
+
+    button
+        .create_followup_message(&ctx, |f| {
+            f.flags(serenity::model::application::interaction::MessageFlags::EPHEMERAL)
+            .content(response)
+        })
+        .await
+        .map_err(Error::Serenity)?;
+
+    Ok(())
+}
+
 async fn give_user_mensaplan_role(
     ctx: &serenity::Context,
     button: &serenity::model::application::interaction::message_component::MessageComponentInteraction,
     bot_data: &Data,
 ) -> Result<(), Error> {
-    let role = bot_data.config.roles.mealplannotify;
+    let role = bot_data
+        .config
+        .resolve(button.guild_id)
+        .await?
+        .roles
+        .mealplannotify;
     let member = match button.member.as_ref() {
         Some(m) => m,
         None => {
@@ -362,6 +490,70 @@ async fn give_user_mensaplan_role(
     Ok(())
 }
 
+/// Replies to a button with a message only the user can see
+async fn ephemeral_reply(
+    ctx: &serenity::Context,
+    button: &serenity::model::application::interaction::message_component::MessageComponentInteraction,
+    content: &str,
+) -> Result<(), Error> {
+    button
+        .create_interaction_response(&ctx, |f| {
+            f.kind(serenity::InteractionResponseType::ChannelMessageWithSource)
+                .interaction_response_data(|f| {
+                    f.flags(serenity::model::application::interaction::MessageFlags::EPHEMERAL)
+                        .content(content)
+                })
+        })
+        .await
+        .map_err(Error::Serenity)?;
+
+    Ok(())
+}
+
+async fn mensa_subscribe(
+    ctx: &serenity::Context,
+    button: &serenity::model::application::interaction::message_component::MessageComponentInteraction,
+    data: &Data,
+) -> Result<(), Error> {
+    let notify_at = chrono::NaiveTime::from_hms_opt(11, 0, 0).unwrap();
+    mensaplan::subscribe(&data.db, button.user.id, button.guild_id, notify_at).await?;
+
+    ephemeral_reply(
+        ctx,
+        button,
+        "You will get the dishes of the day via DM at 11:00, use `/mensa subscribe` to pick another time",
+    )
+    .await
+}
+
+async fn mensa_unsubscribe(
+    ctx: &serenity::Context,
+    button: &serenity::model::application::interaction::message_component::MessageComponentInteraction,
+    data: &Data,
+) -> Result<(), Error> {
+    mensaplan::unsubscribe(&data.db, button.user.id).await?;
+    ephemeral_reply(ctx, button, "You won't get the daily menu anymore").await
+}
+
+async fn mensa_alert_remove(
+    ctx: &serenity::Context,
+    button: &serenity::model::application::interaction::message_component::MessageComponentInteraction,
+    data: &Data,
+) -> Result<(), Error> {
+    let keyword = button
+        .data
+        .custom_id
+        .trim_start_matches("mensa_alert_remove:");
+    mensaplan::remove_keyword(&data.db, button.user.id, keyword).await?;
+
+    ephemeral_reply(
+        ctx,
+        button,
+        &format!("You won't get alerts for {} anymore", keyword),
+    )
+    .await
+}
+
 /// Generic function to handle not implemented buttons
 async fn not_implemented(
     ctx: &serenity::Context,
@@ -0,0 +1,878 @@
+use poise::serenity_prelude as serenity;
+use reqwest::header;
+use serde::Deserialize;
+
+use crate::{html, prelude::Error, structs};
+
+/// At most this many items of a feed are posted per check, e.g. when a feed changed all its guids
+pub const MAX_NEW_ITEMS: usize = 10;
+
+/// Discord rejects embeds with longer texts
+pub const EMBED_TITLE_LIMIT: usize = 256;
+pub const EMBED_DESCRIPTION_LIMIT: usize = 4096;
+pub const EMBED_FIELD_LIMIT: usize = 1024;
+
+/// Message of new items for feeds without their own template
+pub const DEFAULT_TEMPLATE: &str = "Neue Nachricht im Planungsportal · {title}";
+
+/// A feed in any of the supported formats (RSS 2.0, Atom and JSON Feed)
+#[derive(Debug, Clone)]
+pub struct Feed {
+    pub title: String,
+    pub items: Vec<FeedItem>,
+}
+
+/// An item of a feed, normalised from the different formats
+#[derive(Debug, Clone)]
+pub struct FeedItem {
+    pub id: Option<String>,
+    pub title: Option<String>,
+    pub link: Option<String>,
+    pub summary: Option<String>,
+    pub published: Option<chrono::DateTime<chrono::FixedOffset>>,
+    pub updated: Option<chrono::DateTime<chrono::FixedOffset>>,
+    pub author: Option<String>,
+    pub enclosure: Option<Enclosure>,
+    /// Preview image, e.g. of a video
+    pub thumbnail: Option<String>,
+    /// Length of an episode or video, as `H:MM:SS`
+    pub duration: Option<String>,
+}
+
+/// A file attached to an item, e.g. the audio of a podcast episode
+#[derive(Debug, Clone)]
+pub struct Enclosure {
+    pub url: String,
+    pub mime_type: Option<String>,
+}
+
+impl FeedItem {
+    /// Identifies an item across checks, its id or else its link or title
+    pub fn key(&self) -> Option<&str> {
+        self.id
+            .as_deref()
+            .or(self.link.as_deref())
+            .or(self.title.as_deref())
+    }
+
+    /// When the item was published, items are posted again when this changes
+    pub fn date(&self) -> Option<chrono::DateTime<chrono::FixedOffset>> {
+        self.published.or(self.updated)
+    }
+}
+
+/// Formats a duration given in seconds as `H:MM:SS`, other formats like `12:34` are kept
+fn format_duration(raw: &str) -> String {
+    match raw.trim().parse::<f64>() {
+        Ok(seconds) => {
+            let seconds = seconds.round() as u64;
+            if seconds >= 3600 {
+                format!("{}:{:02}:{:02}", seconds / 3600, seconds / 60 % 60, seconds % 60)
+            } else {
+                format!("{}:{:02}", seconds / 60, seconds % 60)
+            }
+        }
+        Err(_) => raw.trim().to_string(),
+    }
+}
+
+/// The `media:*` extension elements (Media RSS) of an item, YouTube nests them in `media:group`
+macro_rules! media_element {
+    ($extensions:expr, $name:expr) => {
+        $extensions.get("media").and_then(|media| {
+            media.get($name).and_then(|e| e.first()).or_else(|| {
+                media
+                    .get("group")
+                    .and_then(|g| g.first())
+                    .and_then(|group| group.children().get($name))
+                    .and_then(|e| e.first())
+            })
+        })
+    };
+}
+
+impl Feed {
+    /// Parses a feed, JSON Feeds are recognised by their leading `{`, XML feeds are tried as
+    /// RSS 2.0 first and as Atom second
+    pub fn parse(bytes: &[u8]) -> Result<Self, Error> {
+        let start = bytes.iter().position(|b| !b.is_ascii_whitespace());
+        if start.is_some_and(|start| bytes[start] == b'{') {
+            let feed = serde_json::from_slice::<JsonFeed>(bytes).map_err(Error::Serde)?;
+            return Ok(feed.into());
+        }
+
+        match rss::Channel::read_from(bytes) {
+            Ok(channel) => Ok(channel.into()),
+            Err(rss_error) => match atom_syndication::Feed::read_from(bytes) {
+                Ok(feed) => Ok(feed.into()),
+                // most feeds are rss, so its error is the more helpful one
+                Err(_) => Err(Error::Rss(rss_error)),
+            },
+        }
+    }
+}
+
+impl From<rss::Channel> for Feed {
+    fn from(channel: rss::Channel) -> Self {
+        let items = channel
+            .items()
+            .iter()
+            .map(|item| FeedItem {
+                id: item.guid().map(|guid| guid.value().to_string()),
+                title: item.title().map(str::to_string),
+                link: item.link().map(str::to_string),
+                summary: item.description().map(str::to_string),
+                published: item
+                    .pub_date()
+                    .and_then(|date| chrono::DateTime::parse_from_rfc2822(date).ok()),
+                updated: None,
+                author: item
+                    .author()
+                    .or_else(|| {
+                        item.dublin_core_ext()
+                            .and_then(|dc| dc.creators().first())
+                            .map(|c| c.as_str())
+                    })
+                    .map(str::to_string),
+                enclosure: item.enclosure().map(|enclosure| Enclosure {
+                    url: enclosure.url().to_string(),
+                    mime_type: Some(enclosure.mime_type().to_string()),
+                }),
+                thumbnail: media_element!(item.extensions(), "thumbnail")
+                    .and_then(|thumbnail| thumbnail.attrs().get("url").cloned())
+                    .or_else(|| {
+                        item.itunes_ext()
+                            .and_then(|itunes| itunes.image())
+                            .map(str::to_string)
+                    }),
+                duration: item
+                    .itunes_ext()
+                    .and_then(|itunes| itunes.duration())
+                    .map(str::to_string)
+                    .or_else(|| {
+                        media_element!(item.extensions(), "content")
+                            .and_then(|content| content.attrs().get("duration").cloned())
+                    })
+                    .map(|duration| format_duration(&duration)),
+            })
+            .collect();
+
+        Self {
+            title: channel.title().to_string(),
+            items,
+        }
+    }
+}
+
+impl From<atom_syndication::Feed> for Feed {
+    fn from(feed: atom_syndication::Feed) -> Self {
+        let items = feed
+            .entries()
+            .iter()
+            .map(|entry| {
+                let link = entry
+                    .links()
+                    .iter()
+                    .find(|link| link.rel() == "alternate")
+                    .or_else(|| entry.links().first())
+                    .map(|link| link.href().to_string());
+                let enclosure = entry
+                    .links()
+                    .iter()
+                    .find(|link| link.rel() == "enclosure")
+                    .map(|link| Enclosure {
+                        url: link.href().to_string(),
+                        mime_type: link.mime_type().map(str::to_string),
+                    });
+
+                let media_description = media_element!(entry.extensions(), "description")
+                    .and_then(|description| description.value());
+
+                FeedItem {
+                    id: Some(entry.id().to_string()),
+                    title: Some(entry.title().as_str().to_string()),
+                    link,
+                    summary: entry
+                        .summary()
+                        .map(|summary| summary.as_str())
+                        .or_else(|| entry.content().and_then(|content| content.value()))
+                        .or(media_description)
+                        .map(str::to_string),
+                    published: entry.published().copied(),
+                    updated: Some(*entry.updated()),
+                    author: entry.authors().first().map(|a| a.name().to_string()),
+                    enclosure,
+                    thumbnail: media_element!(entry.extensions(), "thumbnail")
+                        .and_then(|thumbnail| thumbnail.attrs().get("url").cloned()),
+                    duration: media_element!(entry.extensions(), "content")
+                        .and_then(|content| content.attrs().get("duration"))
+                        .map(|duration| format_duration(duration)),
+                }
+            })
+            .collect();
+
+        Self {
+            title: feed.title().as_str().to_string(),
+            items,
+        }
+    }
+}
+
+/// A JSON Feed (https://www.jsonfeed.org/version/1.1/), only the fields that are posted
+#[derive(Debug, Deserialize)]
+struct JsonFeed {
+    #[serde(default)]
+    title: String,
+    #[serde(default)]
+    items: Vec<JsonFeedItem>,
+}
+
+#[derive(Debug, Deserialize)]
+struct JsonFeedItem {
+    /// a string, but some feeds use numbers
+    id: Option<serde_json::Value>,
+    url: Option<String>,
+    title: Option<String>,
+    summary: Option<String>,
+    content_text: Option<String>,
+    content_html: Option<String>,
+    date_published: Option<chrono::DateTime<chrono::FixedOffset>>,
+    date_modified: Option<chrono::DateTime<chrono::FixedOffset>>,
+    #[serde(default)]
+    authors: Vec<JsonFeedAuthor>,
+    /// version 1.0 only had a single author
+    author: Option<JsonFeedAuthor>,
+    #[serde(default)]
+    attachments: Vec<JsonFeedAttachment>,
+    image: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct JsonFeedAuthor {
+    name: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct JsonFeedAttachment {
+    url: String,
+    mime_type: Option<String>,
+    duration_in_seconds: Option<f64>,
+}
+
+impl From<JsonFeed> for Feed {
+    fn from(feed: JsonFeed) -> Self {
+        let items = feed
+            .items
+            .into_iter()
+            .map(|item| FeedItem {
+                id: item.id.map(|id| match id {
+                    serde_json::Value::String(id) => id,
+                    id => id.to_string(),
+                }),
+                title: item.title,
+                link: item.url,
+                summary: item.summary.or(item.content_html).or(item.content_text),
+                published: item.date_published,
+                updated: item.date_modified,
+                author: item
+                    .authors
+                    .into_iter()
+                    .chain(item.author)
+                    .find_map(|author| author.name),
+                duration: item
+                    .attachments
+                    .iter()
+                    .find_map(|a| a.duration_in_seconds)
+                    .map(|seconds| format_duration(&seconds.to_string())),
+                enclosure: item.attachments.into_iter().next().map(|a| Enclosure {
+                    url: a.url,
+                    mime_type: a.mime_type,
+                }),
+                thumbnail: item.image,
+            })
+            .collect();
+
+        Self {
+            title: feed.title,
+            items,
+        }
+    }
+}
+
+/// A downloaded feed with the validators for the next conditional request
+pub struct FetchedFeed {
+    pub feed: Feed,
+    pub etag: Option<String>,
+    pub last_modified: Option<String>,
+}
+
+/// Downloads a feed, `None` if it did not change since the ETag/Last-Modified of `state`
+pub async fn fetch(
+    url: &str,
+    state: Option<&structs::RssFeed>,
+) -> Result<Option<FetchedFeed>, Error> {
+    let mut request = reqwest::Client::new().get(url);
+    if let Some(state) = state {
+        if let Some(etag) = &state.etag {
+            request = request.header(header::IF_NONE_MATCH, etag);
+        }
+        if let Some(last_modified) = &state.last_modified {
+            request = request.header(header::IF_MODIFIED_SINCE, last_modified);
+        }
+    }
+
+    let response = request.send().await.map_err(Error::NetRequest)?;
+    if response.status() == reqwest::StatusCode::NOT_MODIFIED {
+        return Ok(None);
+    }
+    let response = response.error_for_status().map_err(Error::NetRequest)?;
+
+    let validator = |name: header::HeaderName| {
+        response
+            .headers()
+            .get(name)
+            .and_then(|v| v.to_str().ok())
+            .map(str::to_string)
+    };
+    let etag = validator(header::ETAG);
+    let last_modified = validator(header::LAST_MODIFIED);
+
+    let bytes = response.bytes().await.map_err(Error::NetRequest)?;
+    let feed = Feed::parse(&bytes)?;
+
+    Ok(Some(FetchedFeed {
+        feed,
+        etag,
+        last_modified,
+    }))
+}
+
+/// The items of a feed that were not seen before, oldest first
+///
+/// Items with a date are new when they are newer than the last seen one, items without a date when
+/// they are listed before the last seen id
+pub fn new_items<'a>(items: &'a [FeedItem], state: &structs::RssFeed) -> Vec<&'a FeedItem> {
+    let last_seen = state
+        .last_guid
+        .as_deref()
+        .and_then(|id| items.iter().position(|i| i.key() == Some(id)));
+
+    let mut new = items
+        .iter()
+        .enumerate()
+        .filter(|(index, item)| match (item.date(), state.last_pub_date) {
+            (Some(date), Some(last)) => date > last,
+            _ => last_seen.is_none_or(|last| *index < last),
+        })
+        .map(|(_, item)| item)
+        .collect::<Vec<_>>();
+    // feeds list their newest items first, items without a date keep that order
+    new.reverse();
+    new.sort_by_key(|item| item.date());
+
+    let skipped = new.len().saturating_sub(MAX_NEW_ITEMS);
+    new.split_off(skipped)
+}
+
+/// Fills in the `{title}`, `{link}` and `{feed}` placeholders of a message template
+pub fn render_template(template: &str, feed_title: &str, title: &str, link: &str) -> String {
+    template
+        .replace("{title}", title)
+        .replace("{link}", link)
+        .replace("{feed}", feed_title)
+}
+
+/// The message content of a new item, mentioning the feed's role
+pub fn item_content(feed: &structs::RssFeed, feed_title: &str, title: &str, link: &str) -> String {
+    let content = render_template(
+        feed.template.as_deref().unwrap_or(DEFAULT_TEMPLATE),
+        feed_title,
+        title,
+        link,
+    );
+
+    match feed.mention_role {
+        Some(role) => format!("<@&{}> {}", role, content),
+        None => content,
+    }
+}
+
+/// Removes the planning portal's escaped line break markers from descriptions
+pub fn clean_regex() -> Result<regex::Regex, Error> {
+    regex::Regex::new(r"\\n(if wk med|all)").map_err(Error::Regex)
+}
+
+/// Creates the embed of an item, its HTML description is converted to markdown and shortened to
+/// Discord's limits, linked PDFs and other files are listed in an extra field
+pub fn create_embed<'a>(
+    e: &'a mut serenity::CreateEmbed,
+    clean_regex: &regex::Regex,
+    item: &FeedItem,
+    date: &chrono::DateTime<chrono::Local>,
+) -> &'a mut serenity::CreateEmbed {
+    let summary = clean_regex.replace_all(item.summary.as_deref().unwrap_or_default(), "");
+    let mut converted = html::to_markdown(&summary, item.link.as_deref());
+
+    e.title(html::truncate(
+        item.title.as_deref().unwrap_or_default(),
+        EMBED_TITLE_LIMIT,
+        None,
+    ))
+    .description(html::truncate(
+        &converted.markdown,
+        EMBED_DESCRIPTION_LIMIT,
+        item.link.as_deref(),
+    ))
+    .timestamp(date.to_rfc3339())
+    .color(0xb00b69);
+    if let Some(link) = &item.link {
+        e.url(link);
+    }
+    if let Some(author) = &item.author {
+        e.author(|a| a.name(html::truncate(author, EMBED_TITLE_LIMIT, None)));
+    }
+
+    // the enclosure is shown as image if it is one, otherwise the first image of the description
+    let mut images = converted.images.into_iter();
+    match &item.enclosure {
+        Some(enclosure)
+            if enclosure
+                .mime_type
+                .as_deref()
+                .is_some_and(|mime| mime.starts_with("image/")) =>
+        {
+            e.image(&enclosure.url);
+        }
+        enclosure => {
+            if let Some(enclosure) = enclosure {
+                converted.files.insert(
+                    0,
+                    html::Attachment {
+                        name: html::file_name(&enclosure.url),
+                        url: enclosure.url.clone(),
+                    },
+                );
+            }
+            if let Some(image) = images.next() {
+                e.image(image);
+            }
+        }
+    }
+    // further images are listed with the files
+    converted
+        .files
+        .extend(images.map(|url| html::Attachment {
+            name: html::file_name(&url),
+            url,
+        }));
+
+    if let Some(field) = attachment_field(&converted.files) {
+        e.field("Anhänge", field, false);
+    }
+    e
+}
+
+/// Lists files as links, as many as fit into an embed field
+fn attachment_field(files: &[html::Attachment]) -> Option<String> {
+    let mut field = String::new();
+    for (i, file) in files.iter().enumerate() {
+        let line = format!("📎 [{}]({})\n", file.name.replace(['[', ']'], ""), file.url);
+        let more = format!("… und {} weitere", files.len() - i);
+        if field.chars().count() + line.chars().count() + more.chars().count() > EMBED_FIELD_LIMIT {
+            field.push_str(&more);
+            break;
+        }
+        field.push_str(&line);
+    }
+    let field = field.trim_end().to_string();
+    if field.is_empty() {
+        None
+    } else {
+        Some(field)
+    }
+}
+
+pub async fn load(
+    db: &sqlx::PgPool,
+    channel_id: serenity::ChannelId,
+    url: &str,
+) -> Result<Option<structs::RssFeed>, Error> {
+    sqlx::query_as::<sqlx::Postgres, structs::RssFeed>(
+        "SELECT * FROM rss_feeds WHERE channel_id = $1 AND url = $2",
+    )
+    .bind(channel_id.0 as i64)
+    .bind(url)
+    .fetch_optional(db)
+    .await
+    .map_err(Error::Database)
+}
+
+/// The feeds subscribed on a server
+pub async fn guild_feeds(
+    db: &sqlx::PgPool,
+    guild_id: serenity::GuildId,
+) -> Result<Vec<structs::RssFeed>, Error> {
+    sqlx::query_as::<sqlx::Postgres, structs::RssFeed>(
+        "SELECT * FROM rss_feeds WHERE guild_id = $1 ORDER BY channel_id, url",
+    )
+    .bind(guild_id.0 as i64)
+    .fetch_all(db)
+    .await
+    .map_err(Error::Database)
+}
+
+/// Subscribes a channel to a feed, starting after its current items, or changes the template
+/// and role of an existing subscription. Returns whether the subscription is new
+///
+/// `None` keeps the template or role of an existing subscription, `Some(None)` removes it.
+pub async fn subscribe(
+    db: &sqlx::PgPool,
+    guild_id: serenity::GuildId,
+    channel_id: serenity::ChannelId,
+    url: &str,
+    template: Option<Option<&str>>,
+    mention_role: Option<Option<serenity::RoleId>>,
+    feed: &FetchedFeed,
+) -> Result<bool, Error> {
+    let existing = load(db, channel_id, url).await?;
+    if existing.is_none() {
+        store(db, guild_id, channel_id, url, None, feed).await?;
+    }
+
+    sqlx::query(
+        "UPDATE rss_feeds SET guild_id = $1,
+        template = CASE WHEN $6 THEN $2 ELSE template END,
+        mention_role = CASE WHEN $7 THEN $3 ELSE mention_role END
+        WHERE channel_id = $4 AND url = $5",
+    )
+    .bind(guild_id.0 as i64)
+    .bind(template.flatten())
+    .bind(mention_role.flatten().map(|r| r.0 as i64))
+    .bind(channel_id.0 as i64)
+    .bind(url)
+    .bind(template.is_some())
+    .bind(mention_role.is_some())
+    .execute(db)
+    .await
+    .map_err(Error::Database)?;
+
+    Ok(existing.is_none())
+}
+
+/// Removes the subscriptions of a feed on a server, in a single channel or all of them
+pub async fn unsubscribe(
+    db: &sqlx::PgPool,
+    guild_id: serenity::GuildId,
+    channel_id: Option<serenity::ChannelId>,
+    url: &str,
+) -> Result<u64, Error> {
+    let result = sqlx::query(
+        "DELETE FROM rss_feeds WHERE guild_id = $1 AND url = $2 AND ($3::BIGINT IS NULL OR channel_id = $3)",
+    )
+    .bind(guild_id.0 as i64)
+    .bind(url)
+    .bind(channel_id.map(|c| c.0 as i64))
+    .execute(db)
+    .await
+    .map_err(Error::Database)?;
+
+    Ok(result.rows_affected())
+}
+
+/// Remembers the newest item of a fetched feed, the items up to it won't be posted again
+pub async fn store(
+    db: &sqlx::PgPool,
+    guild_id: serenity::GuildId,
+    channel_id: serenity::ChannelId,
+    url: &str,
+    previous: Option<&structs::RssFeed>,
+    feed: &FetchedFeed,
+) -> Result<(), Error> {
+    let items = &feed.feed.items;
+    let newest = items
+        .iter()
+        .filter_map(|item| item.date().map(|date| (date, item)))
+        .max_by_key(|(date, _)| *date);
+
+    let last_pub_date = newest
+        .map(|(date, _)| date.with_timezone(&chrono::Utc))
+        .into_iter()
+        .chain(previous.and_then(|p| p.last_pub_date))
+        .max();
+    // feeds list their newest item first
+    let last_guid = newest
+        .map(|(_, item)| item)
+        .or_else(|| items.first())
+        .and_then(|item| item.key())
+        .map(str::to_string)
+        .or_else(|| previous.and_then(|p| p.last_guid.clone()));
+
+    sqlx::query(
+        "INSERT INTO rss_feeds (channel_id, url, last_guid, last_pub_date, etag, last_modified, checked_at, guild_id)
+        VALUES ($1, $2, $3, $4, $5, $6, CURRENT_TIMESTAMP, $7)
+        ON CONFLICT (channel_id, url) DO UPDATE SET last_guid = $3, last_pub_date = $4, etag = $5,
+        last_modified = $6, checked_at = CURRENT_TIMESTAMP, guild_id = COALESCE(rss_feeds.guild_id, $7)",
+    )
+    .bind(channel_id.0 as i64)
+    .bind(url)
+    .bind(last_guid)
+    .bind(last_pub_date)
+    .bind(&feed.etag)
+    .bind(&feed.last_modified)
+    .bind(guild_id.0 as i64)
+    .execute(db)
+    .await
+    .map_err(Error::Database)?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn state(
+        last_guid: Option<&str>,
+        last_pub_date: Option<chrono::DateTime<chrono::Utc>>,
+    ) -> structs::RssFeed {
+        structs::RssFeed {
+            channel_id: 1,
+            url: "https://example.com/feed".to_string(),
+            last_guid: last_guid.map(str::to_string),
+            last_pub_date,
+            etag: None,
+            last_modified: None,
+            checked_at: None,
+            guild_id: None,
+            template: None,
+            mention_role: None,
+        }
+    }
+
+    fn item(id: &str, published: Option<&str>) -> FeedItem {
+        FeedItem {
+            id: Some(id.to_string()),
+            title: None,
+            link: None,
+            summary: None,
+            published: published.map(|date| chrono::DateTime::parse_from_rfc3339(date).unwrap()),
+            updated: None,
+            author: None,
+            enclosure: None,
+            thumbnail: None,
+            duration: None,
+        }
+    }
+
+    fn ids(items: Vec<&FeedItem>) -> Vec<&str> {
+        items.into_iter().filter_map(FeedItem::key).collect()
+    }
+
+    const RSS: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<rss version="2.0" xmlns:itunes="http://www.itunes.com/dtds/podcast-1.0.dtd"
+    xmlns:dc="http://purl.org/dc/elements/1.1/">
+<channel>
+    <title>Planungsportal</title>
+    <link>https://example.com</link>
+    <description>Neuigkeiten</description>
+    <item>
+        <title>Folge 12</title>
+        <link>https://example.com/folge-12</link>
+        <guid isPermaLink="false">folge-12</guid>
+        <description>&lt;p&gt;Neue &lt;b&gt;Folge&lt;/b&gt;&lt;/p&gt;</description>
+        <pubDate>Tue, 14 Jan 2025 09:30:00 +0100</pubDate>
+        <dc:creator>Fachschaft</dc:creator>
+        <enclosure url="https://example.com/folge-12.mp3" length="1234" type="audio/mpeg"/>
+        <itunes:duration>3725</itunes:duration>
+        <itunes:image href="https://example.com/folge-12.jpg"/>
+    </item>
+    <item>
+        <title>Ohne Guid</title>
+        <link>https://example.com/ohne-guid</link>
+    </item>
+</channel>
+</rss>"#;
+
+    const ATOM: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<feed xmlns="http://www.w3.org/2005/Atom">
+    <id>urn:example:feed</id>
+    <title>Hochschule</title>
+    <updated>2025-01-14T10:00:00+01:00</updated>
+    <entry>
+        <id>urn:example:1</id>
+        <title>Prüfungsplan veröffentlicht</title>
+        <link rel="enclosure" href="https://example.com/plan.pdf" type="application/pdf"/>
+        <link rel="alternate" href="https://example.com/pruefungsplan"/>
+        <summary>Der Plan ist online</summary>
+        <updated>2025-01-14T10:00:00+01:00</updated>
+        <author><name>Prüfungsamt</name></author>
+    </entry>
+</feed>"#;
+
+    /// The shape of a YouTube channel feed
+    const YOUTUBE: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<feed xmlns:yt="http://www.youtube.com/xml/schemas/2015" xmlns:media="http://search.yahoo.com/mrss/" xmlns="http://www.w3.org/2005/Atom">
+    <id>yt:channel:UC123</id>
+    <title>Hochschule Kempten</title>
+    <updated>2025-01-10T12:00:00+00:00</updated>
+    <entry>
+        <id>yt:video:abc123</id>
+        <yt:videoId>abc123</yt:videoId>
+        <title>Campus Tour</title>
+        <link rel="alternate" href="https://www.youtube.com/watch?v=abc123"/>
+        <author><name>Hochschule Kempten</name></author>
+        <published>2025-01-10T12:00:00+00:00</published>
+        <updated>2025-01-11T08:00:00+00:00</updated>
+        <media:group>
+            <media:title>Campus Tour</media:title>
+            <media:content url="https://www.youtube.com/v/abc123" type="application/x-shockwave-flash" width="640" height="390"/>
+            <media:thumbnail url="https://i1.ytimg.com/vi/abc123/hqdefault.jpg" width="480" height="360"/>
+            <media:description>Ein Rundgang über den Campus</media:description>
+        </media:group>
+    </entry>
+</feed>"#;
+
+    const JSON: &str = r#"
+    {
+        "version": "https://jsonfeed.org/version/1.1",
+        "title": "Podcast",
+        "items": [
+            {
+                "id": 42,
+                "url": "https://example.com/42",
+                "title": "Folge 42",
+                "content_html": "<p>Shownotes</p>",
+                "date_published": "2025-01-14T09:00:00+01:00",
+                "author": { "name": "Studio" },
+                "image": "https://example.com/42.jpg",
+                "attachments": [
+                    { "url": "https://example.com/42.mp3", "mime_type": "audio/mpeg", "duration_in_seconds": 95.4 }
+                ]
+            }
+        ]
+    }"#;
+
+    #[test]
+    fn parse_rss() {
+        let feed = Feed::parse(RSS.as_bytes()).unwrap();
+        assert_eq!(feed.title, "Planungsportal");
+        assert_eq!(feed.items.len(), 2);
+
+        let item = &feed.items[0];
+        assert_eq!(item.key(), Some("folge-12"));
+        assert_eq!(item.summary.as_deref(), Some("<p>Neue <b>Folge</b></p>"));
+        assert_eq!(
+            item.date(),
+            chrono::DateTime::parse_from_rfc3339("2025-01-14T09:30:00+01:00").ok()
+        );
+        assert_eq!(item.author.as_deref(), Some("Fachschaft"));
+        let enclosure = item.enclosure.as_ref().unwrap();
+        assert_eq!(enclosure.url, "https://example.com/folge-12.mp3");
+        assert_eq!(enclosure.mime_type.as_deref(), Some("audio/mpeg"));
+        assert_eq!(item.thumbnail.as_deref(), Some("https://example.com/folge-12.jpg"));
+        assert_eq!(item.duration.as_deref(), Some("1:02:05"));
+
+        // items without a guid are told apart by their link
+        assert_eq!(feed.items[1].key(), Some("https://example.com/ohne-guid"));
+        assert_eq!(feed.items[1].date(), None);
+    }
+
+    #[test]
+    fn parse_atom() {
+        let feed = Feed::parse(ATOM.as_bytes()).unwrap();
+        assert_eq!(feed.title, "Hochschule");
+
+        let item = &feed.items[0];
+        assert_eq!(item.key(), Some("urn:example:1"));
+        assert_eq!(item.link.as_deref(), Some("https://example.com/pruefungsplan"));
+        assert_eq!(item.summary.as_deref(), Some("Der Plan ist online"));
+        assert_eq!(item.author.as_deref(), Some("Prüfungsamt"));
+        assert_eq!(
+            item.enclosure.as_ref().map(|e| e.url.as_str()),
+            Some("https://example.com/plan.pdf")
+        );
+        // without a published date the update counts
+        assert_eq!(item.date(), item.updated);
+        assert!(item.date().is_some());
+    }
+
+    #[test]
+    fn parse_youtube() {
+        let feed = Feed::parse(YOUTUBE.as_bytes()).unwrap();
+
+        let item = &feed.items[0];
+        assert_eq!(item.key(), Some("yt:video:abc123"));
+        assert_eq!(item.link.as_deref(), Some("https://www.youtube.com/watch?v=abc123"));
+        assert_eq!(
+            item.thumbnail.as_deref(),
+            Some("https://i1.ytimg.com/vi/abc123/hqdefault.jpg")
+        );
+        assert_eq!(item.summary.as_deref(), Some("Ein Rundgang über den Campus"));
+        assert_eq!(
+            item.date(),
+            chrono::DateTime::parse_from_rfc3339("2025-01-10T12:00:00+00:00").ok()
+        );
+    }
+
+    #[test]
+    fn parse_json_feed() {
+        let feed = Feed::parse(JSON.as_bytes()).unwrap();
+        assert_eq!(feed.title, "Podcast");
+
+        let item = &feed.items[0];
+        assert_eq!(item.key(), Some("42"));
+        assert_eq!(item.summary.as_deref(), Some("<p>Shownotes</p>"));
+        assert_eq!(item.author.as_deref(), Some("Studio"));
+        assert_eq!(item.thumbnail.as_deref(), Some("https://example.com/42.jpg"));
+        assert_eq!(item.duration.as_deref(), Some("1:35"));
+        assert_eq!(
+            item.enclosure.as_ref().map(|e| e.url.as_str()),
+            Some("https://example.com/42.mp3")
+        );
+    }
+
+    #[test]
+    fn parse_invalid() {
+        assert!(Feed::parse(b"<html><body>Not found</body></html>").is_err());
+        assert!(Feed::parse(b"  { \"items\": 3 }").is_err());
+    }
+
+    #[test]
+    fn new_items_by_date() {
+        let items = vec![
+            item("c", Some("2025-01-14T12:00:00+01:00")),
+            item("b", Some("2025-01-13T12:00:00+01:00")),
+            item("a", Some("2025-01-12T12:00:00+01:00")),
+        ];
+        let last = chrono::DateTime::parse_from_rfc3339("2025-01-12T12:00:00+01:00")
+            .unwrap()
+            .with_timezone(&chrono::Utc);
+
+        // oldest first, the last seen guid doesn't matter when there are dates
+        assert_eq!(ids(new_items(&items, &state(Some("c"), Some(last)))), vec!["b", "c"]);
+        assert!(new_items(&items, &state(None, Some(items[0].date().unwrap().into()))).is_empty());
+    }
+
+    #[test]
+    fn new_items_without_dates() {
+        let items = vec![item("c", None), item("b", None), item("a", None)];
+
+        assert_eq!(ids(new_items(&items, &state(Some("b"), None))), vec!["c"]);
+        assert!(new_items(&items, &state(Some("c"), None)).is_empty());
+        // the first check posts everything
+        assert_eq!(ids(new_items(&items, &state(None, None))), vec!["a", "b", "c"]);
+    }
+
+    #[test]
+    fn new_items_when_last_guid_is_gone() {
+        // e.g. the feed changed all its guids, only the newest items are posted again
+        let items = (0..MAX_NEW_ITEMS + 5)
+            .rev()
+            .map(|i| item(&i.to_string(), None))
+            .collect::<Vec<_>>();
+
+        let new = ids(new_items(&items, &state(Some("gone"), None)));
+        let expected = (5..MAX_NEW_ITEMS + 5).map(|i| i.to_string()).collect::<Vec<_>>();
+        assert_eq!(new, expected);
+    }
+}
@@ -0,0 +1,576 @@
+use scraper::{node::Node, ElementRef, Html};
+
+/// Nested list items are indented with em spaces, plain spaces at the start of lines are trimmed
+const INDENT: &str = "\u{2003}";
+
+/// A file linked or embedded in a description
+#[derive(Debug, Clone, PartialEq)]
+pub struct Attachment {
+    pub name: String,
+    pub url: String,
+}
+
+/// A description converted to Discord markdown, with the files it links to
+#[derive(Debug, Clone, Default)]
+pub struct Converted {
+    pub markdown: String,
+    /// Embedded images, in the order they appear
+    pub images: Vec<String>,
+    /// Linked PDFs
+    pub files: Vec<Attachment>,
+}
+
+/// Converts the HTML of a feed item to Discord markdown
+///
+/// Links are kept as masked links, lists as bullet points, headings as bold lines and tables
+/// as code blocks. Relative links are resolved against `base`, usually the link of the item.
+/// Descriptions without any tags are returned as they are.
+pub fn to_markdown(html: &str, base: Option<&str>) -> Converted {
+    if !html.contains('<') {
+        return Converted {
+            markdown: html.trim().to_string(),
+            ..Default::default()
+        };
+    }
+
+    let mut converter = Converter {
+        base: base.and_then(|base| reqwest::Url::parse(base).ok()),
+        converted: Converted::default(),
+    };
+    let fragment = Html::parse_fragment(html);
+    let markdown = converter.children(fragment.root_element());
+    converter.converted.markdown = normalize(&markdown);
+    converter.converted
+}
+
+/// Shortens text to at most `limit` characters, ending with a link to the full text
+///
+/// The text is cut at a line break or space, before a masked link that doesn't fit, and open
+/// code blocks are closed. The link is left out when `limit` is too small for it.
+pub fn truncate(text: &str, limit: usize, read_more: Option<&str>) -> String {
+    if text.chars().count() <= limit {
+        return text.to_string();
+    }
+
+    // the link is left out when it doesn't leave room for any text
+    let suffix = match read_more.map(|link| format!("…\n\n[Weiterlesen]({})", link)) {
+        Some(suffix) if suffix.chars().count() + 5 < limit => suffix,
+        _ => "…".to_string(),
+    };
+    // room for the suffix and closing an open code block
+    let budget = limit.saturating_sub(suffix.chars().count() + 5);
+    if budget == 0 {
+        return text.chars().take(limit).collect();
+    }
+    let mut cut = text.chars().take(budget).collect::<String>();
+
+    // prefer the end of a line or word, unless that throws away most of the text
+    let boundary = cut
+        .rfind('\n')
+        .filter(|i| *i > cut.len() * 3 / 4)
+        .or_else(|| cut.rfind(' ').filter(|i| *i > cut.len() / 2));
+    if let Some(boundary) = boundary {
+        cut.truncate(boundary);
+    }
+    let mut cut = cut.trim_end().to_string();
+
+    // a masked link that was cut in half would show its markdown, it is left out instead
+    if let Some(open) = cut.rfind('[') {
+        let rest = &cut[open..];
+        let unclosed = match rest.find("](") {
+            Some(target) => !rest[target..].contains(')'),
+            None => !rest.contains(']'),
+        };
+        if unclosed && cut.matches("```").count() % 2 == 0 {
+            cut.truncate(open);
+            cut = cut.trim_end().to_string();
+        }
+    }
+
+    if cut.matches("```").count() % 2 == 1 {
+        cut.push_str("\n```\n");
+    }
+    cut.push_str(&suffix);
+    cut
+}
+
+struct Converter {
+    base: Option<reqwest::Url>,
+    converted: Converted,
+}
+
+impl Converter {
+    fn children(&mut self, element: ElementRef) -> String {
+        let mut out = String::new();
+        for child in element.children() {
+            match child.value() {
+                Node::Text(text) => out.push_str(&escape(&collapse_whitespace(text))),
+                Node::Element(_) => {
+                    if let Some(child) = ElementRef::wrap(child) {
+                        out.push_str(&self.element(child));
+                    }
+                }
+                _ => {}
+            }
+        }
+        out
+    }
+
+    fn element(&mut self, element: ElementRef) -> String {
+        match element.value().name() {
+            "script" | "style" | "head" | "title" => String::new(),
+            "br" => "\n".to_string(),
+            "hr" => "\n\n".to_string(),
+            "p" | "div" | "section" | "article" | "header" | "footer" | "figure" => {
+                format!("\n\n{}\n\n", self.children(element).trim())
+            }
+            "h1" | "h2" | "h3" | "h4" | "h5" | "h6" => {
+                format!("\n\n{}\n\n", wrap(&self.children(element), "**"))
+            }
+            "strong" | "b" => wrap(&self.children(element), "**"),
+            "em" | "i" => wrap(&self.children(element), "*"),
+            "u" => wrap(&self.children(element), "__"),
+            "s" | "del" | "strike" => wrap(&self.children(element), "~~"),
+            "code" => {
+                let code = collapse_whitespace(&element.text().collect::<String>());
+                if code.trim().is_empty() {
+                    String::new()
+                } else {
+                    format!("`{}`", code.trim().replace('`', "'"))
+                }
+            }
+            "pre" => format!("\n```\n{}\n```\n", element.text().collect::<String>().trim_end()),
+            "blockquote" => {
+                let quote = normalize(&self.children(element));
+                let quote = quote
+                    .lines()
+                    .map(|line| format!("> {}", line))
+                    .collect::<Vec<_>>()
+                    .join("\n");
+                format!("\n\n{}\n\n", quote)
+            }
+            "ul" | "ol" => format!("\n{}\n", self.list(element)),
+            "li" => format!("\n• {}\n", self.children(element).trim()),
+            "table" => format!("\n{}\n", table(element)),
+            "a" => self.link(element),
+            "img" => {
+                if let Some(src) = element.value().attr("src").and_then(|src| self.resolve(src)) {
+                    if !self.converted.images.contains(&src) {
+                        self.converted.images.push(src);
+                    }
+                }
+                String::new()
+            }
+            _ => self.children(element),
+        }
+    }
+
+    fn list(&mut self, element: ElementRef) -> String {
+        let ordered = element.value().name() == "ol";
+        let mut items = Vec::new();
+
+        for item in element.children().filter_map(ElementRef::wrap) {
+            if item.value().name() != "li" {
+                continue;
+            }
+            let content = normalize(&self.children(item));
+            let marker = if ordered {
+                format!("{}.", items.len() + 1)
+            } else {
+                "•".to_string()
+            };
+            // nested lists and further paragraphs are indented below their item
+            let content = content
+                .lines()
+                .filter(|line| !line.is_empty())
+                .collect::<Vec<_>>()
+                .join(&format!("\n{}{}", INDENT, INDENT));
+            items.push(format!("{} {}", marker, content));
+        }
+
+        items.join("\n")
+    }
+
+    fn link(&mut self, element: ElementRef) -> String {
+        let text = self.children(element);
+        let url = match element.value().attr("href").and_then(|href| self.resolve(href)) {
+            Some(url) => url,
+            None => return text,
+        };
+
+        let name = text.trim();
+        if is_pdf(&url) {
+            let attachment = Attachment {
+                name: if name.is_empty() {
+                    file_name(&url)
+                } else {
+                    unescape(name)
+                },
+                url: url.clone(),
+            };
+            if !self.converted.files.contains(&attachment) {
+                self.converted.files.push(attachment);
+            }
+        }
+
+        if name.is_empty() || unescape(name) == url {
+            url
+        } else {
+            // masked links can't contain brackets in their text
+            let name = name.replace('[', "(").replace(']', ")");
+            // keep the spaces around the link text outside of the link
+            let leading = &text[..text.len() - text.trim_start().len()];
+            let trailing = &text[text.trim_end().len()..];
+            format!("{}[{}]({}){}", leading, name, url, trailing)
+        }
+    }
+
+    /// Makes relative urls absolute, urls that can't be shown (e.g. `javascript:`) are dropped
+    fn resolve(&self, url: &str) -> Option<String> {
+        let url = url.trim();
+        let resolved = match reqwest::Url::parse(url) {
+            Ok(url) => url,
+            Err(_) => self.base.as_ref()?.join(url).ok()?,
+        };
+        match resolved.scheme() {
+            "http" | "https" | "mailto" => Some(resolved.to_string()),
+            _ => None,
+        }
+    }
+}
+
+/// Renders a table as a code block with aligned columns
+fn table(element: ElementRef) -> String {
+    let mut rows: Vec<Vec<String>> = Vec::new();
+    let mut header = false;
+
+    for row in element.descendants().filter_map(ElementRef::wrap) {
+        if row.value().name() != "tr" {
+            continue;
+        }
+        let cells = row
+            .children()
+            .filter_map(ElementRef::wrap)
+            .filter(|cell| matches!(cell.value().name(), "td" | "th"))
+            .collect::<Vec<_>>();
+        if rows.is_empty() {
+            header = !cells.is_empty() && cells.iter().all(|cell| cell.value().name() == "th");
+        }
+        rows.push(
+            cells
+                .iter()
+                .map(|cell| {
+                    collapse_whitespace(&cell.text().collect::<String>())
+                        .trim()
+                        .replace('`', "'")
+                })
+                .collect(),
+        );
+    }
+
+    let columns = rows.iter().map(Vec::len).max().unwrap_or_default();
+    if columns == 0 {
+        return String::new();
+    }
+    let widths = (0..columns)
+        .map(|i| {
+            rows.iter()
+                .filter_map(|row| row.get(i))
+                .map(|cell| cell.chars().count())
+                .max()
+                .unwrap_or_default()
+        })
+        .collect::<Vec<_>>();
+
+    let mut lines = rows
+        .iter()
+        .map(|row| {
+            widths
+                .iter()
+                .enumerate()
+                .map(|(i, width)| {
+                    let cell = row.get(i).map(String::as_str).unwrap_or_default();
+                    format!("{}{}", cell, " ".repeat(width - cell.chars().count()))
+                })
+                .collect::<Vec<_>>()
+                .join(" | ")
+                .trim_end()
+                .to_string()
+        })
+        .collect::<Vec<_>>();
+    if header {
+        let separator = widths
+            .iter()
+            .map(|width| "-".repeat(*width))
+            .collect::<Vec<_>>()
+            .join("-+-");
+        lines.insert(1, separator);
+    }
+
+    format!("```\n{}\n```", lines.join("\n"))
+}
+
+/// Puts markdown markers around text, spaces at its ends stay outside of them
+fn wrap(text: &str, marker: &str) -> String {
+    let trimmed = text.trim();
+    if trimmed.is_empty() {
+        return text.to_string();
+    }
+    let leading = &text[..text.len() - text.trim_start().len()];
+    let trailing = &text[text.trim_end().len()..];
+    format!("{}{}{}{}{}", leading, marker, trimmed, marker, trailing)
+}
+
+/// Whitespace in HTML text is shown as a single space
+fn collapse_whitespace(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    let mut space = false;
+    for c in text.chars() {
+        // non-breaking spaces are used for layout and look the same
+        if c.is_ascii_whitespace() || c == '\u{a0}' {
+            space = true;
+        } else {
+            if space {
+                out.push(' ');
+                space = false;
+            }
+            out.push(c);
+        }
+    }
+    if space {
+        out.push(' ');
+    }
+    out
+}
+
+/// Escapes characters Discord would read as markdown, urls are left alone so they stay clickable
+fn escape(text: &str) -> String {
+    text.split(' ')
+        .map(|word| {
+            if word.contains("://") {
+                word.to_string()
+            } else {
+                let mut escaped = String::with_capacity(word.len());
+                for c in word.chars() {
+                    if matches!(c, '\\' | '*' | '_' | '~' | '`' | '|') {
+                        escaped.push('\\');
+                    }
+                    escaped.push(c);
+                }
+                escaped
+            }
+        })
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+fn unescape(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    let mut chars = text.chars();
+    while let Some(c) = chars.next() {
+        if c == '\\' {
+            if let Some(next) = chars.next() {
+                out.push(next);
+            }
+        } else {
+            out.push(c);
+        }
+    }
+    out
+}
+
+/// Trims spaces around lines and collapses runs of empty lines, code blocks are kept as they are
+fn normalize(markdown: &str) -> String {
+    let mut lines: Vec<&str> = Vec::new();
+    let mut in_code = false;
+
+    for line in markdown.lines() {
+        if line.trim_start().starts_with("```") {
+            in_code = !in_code;
+            lines.push(line.trim());
+            continue;
+        }
+        if in_code {
+            lines.push(line.trim_end());
+            continue;
+        }
+
+        let line = line.trim_matches(' ');
+        if line.is_empty() && lines.last().is_none_or(|last| last.is_empty()) {
+            continue;
+        }
+        lines.push(line);
+    }
+
+    lines.join("\n").trim().to_string()
+}
+
+pub fn is_pdf(url: &str) -> bool {
+    reqwest::Url::parse(url)
+        .map(|url| url.path().to_lowercase().ends_with(".pdf"))
+        .unwrap_or(false)
+}
+
+/// The last segment of an url's path, e.g. `plan.pdf`
+pub fn file_name(url: &str) -> String {
+    reqwest::Url::parse(url)
+        .ok()
+        .and_then(|url| {
+            url.path_segments()
+                .and_then(|mut segments| segments.next_back().map(str::to_string))
+        })
+        .filter(|name| !name.is_empty())
+        .unwrap_or_else(|| url.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BASE: &str = "https://www.hs-kempten.de/news/aktuelles";
+
+    #[test]
+    fn plain_text() {
+        let converted = to_markdown("  Keine Tags *hier*  ", Some(BASE));
+        assert_eq!(converted.markdown, "Keine Tags *hier*");
+        assert!(converted.images.is_empty());
+    }
+
+    #[test]
+    fn formatting() {
+        let converted = to_markdown(
+            "<h2>Wichtig</h2><p>Die <b>Bibliothek</b> ist <em>geschlossen</em>.</p><p>2*3_4</p>",
+            None,
+        );
+        assert_eq!(
+            converted.markdown,
+            "**Wichtig**\n\nDie **Bibliothek** ist *geschlossen*.\n\n2\\*3\\_4"
+        );
+    }
+
+    #[test]
+    fn lists() {
+        let converted = to_markdown(
+            "<ul><li>Erstens</li><li>Zweitens<ul><li>Unterpunkt</li></ul></li></ul>\
+            <ol><li>Eins</li><li>Zwei</li></ol>",
+            None,
+        );
+        assert_eq!(
+            converted.markdown,
+            format!(
+                "• Erstens\n• Zweitens\n{}{}• Unterpunkt\n\n1. Eins\n2. Zwei",
+                INDENT, INDENT
+            )
+        );
+    }
+
+    #[test]
+    fn tables() {
+        let converted = to_markdown(
+            "<table><tr><th>Tag</th><th>Raum</th></tr>\
+            <tr><td>Montag</td><td>A 101</td></tr><tr><td>Di</td></tr></table>",
+            None,
+        );
+        assert_eq!(
+            converted.markdown,
+            "```\nTag    | Raum\n-------+------\nMontag | A 101\nDi     |\n```"
+        );
+    }
+
+    #[test]
+    fn links() {
+        let converted = to_markdown(
+            "<p>Mehr <a href=\"/termine?jahr=2025\">Termine</a> und \
+            <a href=\"https://example.com/\">https://example.com/</a> \
+            <a href=\"javascript:alert(1)\">hier</a> <a href=\"#\">[Top]</a></p>",
+            Some(BASE),
+        );
+        assert_eq!(
+            converted.markdown,
+            "Mehr [Termine](https://www.hs-kempten.de/termine?jahr=2025) und \
+            https://example.com/ hier [(Top)](https://www.hs-kempten.de/news/aktuelles#)"
+        );
+
+        // relative links can't be resolved without a base and are left out
+        let converted = to_markdown("<a href=\"termine\">Termine</a>", None);
+        assert_eq!(converted.markdown, "Termine");
+    }
+
+    #[test]
+    fn pdfs_and_images() {
+        let converted = to_markdown(
+            "<p><a href=\"files/Plan.PDF\">Stundenplan</a> <a href=\"/files/info.pdf\"></a>\
+            <img src=\"bild.jpg\"><img src=\"bild.jpg\"></p>",
+            Some(BASE),
+        );
+        assert_eq!(
+            converted.files,
+            vec![
+                Attachment {
+                    name: "Stundenplan".to_string(),
+                    url: "https://www.hs-kempten.de/news/files/Plan.PDF".to_string(),
+                },
+                Attachment {
+                    name: "info.pdf".to_string(),
+                    url: "https://www.hs-kempten.de/files/info.pdf".to_string(),
+                },
+            ]
+        );
+        assert_eq!(converted.images, vec!["https://www.hs-kempten.de/news/bild.jpg"]);
+        assert!(is_pdf("https://example.com/a.pdf?download=1"));
+        assert!(!is_pdf("https://example.com/pdf"));
+    }
+
+    #[test]
+    fn truncate_short_text() {
+        assert_eq!(truncate("kurz", 10, Some(BASE)), "kurz");
+    }
+
+    #[test]
+    fn truncate_at_words() {
+        let text = "Die Hochschule bleibt am Freitag wegen einer Betriebsversammlung geschlossen";
+        let truncated = truncate(text, 40, None);
+        assert_eq!(truncated, "Die Hochschule bleibt am Freitag…");
+        assert!(truncated.chars().count() <= 40);
+
+        let text = "a".repeat(200);
+        let truncated = truncate(&text, 100, Some(BASE));
+        assert!(truncated.ends_with(&format!("…\n\n[Weiterlesen]({})", BASE)));
+        assert_eq!(truncated.chars().count(), 100 - 5);
+    }
+
+    #[test]
+    fn truncate_without_room_for_the_link() {
+        let text = "Wort ".repeat(20);
+        let truncated = truncate(&text, 30, Some(BASE));
+        assert!(!truncated.contains("Weiterlesen"));
+        assert!(truncated.ends_with('…'));
+        assert!(truncated.chars().count() <= 30);
+
+        assert_eq!(truncate(&text, 3, Some(BASE)), "Wor");
+    }
+
+    #[test]
+    fn truncate_before_links() {
+        let text = format!("Neue Termine im [Kalender der Hochschule]({}) ansehen", BASE);
+        let truncated = truncate(&text, 50, None);
+        assert_eq!(truncated, "Neue Termine im…");
+
+        // links that fit are kept
+        let text = format!("[Kalender]({}) und noch mehr Text dahinter", BASE);
+        let truncated = truncate(&text, 64, None);
+        assert_eq!(truncated, format!("[Kalender]({}) und…", BASE));
+
+        // brackets that aren't links stay
+        assert_eq!(truncate("Raum [A 101] ist heute frei", 20, None), "Raum [A 101]…");
+        assert_eq!(truncate("Raum [A 101] ist frei", 14, None), "Raum…");
+    }
+
+    #[test]
+    fn truncate_closes_code_blocks() {
+        let text = format!("```\n{}\n```", "x ".repeat(50));
+        let truncated = truncate(&text, 40, None);
+        assert!(truncated.ends_with("\n```\n…"));
+        assert!(truncated.chars().count() <= 40);
+    }
+}
@@ -0,0 +1,237 @@
+use serde::{Deserialize, Serialize};
+
+use crate::config::FacultyManagerGeneralConfig;
+
+/// Levels are computed by walking up the curve, no one gets past this level
+const MAX_LEVEL: i32 = 10_000;
+
+/// How much XP each level needs
+#[derive(
+    Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize, poise::ChoiceParameter,
+)]
+#[serde(rename_all = "camelCase")]
+pub enum LevelCurve {
+    /// Every level needs `xpPerLevel` XP
+    #[default]
+    Linear,
+    /// Level n needs `xpPerLevel * n²` XP in total
+    Quadratic,
+    /// Going from level n to n + 1 needs `5n² + 50n + 100` XP, like MEE6
+    #[name = "MEE6"]
+    Mee6,
+}
+
+/// Where a user stands on the way to the next level
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Progress {
+    pub level: i32,
+    /// XP earned since reaching the current level
+    pub xp_into_level: f64,
+    /// XP between the current and the next level
+    pub xp_for_next_level: f64,
+}
+
+impl Progress {
+    /// Share of the way to the next level, from 0 to 1
+    pub fn fraction(&self) -> f64 {
+        if self.xp_for_next_level <= 0.0 {
+            return 1.0;
+        }
+        (self.xp_into_level / self.xp_for_next_level).clamp(0.0, 1.0)
+    }
+}
+
+/// Converts between XP and levels, commands and event handlers all go through this
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Leveling {
+    pub curve: LevelCurve,
+    pub xp_per_level: f64,
+}
+
+impl Leveling {
+    pub fn new(general: &FacultyManagerGeneralConfig) -> Self {
+        Self {
+            curve: general.level_curve,
+            xp_per_level: general.xp_per_level,
+        }
+    }
+
+    /// Total XP needed to reach a level, 0 for level 0
+    pub fn xp_for_level(&self, level: i32) -> f64 {
+        let n = level.max(0) as f64;
+        match self.curve {
+            LevelCurve::Linear => self.xp_per_level * n,
+            LevelCurve::Quadratic => self.xp_per_level * n * n,
+            // sum of 5k² + 50k + 100 for k in 0..n
+            LevelCurve::Mee6 => {
+                5.0 * (n - 1.0) * n * (2.0 * n - 1.0) / 6.0 + 50.0 * (n - 1.0) * n / 2.0 + 100.0 * n
+            }
+        }
+    }
+
+    /// The level reached with an amount of XP
+    pub fn level_for_xp(&self, xp: f64) -> i32 {
+        if xp <= 0.0 || (self.xp_per_level <= 0.0 && self.curve != LevelCurve::Mee6) {
+            return 0;
+        }
+
+        // a close guess, corrected for rounding below
+        let mut level = match self.curve {
+            LevelCurve::Linear => (xp / self.xp_per_level).floor(),
+            LevelCurve::Quadratic => (xp / self.xp_per_level).sqrt().floor(),
+            LevelCurve::Mee6 => 0.0,
+        }
+        .min(MAX_LEVEL as f64) as i32;
+
+        while level < MAX_LEVEL && self.xp_for_level(level + 1) <= xp {
+            level += 1;
+        }
+        while level > 0 && self.xp_for_level(level) > xp {
+            level -= 1;
+        }
+        level
+    }
+
+    pub fn progress(&self, xp: f64) -> Progress {
+        let level = self.level_for_xp(xp);
+        let current = self.xp_for_level(level);
+        Progress {
+            level,
+            xp_into_level: xp.max(0.0) - current,
+            xp_for_next_level: self.xp_for_level(level + 1) - current,
+        }
+    }
+}
+
+/// How much less XP messages earn on higher levels: 1 at level 0, then falling logarithmically
+pub fn xp_scaling(level: i32, scaling_factor: f64) -> f64 {
+    1.0 / (1.0 + scaling_factor * (level.max(0) as f64).ln_1p())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn leveling(curve: LevelCurve) -> Leveling {
+        Leveling {
+            curve,
+            xp_per_level: 100.0,
+        }
+    }
+
+    const CURVES: [LevelCurve; 3] = [LevelCurve::Linear, LevelCurve::Quadratic, LevelCurve::Mee6];
+
+    #[test]
+    fn xp_for_level() {
+        let linear = leveling(LevelCurve::Linear);
+        assert_eq!(linear.xp_for_level(0), 0.0);
+        assert_eq!(linear.xp_for_level(1), 100.0);
+        assert_eq!(linear.xp_for_level(2), 200.0);
+        assert_eq!(linear.xp_for_level(10), 1000.0);
+
+        let quadratic = leveling(LevelCurve::Quadratic);
+        assert_eq!(quadratic.xp_for_level(0), 0.0);
+        assert_eq!(quadratic.xp_for_level(1), 100.0);
+        assert_eq!(quadratic.xp_for_level(2), 400.0);
+        assert_eq!(quadratic.xp_for_level(10), 10_000.0);
+
+        let mee6 = leveling(LevelCurve::Mee6);
+        assert_eq!(mee6.xp_for_level(0), 0.0);
+        assert_eq!(mee6.xp_for_level(1), 100.0);
+        assert_eq!(mee6.xp_for_level(2), 255.0);
+        assert_eq!(mee6.xp_for_level(3), 475.0);
+        // the closed form matches adding up the steps
+        let steps = (0..10).map(|n| 5.0 * n as f64 * n as f64 + 50.0 * n as f64 + 100.0);
+        assert_eq!(mee6.xp_for_level(10), steps.sum::<f64>());
+
+        for curve in CURVES {
+            assert_eq!(leveling(curve).xp_for_level(-3), 0.0);
+        }
+    }
+
+    #[test]
+    fn level_for_xp_round_trips() {
+        for curve in CURVES {
+            let leveling = leveling(curve);
+            for level in 0..=200 {
+                let xp = leveling.xp_for_level(level);
+                assert_eq!(leveling.level_for_xp(xp), level, "{:?} at level {}", curve, level);
+            }
+        }
+    }
+
+    #[test]
+    fn level_for_xp_below_boundary() {
+        for curve in CURVES {
+            let leveling = leveling(curve);
+            for level in 1..=50 {
+                let xp = leveling.xp_for_level(level) - 0.01;
+                assert_eq!(leveling.level_for_xp(xp), level - 1, "{:?} at level {}", curve, level);
+            }
+        }
+    }
+
+    #[test]
+    fn level_for_xp_without_xp() {
+        for curve in CURVES {
+            let leveling = leveling(curve);
+            assert_eq!(leveling.level_for_xp(0.0), 0);
+            assert_eq!(leveling.level_for_xp(-50.0), 0);
+            assert_eq!(leveling.progress(-50.0).xp_into_level, 0.0);
+        }
+        let broken = Leveling {
+            curve: LevelCurve::Linear,
+            xp_per_level: 0.0,
+        };
+        assert_eq!(broken.level_for_xp(1000.0), 0);
+    }
+
+    #[test]
+    fn level_for_xp_is_capped() {
+        for curve in CURVES {
+            let leveling = leveling(curve);
+            assert_eq!(leveling.level_for_xp(f64::MAX), MAX_LEVEL);
+            assert_eq!(
+                leveling.level_for_xp(leveling.xp_for_level(MAX_LEVEL + 5)),
+                MAX_LEVEL
+            );
+        }
+    }
+
+    #[test]
+    fn progress_fraction_bounds() {
+        let leveling = leveling(LevelCurve::Linear);
+        assert_eq!(leveling.progress(0.0).fraction(), 0.0);
+        assert_eq!(leveling.progress(150.0).fraction(), 0.5);
+        assert_eq!(leveling.progress(-10.0).fraction(), 0.0);
+
+        let progress = Progress {
+            level: 1,
+            xp_into_level: 150.0,
+            xp_for_next_level: 100.0,
+        };
+        assert_eq!(progress.fraction(), 1.0);
+        let progress = Progress {
+            level: 1,
+            xp_into_level: -5.0,
+            xp_for_next_level: 100.0,
+        };
+        assert_eq!(progress.fraction(), 0.0);
+        let progress = Progress {
+            level: MAX_LEVEL,
+            xp_into_level: 0.0,
+            xp_for_next_level: 0.0,
+        };
+        assert_eq!(progress.fraction(), 1.0);
+    }
+
+    #[test]
+    fn xp_scaling_starts_at_one() {
+        for factor in [0.0, 0.5, 1.0, 10.0] {
+            assert_eq!(xp_scaling(0, factor), 1.0);
+            assert_eq!(xp_scaling(-1, factor), 1.0);
+        }
+        assert!(xp_scaling(10, 0.5) < xp_scaling(5, 0.5));
+        assert_eq!(xp_scaling(10, 0.0), 1.0);
+    }
+}
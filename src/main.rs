@@ -1,13 +1,19 @@
 mod commands;
 mod config;
 mod eventhandler;
+mod feeds;
+mod html;
+mod leveling;
+mod mensaplan;
+mod scheduler;
 mod structs;
 mod tasks;
 mod utils;
+mod verification;
 mod web;
+mod xp;
 
 
-use dashmap::DashMap;
 use dotenv::dotenv;
 use poise::{
     self,
@@ -15,7 +21,6 @@ use poise::{
 };
 use rocket_dyn_templates::Template;
 use sqlx::postgres::PgPoolOptions;
-use structs::CodeEmailPair;
 use tracing_subscriber::prelude::*;
 use utils::CurrentEmail;
 use web::auth::User;
@@ -54,6 +59,12 @@ pub mod prelude {
         Rss(rss::Error),
         /// Regex error
         Regex(regex::Error),
+        /// Error while reading the text of a PDF
+        Pdf(pdf_extract::OutputError),
+        /// Error while rendering a PDF
+        Pdfium(pdfium_render::prelude::PdfiumError),
+        /// Error while encoding an image
+        Image(image::ImageError),
         /// Idk bruh, don't ask me
         Unknown,
     }
@@ -72,6 +83,9 @@ pub mod prelude {
                 Error::ParseIntError(e) => write!(f, "ParseIntError: {}", e),
                 Error::Rss(e) => write!(f, "Rss error: {}", e),
                 Error::Regex(e) => write!(f, "Regex error: {}", e),
+                Error::Pdf(e) => write!(f, "PDF error: {}", e),
+                Error::Pdfium(e) => write!(f, "PDF rendering error: {}", e),
+                Error::Image(e) => write!(f, "Image error: {}", e),
                 _ => write!(
                     f,
                     "Unknown error occured, ask the developers for more information"
@@ -91,10 +105,12 @@ pub type ApplicationContext<'a> = poise::ApplicationContext<'a, Data, prelude::E
 #[derive(Clone)]
 pub struct Data {
     pub db: sqlx::Pool<sqlx::Postgres>,
-    pub config: config::FacultyManagerConfig,
-    pub email_codes: DashMap<serenity::UserId, CodeEmailPair>,
+    pub config: config::ConfigStore,
     pub email_task: tokio::sync::mpsc::Sender<CurrentEmail>,
     pub influx: influxdb2::Client,
+    pub tasks: scheduler::TaskRegistry,
+    pub timers: scheduler::GuildTimers,
+    pub xp: xp::XpTracker,
 }
 
 #[macro_use] extern crate rocket;
@@ -109,39 +125,11 @@ async fn main() {
         println!("Loaded .env file");
     }
 
-   let rocket_result = rocket::build()
-    .mount("/", 
-        routes![web::index, web::verify, web::reverify, web::admin, web::login, web::logout, web::switch_account, web::setup]
-    )
-    .mount("/api", routes![
-        web::api::send_mail, web::api::check_code, web::api::discord_auth, web::api::discord_callback
-    ])
-    .register("/", catchers![web::unauthorized, web::not_found])
-    .attach(Template::fairing());
-
-    let ctrl_z = tokio::signal::ctrl_c();
-
-    let example_user = User::new(1234567890).create_token(web::auth::Roles::Admin);
-    println!("Example admin token: {}", example_user);
-
-   tokio::select! {
-         _ = start_bot() => {},
-         _ = rocket_result.launch() => {},
-         _ = ctrl_z => {
-              println!("Shutting down");
-         }
-   }
-}
-
-
-async fn start_bot() -> Result<(), prelude::Error> {
-    // only load .env file if it exists
-    if std::path::Path::new(".env").exists() {
-        dotenv().ok();
-    }
-
-    // read config.json
+    // read config.json, used to seed the settings of new guilds
     let config: config::FacultyManagerConfig = config::read_config().expect("Failed to read config file");
+    if let Err(problems) = config.validate() {
+        panic!("config.json is invalid:\n- {}", problems.join("\n- "));
+    }
 
     // print mealplan post day and time
     println!(
@@ -172,14 +160,14 @@ async fn start_bot() -> Result<(), prelude::Error> {
         .max_connections(15)
         .connect(&db_url)
         .await
-        .map_err(prelude::Error::Database)?;
+        .expect("Failed to connect to the database");
 
-    let influx_host = "https://us-east-1-1.aws.cloud2.influxdata.com";
-    let influx_org = "faculty_manager";
-    let _influx_bucket = "faculty_manager";
-    let auth_token = std::env::var("INFLUX_TOKEN").expect("Expected `INFLUX_TOKEN` in the environment");
+    let guild_id = std::env::var("DISCORD_SERVER_ID")
+        .expect("DISCORD_SERVER_ID must be set")
+        .parse::<u64>()
+        .expect("DISCORD_SERVER_ID must be a number");
 
-    let influx_client = influxdb2::Client::new(influx_host, influx_org, auth_token);
+    let config = config::ConfigStore::new(pool.clone(), serenity::GuildId(guild_id), config);
 
     let (tx, mut rx) = tokio::sync::mpsc::channel::<CurrentEmail>(100);
 
@@ -198,6 +186,57 @@ async fn start_bot() -> Result<(), prelude::Error> {
         }
     });
 
+    // the web interface shares the database, email queue and task states with the bot
+    let tasks = scheduler::TaskRegistry::new();
+    let web_state = web::WebState {
+        db: pool.clone(),
+        config: config.clone(),
+        email_task: tx.clone(),
+        tasks: tasks.clone(),
+        http: std::sync::Arc::new(serenity::Http::new(&token)),
+        guild_id: serenity::GuildId(guild_id),
+    };
+
+   let rocket_result = rocket::build()
+    .mount("/", 
+        routes![web::index, web::verify, web::reverify, web::admin, web::login, web::logout, web::switch_account, web::setup]
+    )
+    .mount("/api", routes![
+        web::api::send_mail, web::api::check_code, web::api::get_settings, web::api::patch_settings, web::api::get_tasks, web::api::discord_auth, web::api::discord_callback
+    ])
+    .register("/", catchers![web::unauthorized, web::not_found])
+    .manage(web_state)
+    .attach(Template::fairing());
+
+    let ctrl_z = tokio::signal::ctrl_c();
+
+    let example_user = User::new(1234567890).create_token(web::auth::Roles::Admin);
+    println!("Example admin token: {}", example_user);
+
+   tokio::select! {
+         _ = start_bot(config, pool, tx, tasks, token) => {},
+         _ = rocket_result.launch() => {},
+         _ = ctrl_z => {
+              println!("Shutting down");
+         }
+   }
+}
+
+
+async fn start_bot(
+    config: config::ConfigStore,
+    pool: sqlx::PgPool,
+    tx: tokio::sync::mpsc::Sender<CurrentEmail>,
+    tasks: scheduler::TaskRegistry,
+    token: String,
+) -> Result<(), prelude::Error> {
+    let influx_host = "https://us-east-1-1.aws.cloud2.influxdata.com";
+    let influx_org = "faculty_manager";
+    let _influx_bucket = "faculty_manager";
+    let auth_token = std::env::var("INFLUX_TOKEN").expect("Expected `INFLUX_TOKEN` in the environment");
+
+    let influx_client = influxdb2::Client::new(influx_host, influx_org, auth_token);
+
     poise::Framework::builder()
         .options(poise::FrameworkOptions {
             commands: vec![
@@ -205,12 +244,19 @@ async fn start_bot() -> Result<(), prelude::Error> {
                 commands::user::verify(),
                 commands::user::leaderboard(),
                 commands::user::xp(),
+                commands::mensa::mensa(),
+                commands::feeds::feeds(),
+                commands::levelroles::levelroles(),
                 commands::administration::getmail(),
                 commands::administration::run_command(),
                 commands::administration::set_xp(),
+                commands::administration::recompute_levels(),
                 commands::administration::force_post_mensaplan(),
                 commands::administration::rule_command(),
                 commands::administration::reverify(),
+                commands::administration::config_command(),
+                commands::administration::status(),
+                commands::settings::settings(),
                 commands::moderation::pin(),
                 commands::moderation::delete_message(),
                 commands::moderation::promote_user(),
@@ -218,7 +264,12 @@ async fn start_bot() -> Result<(), prelude::Error> {
                 commands::help(),
             ],
             prefix_options: poise::PrefixFrameworkOptions {
-                prefix: Some(config.prefix.clone()),
+                dynamic_prefix: Some(|ctx| {
+                    Box::pin(async move {
+                        let config = ctx.data.config.resolve(ctx.guild_id).await?;
+                        Ok(Some(config.prefix))
+                    })
+                }),
                 mention_as_prefix: true,
                 ..Default::default()
             },
@@ -241,9 +292,11 @@ async fn start_bot() -> Result<(), prelude::Error> {
                 Ok(Data {
                     db: pool,
                     config,
-                    email_codes: DashMap::new(),
                     email_task: tx,
                     influx: influx_client,
+                    tasks,
+                    timers: scheduler::GuildTimers::new(),
+                    xp: xp::XpTracker::new(),
                 })
             })
         })
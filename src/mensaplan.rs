@@ -0,0 +1,892 @@
+use chrono::Datelike;
+use poise::serenity_prelude as serenity;
+
+use crate::{prelude::Error, structs};
+
+/// A single dish of the mensaplan
+#[derive(Debug, Clone)]
+pub struct Dish {
+    pub name: String,
+    /// Prices in the order they are listed on the plan (students, staff, guests)
+    pub prices: Vec<f64>,
+    pub allergens: Vec<String>,
+    pub vegetarian: bool,
+    pub vegan: bool,
+}
+
+/// All dishes served on one day
+#[derive(Debug, Clone)]
+pub struct DailyMenu {
+    pub date: chrono::NaiveDate,
+    pub dishes: Vec<Dish>,
+}
+
+const WEEKDAYS: [(&str, chrono::Weekday); 7] = [
+    ("Montag", chrono::Weekday::Mon),
+    ("Dienstag", chrono::Weekday::Tue),
+    ("Mittwoch", chrono::Weekday::Wed),
+    ("Donnerstag", chrono::Weekday::Thu),
+    ("Freitag", chrono::Weekday::Fri),
+    ("Samstag", chrono::Weekday::Sat),
+    ("Sonntag", chrono::Weekday::Sun),
+];
+
+/// Lines that carry no dish information
+const IGNORED_PREFIXES: [&str; 6] = [
+    "Speiseplan",
+    "Preise",
+    "Studierende",
+    "Bedienstete",
+    "Gäste",
+    "Änderungen",
+];
+
+/// Lines starting the legend at the bottom of the plan, nothing after them is a dish
+const FOOTER_PREFIXES: [&str; 3] = ["Kennzeichnung", "Allergene", "Legende"];
+
+/// Discord limits an embed to 6000 characters, the rest is left for the title and description
+const EMBED_FIELDS_LIMIT: usize = 6000 - 2 * 256;
+const EMBED_FIELD_LIMIT: usize = 1024;
+const EMBED_MAX_FIELDS: usize = 25;
+/// Ends the last field of an embed that doesn't fit the whole plan
+const MORE_IN_PDF: &str = "… mehr im PDF";
+
+/// Extracts the text of a mensaplan PDF and parses it into daily menus
+///
+/// `reference` is the day the plan was fetched, it is used when the plan doesn't print dates
+pub fn parse_pdf(pdf: &[u8], reference: chrono::NaiveDate) -> Result<Vec<DailyMenu>, Error> {
+    let text = pdf_extract::extract_text_from_mem(pdf).map_err(Error::Pdf)?;
+    parse(&text, reference)
+}
+
+/// Parses the text of a mensaplan, every weekday heading starts a new day and every line with a
+/// price ends a dish
+pub fn parse(text: &str, reference: chrono::NaiveDate) -> Result<Vec<DailyMenu>, Error> {
+    let day_regex = regex::Regex::new(
+        r"^(Montag|Dienstag|Mittwoch|Donnerstag|Freitag|Samstag|Sonntag)\b[,\s]*(?:(\d{1,2})\.(\d{1,2})\.(\d{2,4})?)?",
+    )
+    .map_err(Error::Regex)?;
+    let price_regex =
+        regex::Regex::new(r"\b(\d{1,2}),(\d{2})\b\s*(?:€|EUR)?").map_err(Error::Regex)?;
+    let allergen_regex =
+        regex::Regex::new(r"\(\s*([0-9A-Za-z]{1,3}(?:\s*,\s*[0-9A-Za-z]{1,3})*)\s*\)")
+            .map_err(Error::Regex)?;
+
+    // plans fetched on the weekend are for the coming week
+    let week_start = match reference.weekday() {
+        chrono::Weekday::Sat | chrono::Weekday::Sun => {
+            reference
+                + chrono::Duration::days(7 - reference.weekday().num_days_from_monday() as i64)
+        }
+        weekday => reference - chrono::Duration::days(weekday.num_days_from_monday() as i64),
+    };
+
+    let mut menus: Vec<DailyMenu> = Vec::new();
+    let mut pending_name = String::new();
+
+    for line in text.lines() {
+        let line = line.split_whitespace().collect::<Vec<_>>().join(" ");
+        if line.is_empty() || IGNORED_PREFIXES.iter().any(|p| line.starts_with(p)) {
+            continue;
+        }
+        if FOOTER_PREFIXES.iter().any(|p| line.starts_with(p)) {
+            break;
+        }
+
+        if let Some(caps) = day_regex.captures(&line) {
+            finish_dish(&mut menus, &mut pending_name, Vec::new(), &allergen_regex);
+
+            let weekday = WEEKDAYS
+                .iter()
+                .find(|(name, _)| *name == &caps[1])
+                .map(|(_, weekday)| *weekday)
+                .unwrap_or(chrono::Weekday::Mon);
+
+            let printed_date = match (caps.get(2), caps.get(3)) {
+                (Some(day), Some(month)) => {
+                    let year = caps
+                        .get(4)
+                        .and_then(|y| y.as_str().parse::<i32>().ok())
+                        .map(|y| if y < 100 { 2000 + y } else { y })
+                        .unwrap_or(week_start.year());
+                    chrono::NaiveDate::from_ymd_opt(
+                        year,
+                        month.as_str().parse().unwrap_or(0),
+                        day.as_str().parse().unwrap_or(0),
+                    )
+                }
+                _ => None,
+            };
+            let date = printed_date.unwrap_or(
+                week_start + chrono::Duration::days(weekday.num_days_from_monday() as i64),
+            );
+
+            menus.push(DailyMenu {
+                date,
+                dishes: Vec::new(),
+            });
+
+            // some plans put the first dish on the same line as the day
+            let rest = line[caps.get(0).map(|m| m.end()).unwrap_or(0)..].trim();
+            if !rest.is_empty() {
+                handle_line(
+                    &mut menus,
+                    &mut pending_name,
+                    rest,
+                    &price_regex,
+                    &allergen_regex,
+                );
+            }
+            continue;
+        }
+
+        // text before the first day is the header of the plan
+        if menus.is_empty() {
+            continue;
+        }
+
+        handle_line(
+            &mut menus,
+            &mut pending_name,
+            &line,
+            &price_regex,
+            &allergen_regex,
+        );
+    }
+
+    finish_dish(&mut menus, &mut pending_name, Vec::new(), &allergen_regex);
+
+    Ok(menus)
+}
+
+fn handle_line(
+    menus: &mut [DailyMenu],
+    pending_name: &mut String,
+    line: &str,
+    price_regex: &regex::Regex,
+    allergen_regex: &regex::Regex,
+) {
+    // allergen lists such as (2,10,11) look like prices
+    let allergen_spans = allergen_regex
+        .find_iter(line)
+        .map(|m| m.range())
+        .collect::<Vec<_>>();
+    let price_matches = price_regex
+        .captures_iter(line)
+        .filter(|c| {
+            let start = c.get(0).map(|m| m.start()).unwrap_or(0);
+            !allergen_spans.iter().any(|span| span.contains(&start))
+        })
+        .collect::<Vec<_>>();
+    let prices = price_matches
+        .iter()
+        .filter_map(|c| format!("{}.{}", &c[1], &c[2]).parse::<f64>().ok())
+        .collect::<Vec<_>>();
+
+    let mut text = line.to_string();
+    for price in price_matches.iter().rev().filter_map(|c| c.get(0)) {
+        text.replace_range(price.range(), "");
+    }
+    let text = text.trim();
+    if !text.is_empty() {
+        if !pending_name.is_empty() {
+            pending_name.push(' ');
+        }
+        pending_name.push_str(text);
+    }
+
+    if prices.is_empty() {
+        return;
+    }
+
+    if pending_name.is_empty() {
+        // prices printed on their own line belong to the dish above
+        if let Some(dish) = menus
+            .last_mut()
+            .and_then(|m| m.dishes.last_mut())
+            .filter(|d| d.prices.is_empty())
+        {
+            dish.prices = prices;
+        }
+        return;
+    }
+
+    finish_dish(menus, pending_name, prices, allergen_regex);
+}
+
+fn finish_dish(
+    menus: &mut [DailyMenu],
+    pending_name: &mut String,
+    prices: Vec<f64>,
+    allergen_regex: &regex::Regex,
+) {
+    if pending_name.is_empty() {
+        return;
+    }
+    let raw = std::mem::take(pending_name);
+
+    let mut allergens = Vec::new();
+    for caps in allergen_regex.captures_iter(&raw) {
+        for allergen in caps[1].split(',') {
+            let allergen = allergen.trim().to_string();
+            if !allergens.contains(&allergen) {
+                allergens.push(allergen);
+            }
+        }
+    }
+
+    let name = allergen_regex
+        .replace_all(&raw, "")
+        .split_whitespace()
+        .collect::<Vec<_>>()
+        .join(" ");
+    let lower = name.to_lowercase();
+    let vegan = lower.contains("vegan");
+    let vegetarian = vegan || lower.contains("vegetarisch") || lower.contains("fleischlos");
+
+    if let Some(menu) = menus.last_mut() {
+        menu.dishes.push(Dish {
+            name,
+            prices,
+            allergens,
+            vegetarian,
+            vegan,
+        });
+    }
+}
+
+/// Returns the menus of a plan, the PDF is parsed and stored the first time it is seen
+///
+/// The plan is remembered as the current plan of `url` and of its week, see [`week_menus`]
+pub async fn menus(
+    db: &sqlx::PgPool,
+    url: &str,
+    pdf: &[u8],
+    plan_hash: &str,
+) -> Result<Vec<DailyMenu>, Error> {
+    let reference = chrono::Local::now().date_naive();
+
+    let mut menus = load(db, plan_hash).await?;
+    if menus.is_empty() {
+        // extracting the text is blocking work and pdf_extract panics on some malformed files
+        let pdf = pdf.to_vec();
+        menus = tokio::task::spawn_blocking(move || parse_pdf(&pdf, reference))
+            .await
+            .map_err(|why| {
+                Error::WithMessage(format!("Parsing the mensaplan failed: {}", why))
+            })??;
+
+        store(db, plan_hash, &menus).await?;
+    }
+
+    let week = week_start(menus.first().map(|m| m.date).unwrap_or(reference));
+    sqlx::query(
+        "INSERT INTO mensaplan_weeks (url, week_start, plan_hash) VALUES ($1, $2, $3)
+        ON CONFLICT (url, week_start) DO UPDATE SET plan_hash = $3, fetched_at = CURRENT_TIMESTAMP",
+    )
+    .bind(url)
+    .bind(week)
+    .bind(plan_hash)
+    .execute(db)
+    .await
+    .map_err(Error::Database)?;
+
+    sqlx::query(
+        "INSERT INTO mensaplan_fetches (url, plan_hash) VALUES ($1, $2)
+        ON CONFLICT (url) DO UPDATE SET plan_hash = $2, fetched_at = CURRENT_TIMESTAMP",
+    )
+    .bind(url)
+    .bind(plan_hash)
+    .execute(db)
+    .await
+    .map_err(Error::Database)?;
+
+    Ok(menus)
+}
+
+/// Returns the menus of the week containing `date`
+///
+/// The PDF behind `url` is only downloaded again when it was last fetched more than six hours
+/// ago. Weeks it doesn't cover (anymore) are looked up in the plans fetched before.
+pub async fn week_menus(
+    db: &sqlx::PgPool,
+    url: &str,
+    date: chrono::NaiveDate,
+) -> Result<Vec<DailyMenu>, Error> {
+    let week = week_start(date);
+    let in_week = |menus: Vec<DailyMenu>| {
+        menus
+            .into_iter()
+            .filter(|menu| week_start(menu.date) == week)
+            .collect::<Vec<_>>()
+    };
+
+    let cached = sqlx::query_as::<sqlx::Postgres, structs::MensaplanFetch>(
+        "SELECT * FROM mensaplan_fetches WHERE url = $1
+        AND fetched_at > CURRENT_TIMESTAMP - INTERVAL '6 hours'",
+    )
+    .bind(url)
+    .fetch_optional(db)
+    .await
+    .map_err(Error::Database)?;
+
+    let menus = match cached {
+        Some(cached) => load(db, &cached.plan_hash).await?,
+        None => {
+            let pdf = crate::utils::download_mensaplan(url).await?;
+            let plan_hash = crate::utils::mensaplan_hash(&pdf);
+            menus(db, url, &pdf, &plan_hash).await?
+        }
+    };
+    let menus = in_week(menus);
+    if !menus.is_empty() {
+        return Ok(menus);
+    }
+
+    // e.g. the current week after the plan of the next one was published
+    let earlier = sqlx::query_as::<sqlx::Postgres, structs::MensaplanWeek>(
+        "SELECT * FROM mensaplan_weeks WHERE url = $1 AND week_start = $2",
+    )
+    .bind(url)
+    .bind(week)
+    .fetch_optional(db)
+    .await
+    .map_err(Error::Database)?;
+
+    match earlier {
+        Some(earlier) => Ok(in_week(load(db, &earlier.plan_hash).await?)),
+        None => Ok(menus),
+    }
+}
+
+/// Assigns plans posted before plans were tracked per guild (`guild_id` 0) to a guild
+///
+/// Returns how many plans were assigned
+pub async fn adopt_legacy_plans(
+    db: &sqlx::PgPool,
+    guild_id: serenity::GuildId,
+) -> Result<u64, Error> {
+    let adopted = sqlx::query(
+        "UPDATE mensaplan SET guild_id = $1 WHERE guild_id = 0 AND NOT EXISTS (
+            SELECT 1 FROM mensaplan m WHERE m.date = mensaplan.date AND m.guild_id = $1
+            AND m.canteen = mensaplan.canteen
+        )",
+    )
+    .bind(guild_id.0 as i64)
+    .execute(db)
+    .await
+    .map_err(Error::Database)?;
+
+    Ok(adopted.rows_affected())
+}
+
+/// The monday of the week containing `date`
+pub fn week_start(date: chrono::NaiveDate) -> chrono::NaiveDate {
+    date - chrono::Duration::days(date.weekday().num_days_from_monday() as i64)
+}
+
+/// Dietary filters for the dishes shown to a user
+#[derive(Debug, Default, Clone)]
+pub struct DishFilter {
+    pub vegetarian: bool,
+    pub vegan: bool,
+    /// Allergen codes as printed on the plan, compared case insensitively
+    pub exclude_allergens: Vec<String>,
+}
+
+impl DishFilter {
+    pub fn matches(&self, dish: &Dish) -> bool {
+        if self.vegan && !dish.vegan {
+            return false;
+        }
+        if self.vegetarian && !dish.vegetarian {
+            return false;
+        }
+        !dish.allergens.iter().any(|allergen| {
+            self.exclude_allergens
+                .iter()
+                .any(|excluded| excluded.eq_ignore_ascii_case(allergen))
+        })
+    }
+
+    /// Removes the dishes not matching the filter, days without dishes are kept
+    pub fn apply(&self, menus: Vec<DailyMenu>) -> Vec<DailyMenu> {
+        menus
+            .into_iter()
+            .map(|mut menu| {
+                menu.dishes.retain(|dish| self.matches(dish));
+                menu
+            })
+            .collect()
+    }
+}
+
+/// Stores the parsed menus of a plan, plans that were stored before are left untouched
+pub async fn store(db: &sqlx::PgPool, plan_hash: &str, menus: &[DailyMenu]) -> Result<(), Error> {
+    let stored = sqlx::query_as::<sqlx::Postgres, (i64,)>(
+        "SELECT COUNT(*) FROM mensaplan_dishes WHERE plan_hash = $1",
+    )
+    .bind(plan_hash)
+    .fetch_one(db)
+    .await
+    .map_err(Error::Database)?;
+
+    if stored.0 > 0 {
+        return Ok(());
+    }
+
+    for menu in menus {
+        for (position, dish) in menu.dishes.iter().enumerate() {
+            sqlx::query(
+                "INSERT INTO mensaplan_dishes (plan_hash, day, position, name, prices, allergens, vegetarian, vegan)
+                VALUES ($1, $2, $3, $4, $5, $6, $7, $8)",
+            )
+            .bind(plan_hash)
+            .bind(menu.date)
+            .bind(position as i32)
+            .bind(&dish.name)
+            .bind(&dish.prices)
+            .bind(&dish.allergens)
+            .bind(dish.vegetarian)
+            .bind(dish.vegan)
+            .execute(db)
+            .await
+            .map_err(Error::Database)?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Loads the stored menus of a plan
+pub async fn load(db: &sqlx::PgPool, plan_hash: &str) -> Result<Vec<DailyMenu>, Error> {
+    let rows = sqlx::query_as::<sqlx::Postgres, structs::MensaplanDish>(
+        "SELECT * FROM mensaplan_dishes WHERE plan_hash = $1 ORDER BY day, position",
+    )
+    .bind(plan_hash)
+    .fetch_all(db)
+    .await
+    .map_err(Error::Database)?;
+
+    let mut menus: Vec<DailyMenu> = Vec::new();
+    for row in rows {
+        let dish = Dish {
+            name: row.name,
+            prices: row.prices,
+            allergens: row.allergens,
+            vegetarian: row.vegetarian,
+            vegan: row.vegan,
+        };
+        match menus.last_mut() {
+            Some(menu) if menu.date == row.day => menu.dishes.push(dish),
+            _ => menus.push(DailyMenu {
+                date: row.day,
+                dishes: vec![dish],
+            }),
+        }
+    }
+
+    Ok(menus)
+}
+
+/// The German name of a weekday, as printed on the plan
+pub fn weekday_name(weekday: chrono::Weekday) -> &'static str {
+    WEEKDAYS
+        .iter()
+        .find(|(_, day)| *day == weekday)
+        .map(|(name, _)| *name)
+        .unwrap_or_default()
+}
+
+/// Formats a price the way it is printed on the plan
+pub fn format_price(price: f64) -> String {
+    format!("{:.2} €", price).replace('.', ",")
+}
+
+/// One line of text per dish, with markers for vegan and vegetarian dishes
+pub fn format_dish(dish: &Dish) -> String {
+    let marker = if dish.vegan {
+        "🌱 "
+    } else if dish.vegetarian {
+        "🥦 "
+    } else {
+        ""
+    };
+
+    let mut line = format!("{}{}", marker, dish.name);
+    if let Some(price) = dish.prices.first() {
+        line.push_str(&format!(" — {}", format_price(*price)));
+    }
+    if !dish.allergens.is_empty() {
+        line.push_str(&format!(" *({})*", dish.allergens.join(", ")));
+    }
+    line
+}
+
+/// Embed fields for the given menus, one field per day
+///
+/// Days that don't fit into the embed are cut off, the last field then ends with a note that
+/// the rest is in the PDF
+pub fn embed_fields(menus: &[DailyMenu]) -> Vec<(String, String, bool)> {
+    let note_len = MORE_IN_PDF.chars().count();
+    let mut fields: Vec<(String, String, bool)> = Vec::new();
+    let mut total = 0;
+    let mut complete = true;
+
+    'days: for menu in menus.iter().filter(|menu| !menu.dishes.is_empty()) {
+        if fields.len() == EMBED_MAX_FIELDS {
+            complete = false;
+            break;
+        }
+        let name = format!(
+            "{}, {}",
+            weekday_name(menu.date.weekday()),
+            menu.date.format("%d.%m.")
+        );
+
+        // every field leaves room for the note, both in the field and in the embed
+        let limit = (EMBED_FIELD_LIMIT - note_len)
+            .min(EMBED_FIELDS_LIMIT.saturating_sub(total + name.chars().count() + note_len));
+        let mut value = String::new();
+        for dish in &menu.dishes {
+            let line = format!("• {}\n", format_dish(dish));
+            if value.chars().count() + line.chars().count() > limit {
+                if !value.is_empty() {
+                    fields.push((name, value, false));
+                }
+                complete = false;
+                break 'days;
+            }
+            value.push_str(&line);
+        }
+
+        total += name.chars().count() + value.chars().count();
+        fields.push((name, value, false));
+    }
+
+    if !complete {
+        if let Some((_, value, _)) = fields.last_mut() {
+            value.push_str(MORE_IN_PDF);
+        }
+    }
+    fields
+}
+
+/// Fills an embed with the dishes of the given menus
+pub fn create_embed<'a>(
+    e: &'a mut serenity::CreateEmbed,
+    menus: &[DailyMenu],
+) -> &'a mut serenity::CreateEmbed {
+    e.title("Mensaplan")
+        .description("🌱 vegan · 🥦 vegetarisch · Allergene in Klammern")
+        .fields(embed_fields(menus))
+}
+
+/// Subscribes a user to a daily DM with the dishes of the day, replacing an earlier subscription
+pub async fn subscribe(
+    db: &sqlx::PgPool,
+    user_id: serenity::UserId,
+    guild_id: Option<serenity::GuildId>,
+    notify_at: chrono::NaiveTime,
+) -> Result<(), Error> {
+    // don't send today's DM right away if the time already passed
+    let last_sent =
+        (chrono::Local::now().time() > notify_at).then(|| chrono::Local::now().date_naive());
+
+    sqlx::query(
+        "INSERT INTO mensa_subscriptions (user_id, guild_id, notify_at, last_sent) VALUES ($1, $2, $3, $4)
+        ON CONFLICT (user_id) DO UPDATE SET guild_id = $2, notify_at = $3, last_sent = $4",
+    )
+    .bind(user_id.0 as i64)
+    .bind(guild_id.map(|g| g.0 as i64))
+    .bind(notify_at)
+    .bind(last_sent)
+    .execute(db)
+    .await
+    .map_err(Error::Database)?;
+
+    Ok(())
+}
+
+/// Removes a user's daily DM, returns false if they weren't subscribed
+pub async fn unsubscribe(db: &sqlx::PgPool, user_id: serenity::UserId) -> Result<bool, Error> {
+    let res = sqlx::query("DELETE FROM mensa_subscriptions WHERE user_id = $1")
+        .bind(user_id.0 as i64)
+        .execute(db)
+        .await
+        .map_err(Error::Database)?;
+
+    Ok(res.rows_affected() > 0)
+}
+
+/// Adds a keyword a user wants to be alerted about
+pub async fn add_keyword(
+    db: &sqlx::PgPool,
+    user_id: serenity::UserId,
+    guild_id: Option<serenity::GuildId>,
+    keyword: &str,
+) -> Result<(), Error> {
+    sqlx::query(
+        "INSERT INTO mensa_keywords (user_id, keyword, guild_id) VALUES ($1, $2, $3)
+        ON CONFLICT (user_id, keyword) DO UPDATE SET guild_id = $3",
+    )
+    .bind(user_id.0 as i64)
+    .bind(keyword)
+    .bind(guild_id.map(|g| g.0 as i64))
+    .execute(db)
+    .await
+    .map_err(Error::Database)?;
+
+    Ok(())
+}
+
+/// Removes one of a user's keywords, returns false if they didn't have it
+pub async fn remove_keyword(
+    db: &sqlx::PgPool,
+    user_id: serenity::UserId,
+    keyword: &str,
+) -> Result<bool, Error> {
+    let res = sqlx::query("DELETE FROM mensa_keywords WHERE user_id = $1 AND keyword = $2")
+        .bind(user_id.0 as i64)
+        .bind(keyword)
+        .execute(db)
+        .await
+        .map_err(Error::Database)?;
+
+    Ok(res.rows_affected() > 0)
+}
+
+/// The keywords a user is alerted about
+pub async fn keywords(db: &sqlx::PgPool, user_id: serenity::UserId) -> Result<Vec<String>, Error> {
+    let keywords = sqlx::query_as::<sqlx::Postgres, structs::MensaKeyword>(
+        "SELECT * FROM mensa_keywords WHERE user_id = $1 ORDER BY keyword",
+    )
+    .bind(user_id.0 as i64)
+    .fetch_all(db)
+    .await
+    .map_err(Error::Database)?;
+
+    Ok(keywords.into_iter().map(|k| k.keyword).collect())
+}
+
+/// DMs a subscribed user the dishes of the day
+pub async fn send_daily_dm(
+    ctx: &serenity::Context,
+    user_id: serenity::UserId,
+    menu: &DailyMenu,
+) -> Result<(), Error> {
+    let menus = [menu.clone()];
+
+    user_id
+        .create_dm_channel(ctx)
+        .await
+        .map_err(Error::Serenity)?
+        .send_message(ctx, |message| {
+            message.embed(|e| create_embed(e, &menus)).components(|c| {
+                c.create_action_row(|a| {
+                    a.create_button(|b| {
+                        b.style(serenity::ButtonStyle::Secondary)
+                            .label("Unsubscribe")
+                            .custom_id("mensa_unsubscribe")
+                    })
+                })
+            })
+        })
+        .await
+        .map_err(Error::Serenity)?;
+
+    Ok(())
+}
+
+/// DMs a user the dishes matching one of their keywords
+pub async fn send_alert_dm(
+    ctx: &serenity::Context,
+    user_id: serenity::UserId,
+    keyword: &str,
+    matches: &[(chrono::NaiveDate, Dish)],
+) -> Result<(), Error> {
+    let description = matches
+        .iter()
+        .map(|(date, dish)| {
+            format!(
+                "**{}, {}**: {}",
+                weekday_name(date.weekday()),
+                date.format("%d.%m."),
+                format_dish(dish)
+            )
+        })
+        .collect::<Vec<_>>()
+        .join("\n");
+
+    user_id
+        .create_dm_channel(ctx)
+        .await
+        .map_err(Error::Serenity)?
+        .send_message(ctx, |message| {
+            message
+                .content(format!("🍽️ **{}** is on the mensaplan!", keyword))
+                .embed(|e| e.title("Mensaplan").description(description))
+                .components(|c| {
+                    c.create_action_row(|a| {
+                        a.create_button(|b| {
+                            b.style(serenity::ButtonStyle::Secondary)
+                                .label(format!("Stop alerts for {}", keyword))
+                                .custom_id(format!("mensa_alert_remove:{}", keyword))
+                        })
+                    })
+                })
+        })
+        .await
+        .map_err(Error::Serenity)?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn date(day: u32) -> chrono::NaiveDate {
+        chrono::NaiveDate::from_ymd_opt(2025, 1, day).unwrap()
+    }
+
+    /// A wednesday, the plan is for the week starting on monday the 13th
+    fn reference() -> chrono::NaiveDate {
+        date(15)
+    }
+
+    /// Text as extracted from a weekly plan, with the header, the price columns and the legend
+    const PLAN: &str = "Speiseplan Mensa Kempten
+KW 03 vom 13.01.2025 bis 17.01.2025
+Preise Studierende Bedienstete Gäste
+
+Montag, 13.01.2025
+Schweinegeschnetzeltes mit Spätzle (2,3,A,G)
+4,20 € 5,60 € 7,00 €
+Gemüselasagne fleischlos (A,C,G) 3,50 € 4,80 € 6,00 €
+
+Dienstag, 14.01.2025
+Linsen-Dal mit Basmatireis vegan (F,I) 3,20 € 4,50 € 5,70 €
+Putenbrust mit Kräuterkartoffeln
+(10,11,C)
+4,90 € 6,10 € 7,50 €
+
+Mittwoch, 15.01.2025 Currywurst mit Pommes frites (1,3,5,J) 3,90 € 5,20 € 6,50 €
+
+Kennzeichnung der Zusatzstoffe und Allergene
+1 mit Farbstoff 2 mit Konservierungsstoff 3 mit Antioxidationsmittel
+Änderungen vorbehalten 1,00 €
+";
+
+    #[test]
+    fn days_and_dishes() {
+        let menus = parse(PLAN, reference()).unwrap();
+
+        let dates = menus.iter().map(|m| m.date).collect::<Vec<_>>();
+        assert_eq!(dates, vec![date(13), date(14), date(15)]);
+
+        let names = menus
+            .iter()
+            .map(|m| m.dishes.iter().map(|d| d.name.as_str()).collect::<Vec<_>>())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            names,
+            vec![
+                vec!["Schweinegeschnetzeltes mit Spätzle", "Gemüselasagne fleischlos"],
+                vec!["Linsen-Dal mit Basmatireis vegan", "Putenbrust mit Kräuterkartoffeln"],
+                // the first dish may share the line with the day
+                vec!["Currywurst mit Pommes frites"],
+            ]
+        );
+    }
+
+    #[test]
+    fn prices() {
+        let menus = parse(PLAN, reference()).unwrap();
+
+        // prices on their own line belong to the dish above
+        assert_eq!(menus[0].dishes[0].prices, vec![4.2, 5.6, 7.0]);
+        assert_eq!(menus[0].dishes[1].prices, vec![3.5, 4.8, 6.0]);
+        assert_eq!(menus[1].dishes[0].prices, vec![3.2, 4.5, 5.7]);
+        assert_eq!(menus[2].dishes[0].prices, vec![3.9, 5.2, 6.5]);
+
+        let menus = parse(
+            "Freitag\nFischfilet 12,50 EUR\nSalatteller 2,05€",
+            reference(),
+        )
+        .unwrap();
+        assert_eq!(menus[0].dishes[0].prices, vec![12.5]);
+        assert_eq!(menus[0].dishes[1].prices, vec![2.05]);
+    }
+
+    #[test]
+    fn allergens() {
+        let menus = parse(PLAN, reference()).unwrap();
+
+        assert_eq!(menus[0].dishes[0].allergens, vec!["2", "3", "A", "G"]);
+        assert_eq!(menus[1].dishes[0].allergens, vec!["F", "I"]);
+        // lists of two digit codes are not mistaken for prices
+        let putenbrust = &menus[1].dishes[1];
+        assert_eq!(putenbrust.allergens, vec!["10", "11", "C"]);
+        assert_eq!(putenbrust.prices, vec![4.9, 6.1, 7.5]);
+
+        let menus = parse("Montag\nSuppe ( A , G ) (G, L) 2,00 €", reference()).unwrap();
+        assert_eq!(menus[0].dishes[0].name, "Suppe");
+        assert_eq!(menus[0].dishes[0].allergens, vec!["A", "G", "L"]);
+    }
+
+    #[test]
+    fn vegan_and_vegetarian() {
+        let menus = parse(PLAN, reference()).unwrap();
+        let dishes = menus.iter().flat_map(|m| &m.dishes).collect::<Vec<_>>();
+
+        let markers = dishes
+            .iter()
+            .map(|d| (d.vegetarian, d.vegan))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            markers,
+            vec![
+                (false, false),
+                (true, false),
+                // vegan dishes are vegetarian as well
+                (true, true),
+                (false, false),
+                (false, false),
+            ]
+        );
+
+        let menus = parse("Montag\nGemüsecurry Vegetarisch 3,00 €", reference()).unwrap();
+        assert!(menus[0].dishes[0].vegetarian);
+        assert!(!menus[0].dishes[0].vegan);
+    }
+
+    #[test]
+    fn missing_days() {
+        // no plan for tuesday and thursday, e.g. holidays, and no printed dates
+        let plan = "Speiseplan
+Montag
+Nudeln mit Tomatensoße (A) 2,80 €
+Mittwoch
+Freitag
+Backfisch (A,D) 4,10 €
+";
+        let menus = parse(plan, reference()).unwrap();
+
+        let dates = menus.iter().map(|m| m.date).collect::<Vec<_>>();
+        assert_eq!(dates, vec![date(13), date(15), date(17)]);
+        assert_eq!(menus[0].dishes.len(), 1);
+        assert!(menus[1].dishes.is_empty());
+        assert_eq!(menus[2].dishes[0].name, "Backfisch");
+
+        // plans fetched on the weekend are for the coming week
+        let menus = parse(plan, date(18)).unwrap();
+        assert_eq!(menus[0].date, date(20));
+    }
+
+    #[test]
+    fn no_days() {
+        let menus = parse("Speiseplan\nDie Mensa ist geschlossen 1,00 €", reference()).unwrap();
+        assert!(menus.is_empty());
+    }
+}
@@ -0,0 +1,552 @@
+use std::{collections::BTreeMap, future::Future, pin::Pin, sync::Arc};
+
+use chrono::{Datelike, TimeZone};
+use dashmap::DashMap;
+use poise::serenity_prelude as serenity;
+use serde::Serialize;
+use tracing::info;
+
+use crate::{prelude::Error, structs, Data};
+
+type TaskFuture = Pin<Box<dyn Future<Output = Result<(), Error>> + Send>>;
+type TaskFn = Arc<dyn Fn(serenity::Context, Data) -> TaskFuture + Send + Sync>;
+
+/// Failed runs are retried after 1, 2, 4, ... minutes, but at least once an hour
+const MAX_BACKOFF_MINUTES: i64 = 60;
+/// Crashed tasks are restarted after 5, 10, 20, ... seconds, but at least every 10 minutes
+const RESTART_DELAY_SECS: u64 = 5;
+const MAX_RESTART_DELAY_SECS: u64 = 10 * 60;
+
+/// When a task runs
+#[derive(Debug, Clone, Copy)]
+pub enum Schedule {
+    /// Every time the duration has passed since the last run
+    Every(chrono::Duration),
+    /// Every week on the given day at the given local time
+    Weekly(chrono::Weekday, chrono::NaiveTime),
+}
+
+impl Schedule {
+    /// The first time the task is due after a run at `last`
+    pub fn next_after(
+        &self,
+        last: chrono::DateTime<chrono::Local>,
+    ) -> chrono::DateTime<chrono::Local> {
+        match *self {
+            Schedule::Every(interval) => last + interval,
+            Schedule::Weekly(day, time) => {
+                let days_until =
+                    (7 + day.num_days_from_monday() - last.weekday().num_days_from_monday()) % 7;
+                let mut date = last.date_naive() + chrono::Duration::days(days_until as i64);
+                if local(date.and_time(time)) <= last {
+                    date += chrono::Duration::weeks(1);
+                }
+                local(date.and_time(time))
+            }
+        }
+    }
+}
+
+impl std::fmt::Display for Schedule {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Schedule::Every(interval) if interval.num_seconds() % 3600 == 0 => {
+                write!(f, "every {}h", interval.num_hours())
+            }
+            Schedule::Every(interval) if interval.num_seconds() % 60 == 0 => {
+                write!(f, "every {}min", interval.num_minutes())
+            }
+            Schedule::Every(interval) => write!(f, "every {}s", interval.num_seconds()),
+            Schedule::Weekly(day, time) => write!(f, "{} at {}", day, time.format("%H:%M")),
+        }
+    }
+}
+
+/// A local date time, skipping forward over gaps from daylight saving time
+fn local(naive: chrono::NaiveDateTime) -> chrono::DateTime<chrono::Local> {
+    chrono::Local
+        .from_local_datetime(&naive)
+        .earliest()
+        .unwrap_or_else(|| local(naive + chrono::Duration::hours(1)))
+}
+
+#[derive(Clone)]
+struct Task {
+    name: &'static str,
+    schedule: Schedule,
+    run: TaskFn,
+}
+
+/// What a task is doing right now
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TaskState {
+    /// Waiting for its next run
+    Idle,
+    Running,
+    /// Panicked, waiting to be restarted
+    Crashed,
+}
+
+impl std::fmt::Display for TaskState {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            TaskState::Idle => write!(f, "idle"),
+            TaskState::Running => write!(f, "running"),
+            TaskState::Crashed => write!(f, "crashed"),
+        }
+    }
+}
+
+/// The state of a task as shown by `/status` and the admin dashboard
+#[derive(Debug, Clone, Serialize)]
+pub struct TaskStatus {
+    pub name: &'static str,
+    pub schedule: String,
+    pub state: TaskState,
+    pub last_success: Option<chrono::DateTime<chrono::Utc>>,
+    pub last_error: Option<String>,
+    pub last_error_at: Option<chrono::DateTime<chrono::Utc>>,
+    /// Failed runs in a row
+    pub failures: i32,
+    /// Restarts after panics since the bot started
+    pub restarts: u32,
+    pub next_run: Option<chrono::DateTime<chrono::Utc>>,
+}
+
+/// The tasks of the bot, shared between the bot and the web interface
+///
+/// Every task is registered once, so a second `Ready` event after a reconnect does not start
+/// duplicates of tasks that are already supervised.
+#[derive(Clone, Default)]
+pub struct TaskRegistry {
+    tasks: Arc<std::sync::RwLock<BTreeMap<&'static str, TaskStatus>>>,
+}
+
+impl TaskRegistry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// All tasks ordered by name
+    pub fn list(&self) -> Vec<TaskStatus> {
+        self.tasks
+            .read()
+            .expect("task registry lock poisoned")
+            .values()
+            .cloned()
+            .collect()
+    }
+
+    /// Registers a task, returns false if it is supervised already
+    fn claim(&self, task: &Task) -> bool {
+        let mut tasks = self.tasks.write().expect("task registry lock poisoned");
+        if tasks.contains_key(task.name) {
+            return false;
+        }
+
+        tasks.insert(
+            task.name,
+            TaskStatus {
+                name: task.name,
+                schedule: task.schedule.to_string(),
+                state: TaskState::Idle,
+                last_success: None,
+                last_error: None,
+                last_error_at: None,
+                failures: 0,
+                restarts: 0,
+                next_run: None,
+            },
+        );
+        true
+    }
+
+    fn update(&self, name: &str, f: impl FnOnce(&mut TaskStatus)) {
+        if let Some(status) = self
+            .tasks
+            .write()
+            .expect("task registry lock poisoned")
+            .get_mut(name)
+        {
+            f(status);
+        }
+    }
+}
+
+/// The runs of a task in one guild
+#[derive(Debug, Default)]
+struct GuildRun {
+    last_success: Option<chrono::DateTime<chrono::Utc>>,
+    failures: u32,
+    retry_at: Option<chrono::DateTime<chrono::Utc>>,
+}
+
+/// When the tasks that work through every guild last ran in each of them
+///
+/// These tasks tick every minute and only check the guilds whose interval has passed, so changed
+/// settings apply from the next tick on. A guild that fails doesn't fail the tick, it is retried
+/// on its own with backoff while the other guilds keep their interval. The runs are only kept in
+/// memory, every guild is checked once after startup.
+#[derive(Clone, Default)]
+pub struct GuildTimers {
+    runs: Arc<DashMap<(&'static str, serenity::GuildId), GuildRun>>,
+}
+
+impl GuildTimers {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Whether the task should run in the guild, i.e. `interval` has passed since its last
+    /// successful run there or a failed run is due for a retry
+    pub fn due(
+        &self,
+        task: &'static str,
+        guild_id: serenity::GuildId,
+        interval: chrono::Duration,
+    ) -> bool {
+        let now = chrono::Utc::now();
+        self.runs
+            .get(&(task, guild_id))
+            .is_none_or(|run| match run.retry_at {
+                Some(retry_at) => now >= retry_at,
+                None => run.last_success.is_none_or(|last| now - last >= interval),
+            })
+    }
+
+    /// Records the result of a task in a guild
+    ///
+    /// Failures are logged and retried after 1, 2, 4, ... minutes, but at least every `interval`
+    /// and once an hour. Only a guild starting to fail and recovering are reported in the logs
+    /// channel, not every failed retry in between.
+    pub async fn record(
+        &self,
+        ctx: &serenity::Context,
+        data: &Data,
+        task: &'static str,
+        guild_id: serenity::GuildId,
+        interval: chrono::Duration,
+        result: Result<(), Error>,
+    ) {
+        let now = chrono::Utc::now();
+        // the entry must not be held while reporting
+        let message = {
+            let mut run = self.runs.entry((task, guild_id)).or_default();
+            match result {
+                Ok(()) => {
+                    let recovered = run.failures;
+                    *run = GuildRun {
+                        last_success: Some(now),
+                        ..Default::default()
+                    };
+                    (recovered > 0).then(|| {
+                        format!(
+                            "Task {} recovered in {} after {} failed runs",
+                            task, guild_id.0, recovered
+                        )
+                    })
+                }
+                Err(why) => {
+                    run.failures += 1;
+                    let backoff = chrono::Duration::minutes(
+                        2i64.saturating_pow((run.failures - 1).min(16))
+                            .min(MAX_BACKOFF_MINUTES),
+                    )
+                    .min(interval);
+                    run.retry_at = Some(now + backoff);
+
+                    tracing::error!(
+                        "Task {} failed in {} ({} in a row): {}",
+                        task,
+                        guild_id.0,
+                        run.failures,
+                        why
+                    );
+                    (run.failures == 1).then(|| {
+                        format!(
+                            "Task {} failed in {}, retrying with backoff until it works again: {}",
+                            task, guild_id.0, why
+                        )
+                    })
+                }
+            }
+        };
+
+        if let Some(message) = message {
+            report(ctx, data, message).await;
+        }
+    }
+}
+
+/// Runs the recurring tasks of the bot
+///
+/// The last successful run of every task is stored in `scheduled_tasks`, so a run that was missed
+/// while the bot was offline is caught up (once) right after startup. Tasks that never ran are due
+/// immediately. Failed runs are retried with exponential backoff, the first failure and the
+/// recovery are reported in the logs channel. Tasks working through every guild handle failures
+/// of a single guild with [`GuildTimers`] and only fail as a whole, e.g. when the database is down.
+/// Every task runs under a supervisor that restarts it with backoff when it panics.
+#[derive(Default)]
+pub struct Scheduler {
+    tasks: Vec<Task>,
+}
+
+impl Scheduler {
+    pub fn new() -> Self {
+        Self { tasks: Vec::new() }
+    }
+
+    /// Registers a task, `name` identifies its runs in the database so it must stay stable
+    pub fn task<F, Fut>(mut self, name: &'static str, schedule: Schedule, run: F) -> Self
+    where
+        F: Fn(serenity::Context, Data) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Result<(), Error>> + Send + 'static,
+    {
+        self.tasks.push(Task {
+            name,
+            schedule,
+            run: Arc::new(move |ctx, data| Box::pin(run(ctx, data))),
+        });
+        self
+    }
+
+    /// Spawns every registered task that isn't running yet, each one runs independently of the others
+    pub fn start(self, ctx: serenity::Context, data: Data) {
+        for task in self.tasks {
+            if !data.tasks.claim(&task) {
+                info!("Task {} is running already", task.name);
+                continue;
+            }
+
+            info!("Scheduling task {} ({})", task.name, task.schedule);
+            let ctx = ctx.clone();
+            let data = data.clone();
+            tokio::spawn(async move {
+                supervise(task, ctx, data).await;
+            });
+        }
+    }
+}
+
+/// Runs a task and restarts it whenever it panics
+async fn supervise(task: Task, ctx: serenity::Context, data: Data) {
+    let mut crashes: u32 = 0;
+
+    loop {
+        let started = std::time::Instant::now();
+        let instance = tokio::spawn(run_task(task.clone(), ctx.clone(), data.clone()));
+        let why = match instance.await {
+            Ok(()) => return,
+            Err(why) if why.is_panic() => panic_message(why.into_panic()),
+            Err(why) => why.to_string(),
+        };
+
+        // a task that ran fine for a while is restarted quickly again
+        if started.elapsed().as_secs() > MAX_RESTART_DELAY_SECS {
+            crashes = 0;
+        }
+        crashes += 1;
+        let delay = (RESTART_DELAY_SECS << (crashes - 1).min(16)).min(MAX_RESTART_DELAY_SECS);
+
+        let now = chrono::Utc::now();
+        data.tasks.update(task.name, |status| {
+            status.state = TaskState::Crashed;
+            status.restarts += 1;
+            status.last_error = Some(format!("crashed: {}", why));
+            status.last_error_at = Some(now);
+            status.next_run = Some(now + chrono::Duration::seconds(delay as i64));
+        });
+
+        tracing::error!(
+            "Task {} crashed, restarting in {}s: {}",
+            task.name,
+            delay,
+            why
+        );
+        report(
+            &ctx,
+            &data,
+            format!(
+                "Task {} crashed, restarting in {} seconds: {}",
+                task.name, delay, why
+            ),
+        )
+        .await;
+
+        tokio::time::sleep(std::time::Duration::from_secs(delay)).await;
+    }
+}
+
+fn panic_message(payload: Box<dyn std::any::Any + Send>) -> String {
+    match payload.downcast::<String>() {
+        Ok(message) => *message,
+        Err(payload) => payload
+            .downcast_ref::<&str>()
+            .map(|message| message.to_string())
+            .unwrap_or_else(|| "panicked".to_string()),
+    }
+}
+
+async fn run_task(task: Task, ctx: serenity::Context, data: Data) {
+    let state = match load_state(&data.db, task.name).await {
+        Ok(state) => state,
+        Err(why) => {
+            tracing::error!("Failed to load last run of task {}: {}", task.name, why);
+            None
+        }
+    };
+    let mut last_run = state
+        .as_ref()
+        .and_then(|s| s.last_run)
+        .map(|t| t.with_timezone(&chrono::Local));
+    let mut failures = state.as_ref().map(|s| s.failures).unwrap_or(0);
+    let mut retry_at = None;
+
+    data.tasks.update(task.name, |status| {
+        status.last_success = last_run.map(|t| t.with_timezone(&chrono::Utc));
+        status.failures = failures;
+        if status.last_error.is_none() {
+            status.last_error = state.and_then(|s| s.last_error);
+        }
+    });
+
+    loop {
+        let now = chrono::Local::now();
+        let due = retry_at.unwrap_or_else(|| match last_run {
+            Some(last) => task.schedule.next_after(last),
+            None => now,
+        });
+        data.tasks.update(task.name, |status| {
+            status.state = TaskState::Idle;
+            status.next_run = Some(due.with_timezone(&chrono::Utc));
+        });
+        if let Ok(wait) = (due - now).to_std() {
+            tokio::time::sleep(wait).await;
+        }
+
+        let started = chrono::Local::now();
+        data.tasks
+            .update(task.name, |status| status.state = TaskState::Running);
+        let result = (task.run)(ctx.clone(), data.clone()).await;
+
+        let error = match result {
+            Ok(()) => {
+                if failures > 0 {
+                    report(
+                        &ctx,
+                        &data,
+                        format!(
+                            "Task {} recovered after {} failed runs",
+                            task.name, failures
+                        ),
+                    )
+                    .await;
+                }
+                last_run = Some(started);
+                failures = 0;
+                retry_at = None;
+                data.tasks.update(task.name, |status| {
+                    status.last_success = Some(started.with_timezone(&chrono::Utc));
+                    status.failures = 0;
+                });
+                None
+            }
+            Err(why) => {
+                failures += 1;
+                let backoff = chrono::Duration::minutes(
+                    2i64.saturating_pow((failures - 1).min(16) as u32)
+                        .min(MAX_BACKOFF_MINUTES),
+                );
+                retry_at = Some(chrono::Local::now() + backoff);
+                data.tasks.update(task.name, |status| {
+                    status.last_error = Some(why.to_string());
+                    status.last_error_at = Some(chrono::Utc::now());
+                    status.failures = failures;
+                });
+
+                tracing::error!("Task {} failed ({} in a row): {}", task.name, failures, why);
+                // the retries are only logged, the recovery is reported again
+                if failures == 1 {
+                    report(
+                        &ctx,
+                        &data,
+                        format!(
+                            "Task {} failed, retrying in {} minutes with backoff: {}",
+                            task.name,
+                            backoff.num_minutes(),
+                            why
+                        ),
+                    )
+                    .await;
+                }
+                Some(why.to_string())
+            }
+        };
+
+        if let Err(why) = store_state(
+            &data.db,
+            task.name,
+            last_run.map(|t| t.with_timezone(&chrono::Utc)),
+            failures,
+            error,
+        )
+        .await
+        {
+            tracing::error!("Failed to store last run of task {}: {}", task.name, why);
+        }
+    }
+}
+
+async fn load_state(
+    db: &sqlx::PgPool,
+    name: &str,
+) -> Result<Option<structs::ScheduledTask>, Error> {
+    sqlx::query_as::<sqlx::Postgres, structs::ScheduledTask>(
+        "SELECT * FROM scheduled_tasks WHERE name = $1",
+    )
+    .bind(name)
+    .fetch_optional(db)
+    .await
+    .map_err(Error::Database)
+}
+
+async fn store_state(
+    db: &sqlx::PgPool,
+    name: &str,
+    last_run: Option<chrono::DateTime<chrono::Utc>>,
+    failures: i32,
+    last_error: Option<String>,
+) -> Result<(), Error> {
+    sqlx::query(
+        "INSERT INTO scheduled_tasks (name, last_run, failures, last_error) VALUES ($1, $2, $3, $4)
+        ON CONFLICT (name) DO UPDATE SET last_run = $2, failures = $3,
+        last_error = COALESCE($4, scheduled_tasks.last_error)",
+    )
+    .bind(name)
+    .bind(last_run)
+    .bind(failures)
+    .bind(last_error)
+    .execute(db)
+    .await
+    .map_err(Error::Database)?;
+
+    Ok(())
+}
+
+/// Tells the admins about failing tasks in the logs channel of config.json
+async fn report(ctx: &serenity::Context, data: &Data, message: String) {
+    // discord rejects messages longer than 2000 characters
+    let message = message.chars().take(2000).collect::<String>();
+    if let Err(why) = data
+        .config
+        .seed()
+        .channels
+        .logs
+        .say(ctx, message)
+        .await
+        .map_err(Error::Serenity)
+    {
+        tracing::error!("Failed to log task status: {:?}", why);
+    }
+}
@@ -12,6 +12,7 @@ pub struct UserXP {
 pub struct VerifiedUsers {
     pub user_id: i64,
     pub user_email: String,
+    pub last_verified_at: Option<chrono::DateTime<chrono::Utc>>,
 }
 
 #[allow(unused)]
@@ -26,8 +27,62 @@ pub struct VoiceChannels {
 #[derive(Debug, FromRow)]
 pub struct Mensaplan {
     pub date: String,
+    pub guild_id: i64,
+    pub canteen: String,
     pub posted: bool,
     pub plan_hash: String,
+    pub channel_id: Option<i64>,
+    pub message_id: Option<i64>,
+    /// Monday of the week the plan covers, unknown if it couldn't be parsed
+    pub week_start: Option<chrono::NaiveDate>,
+}
+
+#[allow(unused)]
+#[derive(Debug, FromRow)]
+pub struct MensaplanDish {
+    pub dish_id: i32,
+    pub plan_hash: String,
+    pub day: chrono::NaiveDate,
+    pub position: i32,
+    pub name: String,
+    pub prices: Vec<f64>,
+    pub allergens: Vec<String>,
+    pub vegetarian: bool,
+    pub vegan: bool,
+}
+
+#[allow(unused)]
+#[derive(Debug, FromRow)]
+pub struct MensaplanWeek {
+    pub url: String,
+    pub week_start: chrono::NaiveDate,
+    pub plan_hash: String,
+    pub fetched_at: chrono::DateTime<chrono::Utc>,
+}
+
+#[allow(unused)]
+#[derive(Debug, FromRow)]
+pub struct MensaplanFetch {
+    pub url: String,
+    pub plan_hash: String,
+    pub fetched_at: chrono::DateTime<chrono::Utc>,
+}
+
+#[allow(unused)]
+#[derive(Debug, FromRow)]
+pub struct MensaSubscription {
+    pub user_id: i64,
+    pub guild_id: Option<i64>,
+    pub notify_at: chrono::NaiveTime,
+    pub last_sent: Option<chrono::NaiveDate>,
+}
+
+#[allow(unused)]
+#[derive(Debug, FromRow)]
+pub struct MensaKeyword {
+    pub user_id: i64,
+    pub keyword: String,
+    pub guild_id: Option<i64>,
 }
 
 #[allow(unused)]
@@ -68,8 +123,87 @@ pub struct Rss {
     pub channel_id: i64,
 }
 
-#[derive(Debug, Clone)]
-pub struct CodeEmailPair {
-    pub code: String,
-    pub email: String,
-}
\ No newline at end of file
+#[allow(unused)]
+#[derive(Debug, Clone, FromRow)]
+pub struct RssFeed {
+    pub channel_id: i64,
+    pub url: String,
+    pub last_guid: Option<String>,
+    pub last_pub_date: Option<chrono::DateTime<chrono::Utc>>,
+    pub etag: Option<String>,
+    pub last_modified: Option<String>,
+    pub checked_at: Option<chrono::DateTime<chrono::Utc>>,
+    pub guild_id: Option<i64>,
+    pub template: Option<String>,
+    pub mention_role: Option<i64>,
+}
+
+/// Time a user spent in the voice channels of a guild without leaving, open while `ended_at` is empty
+#[allow(unused)]
+#[derive(Debug, FromRow)]
+pub struct VoiceSession {
+    pub session_id: i32,
+    pub guild_id: i64,
+    pub user_id: i64,
+    pub channel_id: i64,
+    pub started_at: chrono::DateTime<chrono::Utc>,
+    pub last_seen_at: chrono::DateTime<chrono::Utc>,
+    pub ended_at: Option<chrono::DateTime<chrono::Utc>>,
+    pub active_minutes: f64,
+    pub xp: f64,
+}
+
+/// An episode or video of the podcast feed, `message_id` is empty for the ones that were
+/// published before the feed was first checked
+#[allow(unused)]
+#[derive(Debug, FromRow)]
+pub struct PodcastEpisode {
+    pub guild_id: i64,
+    pub feed_url: String,
+    pub episode_key: String,
+    pub title: Option<String>,
+    pub message_id: Option<i64>,
+    pub posted_at: chrono::DateTime<chrono::Utc>,
+}
+
+#[allow(unused)]
+#[derive(Debug, FromRow)]
+pub struct PendingVerification {
+    pub user_id: i64,
+    pub user_email: String,
+    pub email_hash: String,
+    pub code_hash: String,
+    pub created_at: chrono::DateTime<chrono::Utc>,
+    pub attempts: i32,
+}
+#[allow(unused)]
+#[derive(Debug, FromRow)]
+pub struct ReverificationCampaign {
+    pub campaign_id: i32,
+    pub guild_id: i64,
+    pub cutoff: chrono::NaiveDate,
+    pub deadline: chrono::DateTime<chrono::Utc>,
+    pub remove_role: bool,
+    pub reminder_sent: bool,
+    pub finished: bool,
+    pub created_at: chrono::DateTime<chrono::Utc>,
+}
+
+#[allow(unused)]
+#[derive(Debug, FromRow)]
+pub struct ReverificationRequest {
+    pub campaign_id: i32,
+    pub user_id: i64,
+    pub completed_at: Option<chrono::DateTime<chrono::Utc>>,
+    /// The reverification DM couldn't be delivered, e.g. because the member has DMs disabled
+    pub dm_failed: bool,
+}
+
+#[allow(unused)]
+#[derive(Debug, FromRow)]
+pub struct ScheduledTask {
+    pub name: String,
+    pub last_run: Option<chrono::DateTime<chrono::Utc>>,
+    pub failures: i32,
+    pub last_error: Option<String>,
+}
@@ -3,20 +3,20 @@
 use std::sync::Arc;
 
 use crate::{
-    config::FacultyManagerMealplanConfig,
+    config::{FacultyManagerConfig, FacultyManagerMealplanConfig},
     prelude::Error,
+    scheduler::Schedule,
     structs::{self},
     Data,
 };
-use chrono::{Datelike, Timelike};
 use influxdb2::models::DataPoint;
 use poise::serenity_prelude::{self as serenity, Mentionable, ShardId};
-use rss::Channel;
 use tracing::info;
 
 struct TaskConfig {
+    pub canteen: String,
+    pub url: String,
     pub notify_role: serenity::RoleId,
-    pub post_mealplan: bool,
     pub post_on_day: chrono::Weekday,
     pub post_at: chrono::NaiveTime,
     pub mealplan_settings: FacultyManagerMealplanConfig,
@@ -24,212 +24,527 @@ struct TaskConfig {
 }
 
 struct TaskConfigRss {
-    pub map: std::collections::HashMap<serenity::ChannelId, String>,
     pub clean_regex: regex::Regex,
-    pub timeout_hrs: u64,
 }
 
-/// Posts the mensa plan for the current week
+/// Posts the mensa plan of every canteen for the current week in every guild that has it enabled
+///
+/// Runs every `mealplan.check` minutes, when the hash of the PDF changes after the plan was
+/// posted this week the new plan is posted as a reply to the original message
 pub async fn post_mensaplan(ctx: serenity::Context, data: Data) -> Result<(), Error> {
-    let task_conf = TaskConfig {
-        notify_role: data.config.roles.mealplannotify,
-        post_mealplan: data.config.mealplan.post_mealplan,
-        post_on_day: data.config.mealplan.post_on_day,
-        post_at: data.config.mealplan.post_at_hour,
-        mealplan_settings: data.config.mealplan.clone(),
-        post_channel: data.config.channels.mealplan,
-    };
+    // guilds sharing a plan url only download it once per check
+    let mut plans: std::collections::HashMap<String, Vec<u8>> = std::collections::HashMap::new();
+
+    for guild_id in data.config.guilds().await? {
+        let config = data.config.get(guild_id).await?;
+        let interval = chrono::Duration::minutes(config.mealplan.check as i64);
+        if !config.mealplan.post_mealplan || !data.timers.due("mensaplan", guild_id, interval) {
+            continue;
+        }
+
+        let mut failed = Vec::new();
+        for canteen in config.canteens() {
+            let task_conf = TaskConfig {
+                canteen: canteen.name,
+                url: canteen.url,
+                notify_role: canteen.notify_role,
+                post_on_day: canteen.post_on_day,
+                post_at: canteen.post_at_hour,
+                mealplan_settings: config.mealplan.clone(),
+                post_channel: canteen.channel,
+            };
+
+            let url = &task_conf.url;
+            if !plans.contains_key(url) {
+                match crate::utils::download_mensaplan(url).await {
+                    Ok(pdf) => {
+                        plans.insert(url.clone(), pdf);
+                    }
+                    Err(why) => {
+                        failed.push(format!("download of {} ({})", url, why));
+                        continue;
+                    }
+                }
+            }
+
+            if let Err(why) =
+                post_mensaplan_in_guild(&ctx, &data, guild_id, &task_conf, &plans[url]).await
+            {
+                failed.push(format!("{} ({})", task_conf.canteen, why));
+            }
+        }
+
+        let result = guild_result("Failed to post", failed);
+        data.timers.record(&ctx, &data, "mensaplan", guild_id, interval, result).await;
+    }
+
+    Ok(())
+}
+
+/// The result of a task in a guild, failing with everything that failed in it
+fn guild_result(message: &str, failed: Vec<String>) -> Result<(), Error> {
+    if failed.is_empty() {
+        Ok(())
+    } else {
+        Err(Error::WithMessage(format!("{} {}", message, failed.join(", "))))
+    }
+}
+
+async fn post_mensaplan_in_guild(
+    ctx: &serenity::Context,
+    data: &Data,
+    guild_id: serenity::GuildId,
+    task_conf: &TaskConfig,
+    pdf: &[u8],
+) -> Result<(), Error> {
+    let now = chrono::Local::now();
+    let today = now.date_naive().format("%Y-%m-%d").to_string();
+    let plan_hash = crate::utils::mensaplan_hash(pdf);
+
+    // the plan posted since the most recent posting time, if there is none the posting was
+    // missed (or is due right now) and is caught up
+    let last_slot = Schedule::Weekly(task_conf.post_on_day, task_conf.post_at)
+        .next_after(now - chrono::Duration::weeks(1));
+    let since = last_slot.date_naive().format("%Y-%m-%d").to_string();
+    let posted = sqlx::query_as::<sqlx::Postgres, structs::Mensaplan>(
+        "SELECT * FROM mensaplan WHERE guild_id = $1 AND canteen = $2 AND date >= $3 AND posted
+        ORDER BY date DESC LIMIT 1",
+    )
+    .bind(guild_id.0 as i64)
+    .bind(&task_conf.canteen)
+    .bind(&since)
+    .fetch_optional(&data.db)
+    .await
+    .map_err(Error::Database)?;
+
+    match posted {
+        Some(posted) if posted.plan_hash == plan_hash => {
+            info!("Mensaplan of {} in {} is up to date", task_conf.canteen, guild_id);
+        }
+        // plans posted before hashes were stored, nothing to compare against
+        Some(posted) if posted.plan_hash.is_empty() => {
+            set_mensaplan_hash(data, guild_id, &posted, &plan_hash, None).await?;
+        }
+        Some(posted) => {
+            let menus =
+                plan_menus(data, &task_conf.url, pdf, &plan_hash).await;
+            let week = menus.first().map(|menu| crate::mensaplan::week_start(menu.date));
+            // the plan of another week replaced the posted one, it is posted at the next
+            // posting time instead of being announced as a correction
+            if posted.week_start.is_some() && week.is_some() && posted.week_start != week {
+                info!(
+                    "Mensaplan of {} in {} covers another week now, waiting for the posting time",
+                    task_conf.canteen, guild_id
+                );
+                return Ok(());
+            }
+
+            info!(
+                "Mensaplan of {} in {} changed, posting update",
+                task_conf.canteen, guild_id
+            );
+            let mensa_plan =
+                crate::utils::render_mensaplan(pdf, &task_conf.mealplan_settings.imgsettings)
+                    .await?;
 
-    loop {
-        let now = chrono::Local::now();
-        let weekday = now.weekday();
-        let hour = now.hour();
+            let channel = posted
+                .channel_id
+                .map(|id| serenity::ChannelId(id as u64))
+                .unwrap_or(task_conf.post_channel);
 
-        if weekday == task_conf.post_on_day && hour == task_conf.post_at.hour() {
-            let mensa_plan = crate::utils::fetch_mensaplan(&task_conf.mealplan_settings.url)
+            channel
+                .send_message(&ctx, |f| {
+                    if let Some(message_id) = posted.message_id {
+                        f.reference_message((channel, serenity::MessageId(message_id as u64)))
+                            .allowed_mentions(|a| a.replied_user(false));
+                    }
+                    if !menus.is_empty() {
+                        f.embed(|e| {
+                            crate::mensaplan::create_embed(e, &menus).title(&task_conf.canteen)
+                        });
+                    }
+                    f.content(format!("📢 Der Mensaplan ({}) wurde aktualisiert", task_conf.canteen))
+                        .add_files(crate::utils::mensaplan_attachments(&mensa_plan))
+                })
                 .await
-                .unwrap();
-            let today = now.date_naive().format("%Y-%m-%d").to_string();
+                .map_err(Error::Serenity)?;
 
-            let mensaplan_posted = sqlx::query_as::<sqlx::Postgres, structs::Mensaplan>(
-                "SELECT * FROM mensaplan WHERE date = $1",
-            )
-            .bind(&today)
-            .fetch_optional(&data.db)
-            .await
-            .map_err(Error::Database)
-            .unwrap()
-            .map(|row| row.posted)
-            .unwrap_or(false);
-
-            if mensaplan_posted {
-                info!("Mensaplan already posted today");
-            } else {
-                let mut channel = task_conf.post_channel;
-                let mut msg = channel
-                    .send_message(&ctx, |f| {
-                        f.content(format!("{}", task_conf.notify_role.mention()))
-                            .add_file(serenity::AttachmentType::Bytes {
-                                data: std::borrow::Cow::Borrowed(&mensa_plan),
-                                filename: "mensaplan.png".to_string(),
-                            })
-                            .components(|c| {
-                                c.create_action_row(|r| {
-                                    r.create_button(|b| {
-                                        b.style(serenity::ButtonStyle::Primary)
-                                            .label("Get Notified on new plans!")
-                                            .emoji(serenity::ReactionType::Custom {
-                                                animated: false,
-                                                id: serenity::EmojiId(960491878048993300),
-                                                name: Some("gulasch".to_string()),
-                                            })
-                                            .custom_id("mensaplan_notify_button")
-                                    })
+            set_mensaplan_hash(data, guild_id, &posted, &plan_hash, week).await?;
+        }
+        None => {
+            let mensa_plan =
+                crate::utils::render_mensaplan(pdf, &task_conf.mealplan_settings.imgsettings)
+                    .await?;
+            let menus =
+                plan_menus(data, &task_conf.url, pdf, &plan_hash).await;
+            let week = menus.first().map(|menu| crate::mensaplan::week_start(menu.date));
+
+            let mut channel = task_conf.post_channel;
+            let mut msg = channel
+                .send_message(&ctx, |f| {
+                    if !menus.is_empty() {
+                        f.embed(|e| {
+                            crate::mensaplan::create_embed(e, &menus).title(&task_conf.canteen)
+                        });
+                    }
+                    f.content(format!("{}", task_conf.notify_role.mention()))
+                        .add_files(crate::utils::mensaplan_attachments(&mensa_plan))
+                        .components(|c| {
+                            c.create_action_row(|r| {
+                                r.create_button(|b| {
+                                    b.style(serenity::ButtonStyle::Primary)
+                                        .label("Get Notified on new plans!")
+                                        .emoji(serenity::ReactionType::Custom {
+                                            animated: false,
+                                            id: serenity::EmojiId(960491878048993300),
+                                            name: Some("gulasch".to_string()),
+                                        })
+                                        .custom_id("mensaplan_notify_button")
+                                })
+                                .create_button(|b| {
+                                    b.style(serenity::ButtonStyle::Secondary)
+                                        .label("Daily menu via DM")
+                                        .custom_id("mensa_subscribe")
                                 })
                             })
-                    })
-                    .await
-                    .map_err(Error::Serenity);
+                        })
+                })
+                .await
+                .map_err(Error::Serenity);
 
-                if let Ok(msg) = &mut msg {
-                    if let Err(e) = msg.crosspost(&ctx).await.map_err(Error::Serenity) {
-                        tracing::error!("Failed to crosspost mensaplan: {:?}", e);
-                    }
+            if let Ok(msg) = &mut msg {
+                if let Err(e) = msg.crosspost(&ctx).await.map_err(Error::Serenity) {
+                    tracing::error!("Failed to crosspost mensaplan: {:?}", e);
                 }
-
-                let sql_res = sqlx::query("INSERT INTO mensaplan (date, posted) VALUES ($1, $2)")
-                    .bind(&today)
-                    .bind(true)
-                    .execute(&data.db)
-                    .await
-                    .map_err(Error::Database);
             }
-        } else {
-            info!("Not posting mensaplan today");
+
+            let message_id = msg.as_ref().ok().map(|m| m.id.0 as i64);
+            sqlx::query(
+                "INSERT INTO mensaplan (date, guild_id, canteen, posted, plan_hash, channel_id, message_id, week_start)
+                VALUES ($1, $2, $3, $4, $5, $6, $7, $8)",
+            )
+            .bind(&today)
+            .bind(guild_id.0 as i64)
+            .bind(&task_conf.canteen)
+            .bind(true)
+            .bind(&plan_hash)
+            .bind(channel.0 as i64)
+            .bind(message_id)
+            .bind(week)
+            .execute(&data.db)
+            .await
+            .map_err(Error::Database)?;
         }
+    }
+
+    Ok(())
+}
 
-        info!("Sleeping for 5 minutes");
-        tokio::time::sleep(tokio::time::Duration::from_secs(
-            data.config.mealplan.check * 60,
-        ))
-        .await;
+/// The parsed menus of a plan, the image is still posted when parsing fails
+async fn plan_menus(
+    data: &Data,
+    url: &str,
+    pdf: &[u8],
+    plan_hash: &str,
+) -> Vec<crate::mensaplan::DailyMenu> {
+    match crate::mensaplan::menus(&data.db, url, pdf, plan_hash).await {
+        Ok(menus) => menus,
+        Err(why) => {
+            tracing::error!("Failed to parse mensaplan: {}", why);
+            Vec::new()
+        }
     }
 }
 
-pub async fn post_rss(ctx: serenity::Context, data: Data) -> Result<(), Error> {
-    let conf = TaskConfigRss {
-        map: data.config.rss_settings.rss_feed_data,
-        clean_regex: regex::Regex::new(r"\\n(if wk med|all)").unwrap(),
-        timeout_hrs: data.config.rss_settings.rss_check_interval_hours,
+async fn set_mensaplan_hash(
+    data: &Data,
+    guild_id: serenity::GuildId,
+    posted: &structs::Mensaplan,
+    plan_hash: &str,
+    week: Option<chrono::NaiveDate>,
+) -> Result<(), Error> {
+    sqlx::query(
+        "UPDATE mensaplan SET plan_hash = $1, week_start = COALESCE($5, week_start)
+        WHERE date = $2 AND guild_id = $3 AND canteen = $4",
+    )
+    .bind(plan_hash)
+    .bind(&posted.date)
+    .bind(guild_id.0 as i64)
+    .bind(&posted.canteen)
+    .bind(week)
+    .execute(&data.db)
+    .await
+    .map_err(Error::Database)?;
+
+    Ok(())
+}
+
+/// Sends the daily mensa DMs to subscribers whose time has come
+pub async fn mensa_daily_menus(ctx: serenity::Context, data: Data) -> Result<(), Error> {
+    // plans of the guilds' urls, shared by everyone handled in this run
+    let mut weeks = std::collections::HashMap::new();
+    send_daily_menus(&ctx, &data, &mut weeks).await
+}
+
+/// Sends alerts for dishes matching a user's keywords
+pub async fn mensa_dish_alerts(ctx: serenity::Context, data: Data) -> Result<(), Error> {
+    let mut weeks = std::collections::HashMap::new();
+    send_dish_alerts(&ctx, &data, &mut weeks).await
+}
+
+/// The menus of this week for the mensaplan url of a guild, falling back to config.json
+async fn subscription_menus(
+    data: &Data,
+    weeks: &mut std::collections::HashMap<String, Vec<crate::mensaplan::DailyMenu>>,
+    guild_id: Option<i64>,
+) -> Result<Vec<crate::mensaplan::DailyMenu>, Error> {
+    let config = data
+        .config
+        .resolve(guild_id.map(|g| serenity::GuildId(g as u64)))
+        .await?;
+    // subscriptions get the plan of the first canteen
+    let url = match config.canteen(None) {
+        Some(canteen) => canteen.url,
+        None => return Ok(Vec::new()),
     };
-    let db = data.db.clone();
 
-    loop {
-        for (channel_id, feed_url) in conf.map.iter() {
-            let channel = fetch_feed(feed_url).await.unwrap();
-            let items = channel.items();
+    if !weeks.contains_key(&url) {
+        let today = chrono::Local::now().date_naive();
+        // a failed download is only retried in the next run
+        let menus = match crate::mensaplan::week_menus(&data.db, &url, today).await {
+            Ok(menus) => menus,
+            Err(why) => {
+                tracing::error!("Failed to get mensaplan from {}: {}", url, why);
+                Vec::new()
+            }
+        };
+        weeks.insert(url.clone(), menus);
+    }
 
-            tracing::info!("Checking up on {} rss items", items.len());
+    Ok(weeks[&url].clone())
+}
 
-            for item in items {
-                let title = item.title().unwrap();
-                let link = item.link().unwrap();
-                let description = item.description().unwrap();
-                let date_ = item.pub_date().unwrap();
+async fn send_daily_menus(
+    ctx: &serenity::Context,
+    data: &Data,
+    weeks: &mut std::collections::HashMap<String, Vec<crate::mensaplan::DailyMenu>>,
+) -> Result<(), Error> {
+    let now = chrono::Local::now();
+    let today = now.date_naive();
 
-                // parse to chrono Local
-                let date = chrono::DateTime::parse_from_rfc2822(date_).unwrap();
+    let due = sqlx::query_as::<sqlx::Postgres, structs::MensaSubscription>(
+        "SELECT * FROM mensa_subscriptions WHERE notify_at <= $1 AND (last_sent IS NULL OR last_sent < $2)",
+    )
+    .bind(now.time())
+    .bind(today)
+    .fetch_all(&data.db)
+    .await
+    .map_err(Error::Database)?;
 
-                // to combat spam, filter out old items (all before July 11th 2024)
-                let cutoff_date = chrono::DateTime::parse_from_rfc2822("Sat, 27 Sep 2025 00:00:00 +0200").unwrap();
-                if date < cutoff_date {
-                    continue;
-                } else {
-                    tracing::info!("Found new rss item");
-                }
+    for subscription in due {
+        let user_id = serenity::UserId(subscription.user_id as u64);
+        let menus = subscription_menus(data, weeks, subscription.guild_id).await?;
 
-                let sql_res = sqlx::query_as::<sqlx::Postgres, structs::Rss>(
-                    "SELECT * FROM posted_rss WHERE rss_title = $1 AND channel_id = $2",
+        // days without a plan, e.g. weekends and holidays, are skipped silently
+        if let Some(menu) = menus
+            .iter()
+            .find(|menu| menu.date == today && !menu.dishes.is_empty())
+        {
+            if let Err(why) = crate::mensaplan::send_daily_dm(ctx, user_id, menu).await {
+                tracing::error!("Failed to send daily mensa DM to {}: {}", user_id, why);
+            }
+        }
+
+        sqlx::query("UPDATE mensa_subscriptions SET last_sent = $1 WHERE user_id = $2")
+            .bind(today)
+            .bind(subscription.user_id)
+            .execute(&data.db)
+            .await
+            .map_err(Error::Database)?;
+    }
+
+    Ok(())
+}
+
+async fn send_dish_alerts(
+    ctx: &serenity::Context,
+    data: &Data,
+    weeks: &mut std::collections::HashMap<String, Vec<crate::mensaplan::DailyMenu>>,
+) -> Result<(), Error> {
+    let today = chrono::Local::now().date_naive();
+
+    let keywords = sqlx::query_as::<sqlx::Postgres, structs::MensaKeyword>(
+        "SELECT * FROM mensa_keywords",
+    )
+    .fetch_all(&data.db)
+    .await
+    .map_err(Error::Database)?;
+
+    for keyword in keywords {
+        let user_id = serenity::UserId(keyword.user_id as u64);
+        let menus = subscription_menus(data, weeks, keyword.guild_id).await?;
+        let needle = keyword.keyword.to_lowercase();
+
+        let mut matches = Vec::new();
+        for menu in menus.iter().filter(|menu| menu.date >= today) {
+            for dish in menu
+                .dishes
+                .iter()
+                .filter(|dish| dish.name.to_lowercase().contains(&needle))
+            {
+                // every dish is only announced once, even if it matches several keywords
+                let inserted = sqlx::query(
+                    "INSERT INTO mensa_alerts (user_id, day, dish) VALUES ($1, $2, $3) ON CONFLICT DO NOTHING",
                 )
-                .bind(title)
-                .bind(channel_id.0 as i64)
-                .fetch_optional(&db)
+                .bind(keyword.user_id)
+                .bind(menu.date)
+                .bind(&dish.name)
+                .execute(&data.db)
                 .await
-                .map_err(Error::Database)
-                .unwrap();
-
-                if let Some(exists) = sql_res {
-                    info!("An already posted rss item");
-                    let curr_chan = channel_id;
-                    let msg = curr_chan
-                        .message(&ctx, exists.message_id as u64)
-                        .await
-                        .map_err(Error::Serenity)
-                        .unwrap();
-                    let embed = msg.embeds.first().unwrap();
-
-                    let this_date = embed
-                        .timestamp
-                        .as_ref()
-                        .unwrap()
-                        .parse::<chrono::DateTime<chrono::Local>>()
-                        .unwrap();
-                    let item_date = date.with_timezone(&chrono::Local);
-
-                    // compare dates and post update if newer
-                    if this_date < item_date {
-                        update_posts(
-                            &ctx,
-                            &db,
-                            channel_id,
-                            &conf,
-                            title,
-                            link,
-                            description,
-                            &item_date,
-                            &msg,
-                        )
-                        .await?;
-                    }
-                } else {
-                    // because let-else won't let me not return from this
-                    // post
-                    println!("Posting new rss item");
-                    if let Err(why) = post_item(
-                        &ctx,
-                        &db,
-                        channel_id,
-                        &conf,
-                        title,
-                        link,
-                        description,
-                        &date.with_timezone(&chrono::Local),
-                    )
-                    .await
-                    {
-                        tracing::error!("Failed to post rss: {:?}", why);
-                    }
+                .map_err(Error::Database)?;
+
+                if inserted.rows_affected() > 0 {
+                    matches.push((menu.date, dish.clone()));
                 }
+            }
+        }
+
+        if matches.is_empty() {
+            continue;
+        }
+
+        if let Err(why) =
+            crate::mensaplan::send_alert_dm(ctx, user_id, &keyword.keyword, &matches).await
+        {
+            tracing::error!("Failed to send mensa alert to {}: {}", user_id, why);
+        }
+    }
+
+    // alerts for past days are of no use anymore
+    sqlx::query("DELETE FROM mensa_alerts WHERE day < $1")
+        .bind(today - chrono::Duration::days(7))
+        .execute(&data.db)
+        .await
+        .map_err(Error::Database)?;
+
+    Ok(())
+}
+
+/// Posts new items of the rss feeds of every guild that has it enabled and updates changed ones
+pub async fn post_rss(ctx: serenity::Context, data: Data) -> Result<(), Error> {
+    let conf = TaskConfigRss {
+        clean_regex: crate::feeds::clean_regex()?,
+    };
 
-                tracing::info!("Posting in channel: {}", channel_id.0);
+    for guild_id in data.config.guilds().await? {
+        let config = data.config.get(guild_id).await?;
+        let interval = chrono::Duration::hours(config.rss_settings.rss_check_interval_hours as i64);
+        if !config.rss_settings.post_rss || !data.timers.due("rss", guild_id, interval) {
+            continue;
+        }
+
+        // feeds subscribed with /feeds and the ones from the settings, which are stored once checked
+        let mut feeds = std::collections::BTreeSet::new();
+        for feed in crate::feeds::guild_feeds(&data.db, guild_id).await? {
+            feeds.insert((serenity::ChannelId(feed.channel_id as u64), feed.url));
+        }
+        feeds.extend(config.rss_settings.rss_feed_data);
+
+        let mut failed = Vec::new();
+        for (channel_id, feed_url) in &feeds {
+            if let Err(why) =
+                post_feed(&ctx, &data.db, guild_id, channel_id, feed_url, &conf).await
+            {
+                failed.push(format!("{} ({})", feed_url, why));
             }
         }
 
-        info!("Sleeping for {} hours", conf.timeout_hrs);
-        tokio::time::sleep(tokio::time::Duration::from_secs(conf.timeout_hrs * 60 * 60)).await;
+        let result = guild_result("Failed to check", failed);
+        data.timers.record(&ctx, &data, "rss", guild_id, interval, result).await;
     }
+
+    Ok(())
 }
 
-async fn fetch_feed(feed: impl Into<String>) -> Result<Channel, Error> {
-    let bytestream = reqwest::get(feed.into())
-        .await
-        .map_err(Error::NetRequest)?
-        .bytes()
+/// Posts the items of a feed that are new since the last check
+async fn post_feed(
+    ctx: &serenity::Context,
+    db: &sqlx::PgPool,
+    guild_id: serenity::GuildId,
+    channel_id: &serenity::ChannelId,
+    feed_url: &str,
+    conf: &TaskConfigRss,
+) -> Result<(), Error> {
+    let state = crate::feeds::load(db, *channel_id, feed_url).await?;
+    let feed = match crate::feeds::fetch(feed_url, state.as_ref()).await? {
+        Some(feed) => feed,
+        None => {
+            info!("Rss feed {} did not change", feed_url);
+            return Ok(());
+        }
+    };
+
+    let state = match state {
+        Some(state) => state,
+        None => {
+            // a feed starts after the items it has right now, its back catalogue is not posted
+            info!("Started following rss feed {} in {}", feed_url, channel_id.0);
+            return crate::feeds::store(db, guild_id, *channel_id, feed_url, None, &feed).await;
+        }
+    };
+
+    let items = crate::feeds::new_items(&feed.feed.items, &state);
+    tracing::info!("Found {} new rss items in {}", items.len(), feed_url);
+
+    for item in items {
+        let (title, link) = match (&item.title, &item.link) {
+            (Some(title), Some(link)) => (title.as_str(), link.as_str()),
+            _ => continue, // nothing to post without these
+        };
+        let date = item
+            .date()
+            .map(|date| date.with_timezone(&chrono::Local))
+            .unwrap_or_else(chrono::Local::now);
+
+        let sql_res = sqlx::query_as::<sqlx::Postgres, structs::Rss>(
+            "SELECT * FROM posted_rss WHERE rss_title = $1 AND channel_id = $2",
+        )
+        .bind(title)
+        .bind(channel_id.0 as i64)
+        .fetch_optional(db)
         .await
-        .map_err(Error::NetRequest)?;
-    let channel = Channel::read_from(&bytestream[..]).map_err(Error::Rss)?;
+        .map_err(Error::Database)?;
+
+        if let Some(exists) = sql_res {
+            info!("An already posted rss item");
+            let msg = match channel_id.message(ctx, exists.message_id as u64).await {
+                Ok(msg) => msg,
+                Err(why) => {
+                    tracing::warn!("Could not fetch rss message of {}: {}", title, why);
+                    continue;
+                }
+            };
+
+            let this_date = msg
+                .embeds
+                .first()
+                .and_then(|embed| embed.timestamp.as_ref())
+                .and_then(|t| t.parse::<chrono::DateTime<chrono::Local>>().ok());
 
-    Ok(channel)
+            // compare dates and post update if newer
+            if this_date.is_some_and(|this_date| this_date < date) {
+                update_posts(ctx, db, channel_id, conf, item, title, link, &date, &msg).await?;
+            }
+        } else {
+            tracing::info!("Posting new rss item in channel: {}", channel_id.0);
+            let content = crate::feeds::item_content(&state, &feed.feed.title, title, link);
+            // the state isn't stored if an item fails, so the next check retries it, the items
+            // posted before it are in `posted_rss` and not posted twice
+            post_item(ctx, db, channel_id, conf, &content, item, title, link, &date).await?;
+        }
+    }
+
+    crate::feeds::store(db, guild_id, *channel_id, feed_url, Some(&state), &feed).await
 }
 
 async fn update_posts(
@@ -237,9 +552,9 @@ async fn update_posts(
     db: &sqlx::PgPool,
     channel_id: &serenity::model::id::ChannelId,
     conf: &TaskConfigRss,
+    item: &crate::feeds::FeedItem,
     title: &str,
     link: &str,
-    description: &str,
     date_: &chrono::DateTime<chrono::Local>,
     msg: &serenity::model::channel::Message,
 ) -> Result<(), Error> {
@@ -250,11 +565,7 @@ async fn update_posts(
                 title
             ))
             .embed(|e| {
-                e.title(title)
-                    .url(link)
-                    .description(conf.clean_regex.replace_all(description, ""))
-                    .timestamp(date_.to_rfc3339())
-                    .color(0xb00b69)
+                crate::feeds::create_embed(e, &conf.clean_regex, item, date_)
             })
             .components(|c| {
                 c.create_action_row(|a| {
@@ -293,21 +604,16 @@ async fn post_item(
     db: &sqlx::PgPool,
     channel_id: &serenity::model::id::ChannelId,
     conf: &TaskConfigRss,
+    content: &str,
+    item: &crate::feeds::FeedItem,
     title: &str,
     link: &str,
-    description: &str,
     date: &chrono::DateTime<chrono::Local>,
 ) -> Result<(), Error> {
     let msg = channel_id
         .send_message(&ctx, |f| {
-            f.content(format!("Neue Nachricht im Planungsportal · {}", title))
-                .embed(|e| {
-                    e.title(title)
-                        .url(link)
-                        .description(conf.clean_regex.replace_all(description, ""))
-                        .timestamp(date.to_rfc3339())
-                        .color(0xb00b69)
-                })
+            f.content(content)
+                .embed(|e| crate::feeds::create_embed(e, &conf.clean_regex, item, date))
                 .components(|c| {
                     c.create_action_row(|a| {
                         a.create_button(|b| {
@@ -319,21 +625,527 @@ async fn post_item(
                 })
         })
         .await
-        .map_err(Error::Serenity);
+        .map_err(Error::Serenity)?;
 
-    if let Ok(msg) = msg {
-        if let Err(why) = sqlx::query(
-            "INSERT INTO posted_rss (rss_title, channel_id, message_id) VALUES ($1, $2, $3)",
+    // the item is posted, failing here would only post it again with the next check
+    if let Err(why) = sqlx::query(
+        "INSERT INTO posted_rss (rss_title, channel_id, message_id) VALUES ($1, $2, $3)",
+    )
+    .bind(title)
+    .bind(channel_id.0 as i64)
+    .bind(msg.id.0 as i64)
+    .execute(db)
+    .await
+    .map_err(Error::Database)
+    {
+        tracing::error!("Failed to insert rss message id: {:?}", why);
+    }
+
+    Ok(())
+}
+
+/// Announces the new episodes or videos of the podcast feed in every guild that has it enabled
+///
+/// The first check of a feed only remembers its current episodes, afterwards every episode that
+/// wasn't posted yet is announced in `podcastChannel`
+pub async fn post_podcast(ctx: serenity::Context, data: Data) -> Result<(), Error> {
+    for guild_id in data.config.guilds().await? {
+        let config = data.config.get(guild_id).await?;
+        let settings = match config.podcast_settings {
+            Some(settings) if settings.post_podcast => settings,
+            _ => continue,
+        };
+        let interval = chrono::Duration::hours(settings.podcast_check_interval as i64);
+        if !data.timers.due("podcast", guild_id, interval) {
+            continue;
+        }
+
+        let result = post_podcast_in_guild(&ctx, &data.db, guild_id, &settings)
+            .await
+            .map_err(|why| {
+                Error::WithMessage(format!("Failed to check {} ({})", settings.podcast_url, why))
+            });
+        data.timers.record(&ctx, &data, "podcast", guild_id, interval, result).await;
+    }
+
+    Ok(())
+}
+
+async fn post_podcast_in_guild(
+    ctx: &serenity::Context,
+    db: &sqlx::PgPool,
+    guild_id: serenity::GuildId,
+    settings: &crate::config::FacultyManagerPodcastConfig,
+) -> Result<(), Error> {
+    let feed = match crate::feeds::fetch(&settings.podcast_url, None).await? {
+        Some(feed) => feed.feed,
+        None => return Ok(()),
+    };
+
+    let known = sqlx::query_as::<sqlx::Postgres, structs::PodcastEpisode>(
+        "SELECT * FROM podcast_episodes WHERE guild_id = $1 AND feed_url = $2",
+    )
+    .bind(guild_id.0 as i64)
+    .bind(&settings.podcast_url)
+    .fetch_all(db)
+    .await
+    .map_err(Error::Database)?;
+    let first_check = known.is_empty();
+
+    let mut episodes = feed
+        .items
+        .iter()
+        .filter(|item| {
+            item.key()
+                .is_some_and(|key| !known.iter().any(|e| e.episode_key == key))
+        })
+        .collect::<Vec<_>>();
+    // oldest first, feeds usually list their newest episode first
+    episodes.sort_by_key(|item| item.date());
+
+    if first_check {
+        info!(
+            "Started following podcast {} in {}, {} episodes are not posted",
+            settings.podcast_url,
+            guild_id.0,
+            episodes.len()
+        );
+    } else if episodes.len() > crate::feeds::MAX_NEW_ITEMS {
+        tracing::warn!(
+            "Podcast {} has {} new episodes, only posting the newest {}",
+            settings.podcast_url,
+            episodes.len(),
+            crate::feeds::MAX_NEW_ITEMS
+        );
+    }
+    let skipped = if first_check {
+        episodes.len()
+    } else {
+        episodes.len().saturating_sub(crate::feeds::MAX_NEW_ITEMS)
+    };
+
+    for (i, item) in episodes.into_iter().enumerate() {
+        let key = item.key().unwrap_or_default();
+        let message_id = if i < skipped {
+            None
+        } else {
+            match post_episode(ctx, settings, &feed.title, item).await {
+                Ok(msg) => Some(msg.id.0 as i64),
+                Err(why) => {
+                    // not remembered, so it is posted with the next check
+                    tracing::error!("Failed to post podcast episode {}: {}", key, why);
+                    continue;
+                }
+            }
+        };
+
+        sqlx::query(
+            "INSERT INTO podcast_episodes (guild_id, feed_url, episode_key, title, message_id)
+            VALUES ($1, $2, $3, $4, $5) ON CONFLICT DO NOTHING",
         )
-        .bind(title)
-        .bind(channel_id.0 as i64)
-        .bind(msg.id.0 as i64)
+        .bind(guild_id.0 as i64)
+        .bind(&settings.podcast_url)
+        .bind(key)
+        .bind(item.title.as_deref())
+        .bind(message_id)
         .execute(db)
         .await
-        .map_err(Error::Database)
+        .map_err(Error::Database)?;
+    }
+
+    Ok(())
+}
+
+async fn post_episode(
+    ctx: &serenity::Context,
+    settings: &crate::config::FacultyManagerPodcastConfig,
+    feed_title: &str,
+    item: &crate::feeds::FeedItem,
+) -> Result<serenity::Message, Error> {
+    let title = item.title.as_deref().unwrap_or_default();
+    let mut content = format!("Neue Folge von **{}**", feed_title);
+    if let Some(role) = settings.mention_role {
+        content = format!("{} {}", role.mention(), content);
+    }
+    // video descriptions are often long, the link leads to the full text
+    let description = crate::html::to_markdown(
+        item.summary.as_deref().unwrap_or_default(),
+        item.link.as_deref(),
+    );
+    let description = crate::html::truncate(&description.markdown, 300, item.link.as_deref());
+    let date = item
+        .date()
+        .map(|date| date.with_timezone(&chrono::Local))
+        .unwrap_or_else(chrono::Local::now);
+
+    settings
+        .podcast_channel
+        .send_message(ctx, |m| {
+            m.content(content).embed(|e| {
+                e.title(crate::html::truncate(title, crate::feeds::EMBED_TITLE_LIMIT, None))
+                    .description(description)
+                    .timestamp(date.to_rfc3339())
+                    .color(0xb00b69);
+                if let Some(link) = &item.link {
+                    e.url(link);
+                }
+                if let Some(author) = item.author.as_deref() {
+                    e.author(|a| a.name(author));
+                }
+                if let Some(thumbnail) = &item.thumbnail {
+                    e.image(thumbnail);
+                }
+                if let Some(duration) = &item.duration {
+                    e.field("Dauer", duration, true);
+                }
+                e
+            });
+            if let Some(link) = &item.link {
+                m.components(|c| {
+                    c.create_action_row(|a| {
+                        a.create_button(|b| {
+                            b.label("Open in Browser")
+                                .style(serenity::ButtonStyle::Link)
+                                .url(link)
+                        })
+                    })
+                });
+            }
+            m
+        })
+        .await
+        .map_err(Error::Serenity)
+}
+
+/// The first voice XP check since the bot started, voice time is only credited from then on so
+/// time the bot was offline doesn't count while time missed by failed checks does
+static VOICE_XP_SINCE: std::sync::OnceLock<chrono::DateTime<chrono::Utc>> =
+    std::sync::OnceLock::new();
+
+/// Awards XP for the time members spend in voice channels
+///
+/// Runs every minute, members earn `general.voiceXpPerMinute` while they are unmuted and
+/// undeafened in a channel with at least `general.voiceXpMinMembers` such members, the AFK
+/// channel doesn't count. Every stay in voice is recorded as a session in `voice_sessions`.
+pub async fn voice_xp(ctx: serenity::Context, data: Data) -> Result<(), Error> {
+    VOICE_XP_SINCE.get_or_init(chrono::Utc::now);
+    for guild_id in ctx.cache.guilds() {
+        // every guild is checked on every tick, the timers only report failures and recoveries
+        let result = voice_xp_in_guild(&ctx, &data, guild_id).await;
+        let interval = chrono::Duration::minutes(1);
+        data.timers.record(&ctx, &data, "voice_xp", guild_id, interval, result).await;
+    }
+
+    Ok(())
+}
+
+async fn voice_xp_in_guild(
+    ctx: &serenity::Context,
+    data: &Data,
+    guild_id: serenity::GuildId,
+) -> Result<(), Error> {
+    let guild = match ctx.cache.guild(guild_id) {
+        Some(guild) => guild,
+        None => return Ok(()),
+    };
+    let config = data.config.get(guild_id).await?;
+    let now = chrono::Utc::now();
+
+    let open_sessions = sqlx::query_as::<sqlx::Postgres, structs::VoiceSession>(
+        "SELECT * FROM voice_sessions WHERE guild_id = $1 AND ended_at IS NULL",
+    )
+    .bind(guild_id.0 as i64)
+    .fetch_all(&data.db)
+    .await
+    .map_err(Error::Database)?;
+
+    // members in voice, the afk channel doesn't count
+    let in_voice = guild
+        .voice_states
+        .values()
+        .filter_map(|state| state.channel_id.map(|channel_id| (channel_id, state)))
+        .filter(|(channel_id, _)| Some(*channel_id) != guild.afk_channel_id)
+        .filter_map(|(channel_id, state)| {
+            let member = state
+                .member
+                .clone()
+                .or_else(|| guild.members.get(&state.user_id).cloned())?;
+            (!member.user.bot).then_some((channel_id, state, member))
+        })
+        .collect::<Vec<_>>();
+
+    let listening = |state: &serenity::VoiceState| {
+        !(state.mute || state.self_mute || state.deaf || state.self_deaf || state.suppress)
+    };
+    let mut listeners: std::collections::HashMap<serenity::ChannelId, usize> =
+        std::collections::HashMap::new();
+    for (channel_id, state, _) in &in_voice {
+        if listening(state) {
+            *listeners.entry(*channel_id).or_insert(0) += 1;
+        }
+    }
+
+    for (channel_id, state, member) in &in_voice {
+        let session = match open_sessions
+            .iter()
+            .find(|session| session.user_id == state.user_id.0 as i64)
+        {
+            Some(session) => session,
+            None => {
+                sqlx::query(
+                    "INSERT INTO voice_sessions (guild_id, user_id, channel_id, started_at, last_seen_at)
+                    VALUES ($1, $2, $3, $4, $4)",
+                )
+                .bind(guild_id.0 as i64)
+                .bind(state.user_id.0 as i64)
+                .bind(channel_id.0 as i64)
+                .bind(now)
+                .execute(&data.db)
+                .await
+                .map_err(Error::Database)?;
+                continue;
+            }
+        };
+
+        let mut minutes = 0.0;
+        let mut xp = 0.0;
+        let members = listeners.get(channel_id).copied().unwrap_or_default();
+        if config.general.voice_xp_per_minute > 0.0
+            && listening(state)
+            && members >= config.general.voice_xp_min_members
         {
-            tracing::error!("Failed to insert rss message id: {:?}", why);
+            let multipliers =
+                crate::xp::Multipliers::resolve(ctx, *channel_id, &member.roles, &config.general)
+                    .await;
+            if multipliers.total() > 0.0 {
+                let since = session.last_seen_at.max(*VOICE_XP_SINCE.get_or_init(chrono::Utc::now));
+                minutes = ((now - since).num_seconds() as f64 / 60.0).max(0.0);
+                xp = minutes * config.general.voice_xp_per_minute * multipliers.total();
+            }
+        }
+
+        sqlx::query(
+            "UPDATE voice_sessions SET channel_id = $2, last_seen_at = $3,
+            active_minutes = active_minutes + $4, xp = xp + $5 WHERE session_id = $1",
+        )
+        .bind(session.session_id)
+        .bind(channel_id.0 as i64)
+        .bind(now)
+        .bind(minutes)
+        .bind(xp)
+        .execute(&data.db)
+        .await
+        .map_err(Error::Database)?;
+
+        if xp > 0.0 {
+            // the session is recorded already, one member failing doesn't stop the others
+            if let Err(why) =
+                crate::xp::award(ctx, data, guild_id, &config, &member.user, xp).await
+            {
+                tracing::error!(
+                    "Failed to award voice xp to {} in {}: {}",
+                    member.user.id.0,
+                    guild_id.0,
+                    why
+                );
+            }
+        }
+    }
+
+    // sessions of members that left voice end when they were last seen
+    for session in &open_sessions {
+        if in_voice
+            .iter()
+            .any(|(_, state, _)| state.user_id.0 as i64 == session.user_id)
+        {
+            continue;
+        }
+        sqlx::query("UPDATE voice_sessions SET ended_at = last_seen_at WHERE session_id = $1")
+            .bind(session.session_id)
+            .execute(&data.db)
+            .await
+            .map_err(Error::Database)?;
+    }
+
+    Ok(())
+}
+
+/// Reminds members of open reverification campaigns and acts on those who missed the deadline
+pub async fn reverification_deadlines(ctx: serenity::Context, data: Data) -> Result<(), Error> {
+    let campaigns = sqlx::query_as::<sqlx::Postgres, structs::ReverificationCampaign>(
+        "SELECT * FROM reverification_campaigns WHERE NOT finished",
+    )
+    .fetch_all(&data.db)
+    .await
+    .map_err(Error::Database)?;
+
+    let now = chrono::Utc::now();
+    for campaign in campaigns {
+        let config = data
+            .config
+            .get(serenity::GuildId(campaign.guild_id as u64))
+            .await?;
+        let remind_before =
+            chrono::Duration::hours(config.verification.reminder_hours_before_deadline);
+
+        let open = sqlx::query_as::<sqlx::Postgres, structs::ReverificationRequest>(
+            "SELECT * FROM reverification_requests WHERE campaign_id = $1 AND completed_at IS NULL",
+        )
+        .bind(campaign.campaign_id)
+        .fetch_all(&data.db)
+        .await
+        .map_err(Error::Database)?;
+
+        if now >= campaign.deadline {
+            finish_reverification(&ctx, &data, &config, &campaign, &open).await?;
+        } else if !campaign.reminder_sent && now >= campaign.deadline - remind_before {
+            info!(
+                "Reminding {} members of reverification campaign {}",
+                open.len(),
+                campaign.campaign_id
+            );
+            for request in &open {
+                if let Err(why) = crate::verification::send_reverification_dm(
+                    &ctx,
+                    serenity::UserId(request.user_id as u64),
+                    &campaign.deadline,
+                    true,
+                )
+                .await
+                {
+                    tracing::warn!("Could not send reverification reminder: {:?}", why);
+                }
+            }
+
+            sqlx::query(
+                "UPDATE reverification_campaigns SET reminder_sent = TRUE WHERE campaign_id = $1",
+            )
+            .bind(campaign.campaign_id)
+            .execute(&data.db)
+            .await
+            .map_err(Error::Database)?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Closes a campaign after its deadline, removing roles from non-responders if the campaign asks for it
+async fn finish_reverification(
+    ctx: &serenity::Context,
+    data: &Data,
+    config: &FacultyManagerConfig,
+    campaign: &structs::ReverificationCampaign,
+    open: &[structs::ReverificationRequest],
+) -> Result<(), Error> {
+    let guild_id = serenity::GuildId(campaign.guild_id as u64);
+
+    if campaign.remove_role {
+        let roles = config
+            .verification_domains()
+            .into_iter()
+            .map(|d| d.role)
+            .chain(std::iter::once(config.roles.verified))
+            .collect::<std::collections::HashSet<_>>();
+
+        for request in open {
+            let member = match guild_id.member(ctx, request.user_id as u64).await {
+                Ok(m) => m,
+                Err(_) => continue, // left the server in the meantime
+            };
+
+            for role in member.roles.iter().filter(|r| roles.contains(*r)) {
+                if let Err(why) = ctx
+                    .http
+                    .remove_member_role(
+                        guild_id.0,
+                        member.user.id.0,
+                        role.0,
+                        Some("Missed the reverification deadline"),
+                    )
+                    .await
+                {
+                    tracing::warn!("Could not remove role from {}: {:?}", member.user.id, why);
+                }
+            }
+        }
+    }
+
+    sqlx::query("UPDATE reverification_campaigns SET finished = TRUE WHERE campaign_id = $1")
+        .bind(campaign.campaign_id)
+        .execute(&data.db)
+        .await
+        .map_err(Error::Database)?;
+
+    let summary = format!(
+        "Reverification campaign for members who joined before {} ended, {} members did not respond{}",
+        campaign.cutoff,
+        open.len(),
+        if campaign.remove_role {
+            " and lost their verified role"
+        } else {
+            ""
+        }
+    );
+    info!("{}", summary);
+
+    if let Err(why) = config
+        .channels
+        .logs
+        .say(ctx, summary)
+        .await
+        .map_err(Error::Serenity)
+    {
+        tracing::error!("Failed to log reverification summary: {:?}", why);
+    }
+
+    Ok(())
+}
+
+/// Modification time of config.json, if it can be read
+pub fn config_modified() -> Option<std::time::SystemTime> {
+    std::fs::metadata("./config.json")
+        .and_then(|m| m.modified())
+        .ok()
+}
+
+/// Reloads config.json when the file changed on disk since `last_modified`
+pub async fn watch_config(
+    ctx: serenity::Context,
+    data: Data,
+    last_modified: Arc<std::sync::Mutex<Option<std::time::SystemTime>>>,
+) -> Result<(), Error> {
+    let current = config_modified();
+    {
+        let mut last_modified = last_modified.lock().expect("config watcher lock poisoned");
+        if current == *last_modified {
+            return Ok(());
         }
+        *last_modified = current;
+    }
+
+    info!("config.json changed, reloading");
+    let report = match data.config.reload().await {
+        Ok(updated) => format!(
+            "config.json was reloaded, the settings of {} servers were updated",
+            updated
+        ),
+        Err(why) => {
+            tracing::error!("Failed to reload config.json: {}", why);
+            why.to_string()
+        }
+    };
+
+    if let Err(why) = data
+        .config
+        .seed()
+        .channels
+        .logs
+        .say(&ctx, report)
+        .await
+        .map_err(Error::Serenity)
+    {
+        tracing::error!("Failed to log config reload: {:?}", why);
     }
 
     Ok(())
@@ -344,28 +1156,27 @@ pub async fn log_latency_to_influx(
     sm: Arc<serenity::Mutex<serenity::ShardManager>>,
     influx: &influxdb2::Client,
 ) -> Result<(), Error> {
-    loop {
-        info!("Logging latency to influx");
-        let shard = ctx.shard_id;
+    info!("Logging latency to influx");
+    let shard = ctx.shard_id;
+    let latency = {
         let locked = sm.lock().await;
         let runner = locked.runners.lock().await;
-        let latency = runner
+        runner
             .get(&ShardId(shard))
-            .unwrap()
-            .latency
-            .unwrap_or(std::time::Duration::from_nanos(0));
-
-        let points = vec![DataPoint::builder("latency")
-            .field("latency", latency.as_millis() as i64)
-            .timestamp(chrono::Utc::now().timestamp_nanos_opt().unwrap())
-            .build()
-            .unwrap()];
-
-        influx
-            .write("facultymanager", futures::stream::iter(points))
-            .await
-            .unwrap();
+            .and_then(|r| r.latency)
+            .unwrap_or(std::time::Duration::from_nanos(0))
+    };
 
-        tokio::time::sleep(tokio::time::Duration::from_secs(60)).await;
-    }
+    let points = vec![DataPoint::builder("latency")
+        .field("latency", latency.as_millis() as i64)
+        .timestamp(chrono::Utc::now().timestamp_nanos_opt().unwrap_or_default())
+        .build()
+        .map_err(|e| Error::Generic(Box::new(e)))?];
+
+    influx
+        .write("facultymanager", futures::stream::iter(points))
+        .await
+        .map_err(|e| Error::Generic(Box::new(e)))?;
+
+    Ok(())
 }
@@ -1,10 +1,6 @@
 use poise::serenity_prelude as serenity;
-use tokio::{
-    io::{AsyncReadExt, AsyncWriteExt},
-    process::Command,
-};
 
-use crate::prelude::Error;
+use crate::{config::MealplanImageSettings, prelude::Error};
 
 use askama::Template;
 use lettre::{
@@ -12,69 +8,123 @@ use lettre::{
     SmtpTransport, Transport,
 };
 
-/// Comverts a pdf file to a png buffer
-async fn pdf_to_png(filepath: std::path::PathBuf) -> Result<Vec<u8>, Error> {
-    let child = Command::new("convert")
-        .arg("-density")
-        .arg("300")
-        .arg(filepath)
-        .arg("-flatten")
-        .arg("png:-")
-        .output()
+/// Fetch the newest Mensaplan PDF from the website
+pub async fn download_mensaplan(url: &str) -> Result<Vec<u8>, Error> {
+    println!("Fetching Mensaplan from {}", url); // be careful not to rape the server
+    let response = reqwest::get(url)
         .await
-        .map_err(Error::IO)?
-        .stdout;
-
-    Ok(child)
+        .map_err(Error::NetRequest)?
+        .error_for_status()
+        .map_err(Error::NetRequest)?;
+    let pdf = response.bytes().await.map_err(Error::NetRequest)?;
+    Ok(pdf.to_vec())
 }
 
-/// Fetch newest Mensaplans from the website
-pub async fn fetch_mensaplan<'a>(url: &'a str) -> Result<Vec<u8>, Error> {
-    println!("Fetching Mensaplan from {}", url); // be careful not to rape the server
-                                                 // download mensaplan
-    let response = reqwest::get(url).await.map_err(Error::NetRequest)?;
-    let mut file = tokio::fs::File::create("images/mensaplan.pdf")
+/// Render a downloaded mensaplan PDF to one png buffer per page
+///
+/// A `width` or `height` of 0 leaves that dimension unbounded, otherwise the pages are scaled down
+/// to fit while keeping their aspect ratio
+pub async fn render_mensaplan(
+    pdf: &[u8],
+    settings: &MealplanImageSettings,
+) -> Result<Vec<Vec<u8>>, Error> {
+    let pdf = pdf.to_vec();
+    let settings = settings.clone();
+
+    // pdfium is blocking and not async aware
+    tokio::task::spawn_blocking(move || render_pdf(&pdf, &settings))
         .await
-        .map_err(Error::IO)?;
+        .map_err(|why| Error::WithMessage(format!("Rendering the mensaplan failed: {}", why)))?
+}
 
-    file.write_all(&response.bytes().await.map_err(Error::NetRequest)?)
-        .await
-        .map_err(Error::IO)?;
+fn render_pdf(pdf: &[u8], settings: &MealplanImageSettings) -> Result<Vec<Vec<u8>>, Error> {
+    use image::codecs::png::{CompressionType, FilterType, PngEncoder};
+    use pdfium_render::prelude::*;
+
+    let bindings = Pdfium::bind_to_library(Pdfium::pdfium_platform_library_name_at_path("./"))
+        .or_else(|_| Pdfium::bind_to_system_library())
+        .map_err(Error::Pdfium)?;
+    let pdfium = Pdfium::new(bindings);
+    let document = pdfium
+        .load_pdf_from_byte_slice(pdf, None)
+        .map_err(Error::Pdfium)?;
+
+    // PDF pages are measured in points, 72 per inch
+    let mut render_config = PdfRenderConfig::new()
+        .scale_page_by_factor(settings.density as f32 / 72.0)
+        .render_form_data(true)
+        .set_clear_color(if settings.flatten {
+            PdfColor::WHITE
+        } else {
+            PdfColor::new(255, 255, 255, 0)
+        });
+    if settings.width > 0 {
+        render_config = render_config.set_maximum_width(settings.width as i32);
+    }
+    if settings.height > 0 {
+        render_config = render_config.set_maximum_height(settings.height as i32);
+    }
 
-    let png = pdf_to_png("images/mensaplan.pdf".into()).await?;
-    Ok(png)
-}
+    // quality follows ImageMagick, where it selects the png compression level
+    let compression = match settings.quality {
+        0..=49 => CompressionType::Fast,
+        50..=89 => CompressionType::Default,
+        _ => CompressionType::Best,
+    };
+
+    let mut pages = Vec::new();
+    for page in document.pages().iter() {
+        let mut image = page
+            .render_with_config(&render_config)
+            .map_err(Error::Pdfium)?
+            .as_image();
+        if settings.flatten {
+            image = image::DynamicImage::ImageRgb8(image.to_rgb8());
+        }
 
-pub async fn show_levelup_image(user: &serenity::User, level: u16) -> Result<Vec<u8>, Error> {
-    let mut file = tokio::fs::File::open("images/banner.png")
-        .await
-        .map_err(Error::IO)?;
-    let mut buffer = Vec::new();
-    file.read_to_end(&mut buffer).await.map_err(Error::IO)?;
-
-    let with_text = Command::new("convert")
-        .arg("images/banner.png")
-        .arg("-font")
-        .arg("images/Roboto-Bold.ttf")
-        .arg("-gravity")
-        .arg("West")
-        .arg("-pointsize")
-        .arg("35")
-        .arg("-fill")
-        .arg("white")
-        .arg("-draw")
-        .arg(format!("text 280,-30 '{} has reached'", user.name))
-        .arg("-draw")
-        .arg(format!("text 280,45 'LEVEL {}'", level))
-        .arg("png:-")
-        .output()
-        .await;
-
-    if let Ok(with_text) = with_text {
-        Ok(with_text.stdout)
-    } else {
-        Err(Error::WithMessage("Could not convert image".into()))
+        let mut png = Vec::new();
+        image
+            .write_with_encoder(PngEncoder::new_with_quality(
+                &mut png,
+                compression,
+                FilterType::Adaptive,
+            ))
+            .map_err(Error::Image)?;
+        pages.push(png);
+    }
+
+    if pages.is_empty() {
+        return Err(Error::WithMessage("The mensaplan PDF has no pages".to_string()));
     }
+
+    Ok(pages)
+}
+
+/// Attachments for the rendered pages of a mensaplan, Discord allows at most 10 per message
+pub fn mensaplan_attachments(pages: &[Vec<u8>]) -> Vec<serenity::AttachmentType<'_>> {
+    pages
+        .iter()
+        .take(10)
+        .enumerate()
+        .map(|(i, page)| serenity::AttachmentType::Bytes {
+            data: std::borrow::Cow::Borrowed(page),
+            filename: if pages.len() == 1 {
+                "mensaplan.png".to_string()
+            } else {
+                format!("mensaplan-{}.png", i + 1)
+            },
+        })
+        .collect()
+}
+
+/// Hash of a mensaplan PDF, used to notice when the canteen publishes a corrected plan
+pub fn mensaplan_hash(pdf: &[u8]) -> String {
+    blake3::hash(pdf).to_hex().to_string()
+}
+
+/// The banner attached to level-up messages, the text is shown in the embed on top of it
+pub async fn levelup_banner() -> Result<Vec<u8>, Error> {
+    tokio::fs::read("images/banner.png").await.map_err(Error::IO)
 }
 
 
@@ -0,0 +1,243 @@
+use poise::serenity_prelude as serenity;
+
+use crate::{config::FacultyManagerVerificationConfig, prelude::Error, structs};
+
+/// Outcome of checking a code against a user's pending verification
+#[derive(Debug)]
+pub enum CodeCheck {
+    /// The code is correct, holds the e-mail address the code was sent to
+    Valid(String),
+    /// The code is wrong, holds the number of attempts the user has left
+    Invalid(i32),
+    /// There is no pending verification for this user
+    NotPending,
+    /// The code is older than the configured TTL
+    Expired,
+    /// The user guessed wrong too often and has to request a new code
+    TooManyAttempts,
+}
+
+/// Hashes a code so we don't keep it around in plain text
+pub fn hash(value: &str) -> String {
+    blake3::hash(value.trim().as_bytes()).to_hex().to_string()
+}
+
+/// Hashes an e-mail address, ignoring case so `Max.Muster@...` and `max.muster@...` match
+pub fn hash_email(email: &str) -> String {
+    hash(&email.trim().to_lowercase())
+}
+
+/// Stores a freshly generated code for a user, replacing any code they requested before
+pub async fn create_pending(
+    db: &sqlx::PgPool,
+    conf: &FacultyManagerVerificationConfig,
+    user_id: serenity::UserId,
+    email: &str,
+    code: &str,
+) -> Result<(), Error> {
+    // sweep codes nobody is going to use anymore
+    sqlx::query(
+        "DELETE FROM pending_verifications WHERE created_at < CURRENT_TIMESTAMP - make_interval(mins => $1)",
+    )
+    .bind(conf.code_ttl_minutes as i32)
+    .execute(db)
+    .await
+    .map_err(Error::Database)?;
+
+    sqlx::query(
+        "INSERT INTO pending_verifications (user_id, user_email, email_hash, code_hash) VALUES ($1, $2, $3, $4)
+        ON CONFLICT (user_id) DO UPDATE SET user_email = $2, email_hash = $3, code_hash = $4, created_at = CURRENT_TIMESTAMP, attempts = 0",
+    )
+    .bind(user_id.0 as i64)
+    .bind(email)
+    .bind(hash_email(email))
+    .bind(hash(code))
+    .execute(db)
+    .await
+    .map_err(Error::Database)?;
+
+    Ok(())
+}
+
+/// Returns true if another user is currently waiting for a code sent to this address
+pub async fn email_pending_for_other_user(
+    db: &sqlx::PgPool,
+    user_id: serenity::UserId,
+    email: &str,
+) -> Result<bool, Error> {
+    let pending = sqlx::query_as::<sqlx::Postgres, structs::PendingVerification>(
+        "SELECT * FROM pending_verifications WHERE email_hash = $1 AND user_id <> $2",
+    )
+    .bind(hash_email(email))
+    .bind(user_id.0 as i64)
+    .fetch_optional(db)
+    .await
+    .map_err(Error::Database)?;
+
+    Ok(pending.is_some())
+}
+
+/// Checks a supplied code, counting wrong guesses and dropping expired or exhausted codes
+pub async fn check_code(
+    db: &sqlx::PgPool,
+    conf: &FacultyManagerVerificationConfig,
+    user_id: serenity::UserId,
+    supplied_code: &str,
+) -> Result<CodeCheck, Error> {
+    // every check is counted before comparing, so parallel guesses can't share an attempt
+    let pending = sqlx::query_as::<sqlx::Postgres, structs::PendingVerification>(
+        "UPDATE pending_verifications SET attempts = attempts + 1 WHERE user_id = $1 RETURNING *",
+    )
+    .bind(user_id.0 as i64)
+    .fetch_optional(db)
+    .await
+    .map_err(Error::Database)?;
+
+    let pending = match pending {
+        Some(p) => p,
+        None => return Ok(CodeCheck::NotPending),
+    };
+
+    if chrono::Utc::now() - pending.created_at > chrono::Duration::minutes(conf.code_ttl_minutes) {
+        remove_pending(db, user_id).await?;
+        return Ok(CodeCheck::Expired);
+    }
+
+    if pending.attempts > conf.max_attempts {
+        remove_pending(db, user_id).await?;
+        return Ok(CodeCheck::TooManyAttempts);
+    }
+
+    if pending.code_hash == hash(supplied_code) {
+        return Ok(CodeCheck::Valid(pending.user_email));
+    }
+
+    if pending.attempts >= conf.max_attempts {
+        remove_pending(db, user_id).await?;
+        return Ok(CodeCheck::TooManyAttempts);
+    }
+
+    Ok(CodeCheck::Invalid(conf.max_attempts - pending.attempts))
+}
+
+/// Drops a user's pending verification, e.g. after it was completed
+pub async fn remove_pending(db: &sqlx::PgPool, user_id: serenity::UserId) -> Result<(), Error> {
+    sqlx::query("DELETE FROM pending_verifications WHERE user_id = $1")
+        .bind(user_id.0 as i64)
+        .execute(db)
+        .await
+        .map_err(Error::Database)?;
+
+    Ok(())
+}
+
+/// Stores a successful reverification and completes the user's open reverification requests
+///
+/// Returns the guilds of the campaigns that were completed, so the caller can hand back roles
+pub async fn complete_reverification(
+    db: &sqlx::PgPool,
+    user_id: serenity::UserId,
+    email: &str,
+) -> Result<Vec<serenity::GuildId>, Error> {
+    sqlx::query(
+        "INSERT INTO verified_users (user_id, user_email, last_verified_at) VALUES ($1, $2, CURRENT_TIMESTAMP)
+        ON CONFLICT (user_id) DO UPDATE SET user_email = $2, last_verified_at = CURRENT_TIMESTAMP",
+    )
+    .bind(user_id.0 as i64)
+    .bind(email)
+    .execute(db)
+    .await
+    .map_err(Error::Database)?;
+
+    let guilds = sqlx::query_as::<sqlx::Postgres, (i64,)>(
+        "UPDATE reverification_requests r SET completed_at = CURRENT_TIMESTAMP
+        FROM reverification_campaigns c
+        WHERE r.campaign_id = c.campaign_id AND r.user_id = $1 AND r.completed_at IS NULL
+        RETURNING c.guild_id",
+    )
+    .bind(user_id.0 as i64)
+    .fetch_all(db)
+    .await
+    .map_err(Error::Database)?;
+
+    Ok(guilds
+        .into_iter()
+        .map(|(guild_id,)| serenity::GuildId(guild_id as u64))
+        .collect())
+}
+
+/// The guild of the most recent reverification campaign a user was asked to take part in
+pub async fn reverification_guild(
+    db: &sqlx::PgPool,
+    user_id: serenity::UserId,
+) -> Result<Option<serenity::GuildId>, Error> {
+    let guild = sqlx::query_as::<sqlx::Postgres, (i64,)>(
+        "SELECT c.guild_id FROM reverification_requests r
+        JOIN reverification_campaigns c ON r.campaign_id = c.campaign_id
+        WHERE r.user_id = $1 ORDER BY c.created_at DESC LIMIT 1",
+    )
+    .bind(user_id.0 as i64)
+    .fetch_optional(db)
+    .await
+    .map_err(Error::Database)?;
+
+    Ok(guild.map(|(guild_id,)| serenity::GuildId(guild_id as u64)))
+}
+
+/// DMs a member the reverification prompt, `reminder` changes the wording for the reminder before the deadline
+pub async fn send_reverification_dm(
+    ctx: &serenity::Context,
+    user_id: serenity::UserId,
+    deadline: &chrono::DateTime<chrono::Utc>,
+    reminder: bool,
+) -> Result<(), Error> {
+    let (content, description) = if reminder {
+        (
+            "⏰ **Reverification Reminder**",
+            format!(
+                "You haven't completed the reverification yet. Please do so before <t:{}:F>, otherwise you might lose access to the server.",
+                deadline.timestamp()
+            ),
+        )
+    } else {
+        (
+            "🎓 **Reverification Required**",
+            format!(
+                "Hello! To confirm that you're still enrolled, please complete the reverification process before <t:{}:F>. This helps us keep the server up to date for active students.",
+                deadline.timestamp()
+            ),
+        )
+    };
+
+    user_id
+        .create_dm_channel(ctx)
+        .await
+        .map_err(Error::Serenity)?
+        .send_message(ctx, |message| {
+            message
+                .content(content)
+                .embed(|embed| {
+                    embed
+                        .title("Student Status Verification")
+                        .description(description)
+                })
+                .components(|c| {
+                    c.create_action_row(|a| {
+                        a.create_button(|b| {
+                            b.style(serenity::ButtonStyle::Success)
+                                .label("Reverify")
+                                .custom_id("reverify")
+                        })
+                        .create_button(|b| {
+                            b.style(serenity::ButtonStyle::Secondary)
+                                .label("Enter Code")
+                                .custom_id("reverify_code")
+                        })
+                    })
+                })
+        })
+        .await
+        .map_err(Error::Serenity)?;
+
+    Ok(())
+}
@@ -1,71 +1,286 @@
-use rocket::{http::{Cookie, CookieJar}, response::Redirect, serde::json::Json};
+use poise::serenity_prelude as serenity;
+use rocket::{http::{Cookie, CookieJar}, response::Redirect, serde::json::Json, State};
 use rocket_dyn_templates::Template;
 use serde::Deserialize;
 
 use super::structs::{Code, Email, Response};
 
-use crate::web::{Roles, User};
+use crate::{
+    prelude::Error,
+    scheduler::TaskStatus,
+    structs,
+    utils::{generate_verification_code, CurrentEmail},
+    verification::{self, CodeCheck},
+    web::{AdminUser, AuthenticatedUser, Roles, User, WebState},
+};
 
 
+fn respond(data: &str, status: u16, message: &str) -> Json<Response<String>> {
+    Json(Response {
+        data: data.to_string(),
+        status,
+        message: message.to_string(),
+    })
+}
+
+fn internal_error(why: Error) -> Json<Response<String>> {
+    tracing::error!("Web verification failed: {}", why);
+    respond("ERR_INTERNAL", 500, "Interner Fehler, bitte versuche es später erneut")
+}
+
 #[post("/verify/sendMail", format = "application/json", data = "<email>")]
-pub fn send_mail(email: Json<Email>) -> Json<Response<String>> {
-    println!("Email: {}", email.email);
-
-    // check if email is already in use
-    // let pool = &ctx.data().db;
-    // let user = sqlx::query_as::<sqlx::Postgres, structs::VerifiedUsers>( "SELECT * FROM verified_users WHERE user_email = $1", )
-    // .bind(&email_used)
-    // .fetch_optional(pool)
-    // .await
-    // .map_err(Error::Database)?;
-
-    // dummy true for testing
-    let user = true;
-    if user {
-        return Json(Response {
-            data: "ERR_USER_EXISTS".to_string(),
-            status: 400,
-            message: "E-Mail Adresse wird bereits verwendet, möchtest du stattdessen deinen Account wechseln?".to_string(),
-        });
+pub async fn send_mail(
+    email: Json<Email>,
+    user: AuthenticatedUser<'_>,
+    state: &State<WebState>,
+) -> Json<Response<String>> {
+    let user_id = match user.user_id() {
+        Some(id) => serenity::UserId(id),
+        None => return respond("ERR_UNAUTHORIZED", 401, "Bitte melde dich erneut an"),
+    };
+
+    let config = match state.config.get(state.guild_id).await {
+        Ok(config) => config,
+        Err(why) => return internal_error(why),
+    };
+
+    // check if the email belongs to one of the accepted domains
+    if config.verification_domain(&email.email).is_none() {
+        return respond(
+            "ERR_INVALID_EMAIL",
+            400,
+            &format!(
+                "Ungültige E-Mail Adresse, erlaubt sind: {}",
+                config.verification_domain_list()
+            ),
+        );
+    }
+
+    // check if email or account is already in use
+    let user = sqlx::query_as::<sqlx::Postgres, structs::VerifiedUsers>(
+        "SELECT * FROM verified_users WHERE user_email = $1 OR user_id = $2",
+    )
+    .bind(&email.email)
+    .bind(user_id.0 as i64)
+    .fetch_optional(&state.db)
+    .await
+    .map_err(Error::Database);
+
+    match user {
+        Ok(Some(_)) => {
+            return respond(
+                "ERR_USER_EXISTS",
+                400,
+                "E-Mail Adresse wird bereits verwendet, möchtest du stattdessen deinen Account wechseln?",
+            )
+        }
+        Ok(None) => {}
+        Err(why) => return internal_error(why),
+    }
+
+    match verification::email_pending_for_other_user(&state.db, user_id, &email.email).await {
+        Ok(true) => {
+            return respond(
+                "ERR_EMAIL_PENDING",
+                400,
+                "An diese E-Mail Adresse wurde bereits ein Code für einen anderen Account gesendet",
+            )
+        }
+        Ok(false) => {}
+        Err(why) => return internal_error(why),
     }
 
-    let email_regex = regex::Regex::new(r"^[a-zA-Z0-9_.+-]+@stud.hs-kempten.de$").unwrap();
-    // check if the email is valid
-    if !email_regex.is_match(&email.email) {
+    let username = match state.http.get_user(user_id.0).await {
+        Ok(u) => u.name,
+        Err(why) => return internal_error(Error::Serenity(why)),
+    };
+
+    let code = generate_verification_code();
+    if let Err(why) = verification::create_pending(
+        &state.db,
+        &config.verification,
+        user_id,
+        &email.email,
+        &code,
+    )
+    .await
+    {
+        return internal_error(why);
+    }
+
+    let mail = CurrentEmail::new(email.email.clone(), user_id, username, code);
+    if let Err(why) = state.email_task.send(mail).await {
+        tracing::error!("Failed to enqueue verification email: {}", why);
+        return respond("ERR_EMAIL_SEND", 500, "E-Mail konnte nicht versendet werden");
+    }
+
+    respond("SUCCESS", 200, "E-Mail wurde erfolgreich versendet")
+}
+
+#[post("/verify/checkCode", format = "application/json", data = "<code>")]
+pub async fn check_code(
+    code: Json<Code>,
+    user: AuthenticatedUser<'_>,
+    state: &State<WebState>,
+) -> Json<Response<String>> {
+    let user_id = match user.user_id() {
+        Some(id) => serenity::UserId(id),
+        None => return respond("ERR_UNAUTHORIZED", 401, "Bitte melde dich erneut an"),
+    };
+
+    let config = match state.config.get(state.guild_id).await {
+        Ok(config) => config,
+        Err(why) => return internal_error(why),
+    };
+
+    let checked =
+        verification::check_code(&state.db, &config.verification, user_id, &code.code).await;
+
+    let email = match checked {
+        Ok(CodeCheck::Valid(email)) => email,
+        Ok(CodeCheck::Invalid(attempts_left)) => {
+            return respond(
+                "ERR_INVALID_CODE",
+                400,
+                &format!("Ungültiger Code, du hast noch {} Versuche", attempts_left),
+            )
+        }
+        Ok(CodeCheck::NotPending) => {
+            return respond("ERR_NOT_PENDING", 400, "Bitte fordere zuerst einen Code an")
+        }
+        Ok(CodeCheck::Expired) => {
+            return respond("ERR_CODE_EXPIRED", 400, "Der Code ist abgelaufen, bitte fordere einen neuen an")
+        }
+        Ok(CodeCheck::TooManyAttempts) => {
+            return respond(
+                "ERR_TOO_MANY_ATTEMPTS",
+                400,
+                "Zu viele ungültige Versuche, bitte fordere einen neuen Code an",
+            )
+        }
+        Err(why) => return internal_error(why),
+    };
+
+    if let Err(why) = sqlx::query(
+        "INSERT INTO verified_users (user_id, user_email) VALUES ($1, $2)",
+    )
+    .bind(user_id.0 as i64)
+    .bind(&email)
+    .execute(&state.db)
+    .await
+    {
+        return internal_error(Error::Database(why));
+    }
+
+    if let Err(why) = verification::remove_pending(&state.db, user_id).await {
+        tracing::error!("Failed to remove pending verification: {}", why);
+    }
+
+    // give them the role of the domain they verified with
+    let verified_role = config
+        .verification_domain(&email)
+        .map(|d| d.role)
+        .unwrap_or(config.roles.verified);
+
+    if let Err(why) = state
+        .http
+        .add_member_role(
+            state.guild_id.0,
+            user_id.0,
+            verified_role.0,
+            Some("Verified via web"),
+        )
+        .await
+    {
+        return internal_error(Error::Serenity(why));
+    }
+
+    respond("SUCCESS", 200, "Code is valid")
+}
+
+
+#[get("/admin/settings")]
+pub async fn get_settings(
+    _user: AdminUser<'_>,
+    state: &State<WebState>,
+) -> Json<Response<serde_json::Value>> {
+    match state.config.get(state.guild_id).await {
+        Ok(config) => Json(Response {
+            data: serde_json::to_value(config).unwrap_or_default(),
+            status: 200,
+            message: "OK".to_string(),
+        }),
+        Err(why) => {
+            tracing::error!("Failed to load settings: {}", why);
+            Json(Response {
+                data: serde_json::Value::Null,
+                status: 500,
+                message: "Interner Fehler, bitte versuche es später erneut".to_string(),
+            })
+        }
+    }
+}
+
+/// Merges the given (partial) settings into the stored ones, e.g. `{"mealplan": {"url": "..."}}`
+#[patch("/admin/settings", format = "application/json", data = "<patch>")]
+pub async fn patch_settings(
+    patch: Json<serde_json::Value>,
+    _user: AdminUser<'_>,
+    state: &State<WebState>,
+) -> Json<Response<serde_json::Value>> {
+    let updated = match state.config.get(state.guild_id).await {
+        Ok(config) => config.patched(patch.into_inner()),
+        Err(why) => Err(why),
+    };
+    let updated = match (updated, state.guild_id.channels(&state.http).await) {
+        (Ok(config), Ok(channels)) => match config.validate_in_guild(&channels) {
+            Ok(()) => Ok(config),
+            Err(problems) => Err(Error::WithMessage(problems.join(", "))),
+        },
+        (Ok(_), Err(why)) => Err(Error::Serenity(why)),
+        (Err(why), _) => Err(why),
+    };
+
+    let config = match updated {
+        Ok(config) => config,
+        Err(why) => {
+            return Json(Response {
+                data: serde_json::Value::Null,
+                status: 400,
+                message: why.to_string(),
+            })
+        }
+    };
+
+    if let Err(why) = state.config.set(state.guild_id, config.clone()).await {
+        tracing::error!("Failed to store settings: {}", why);
         return Json(Response {
-            data: "FAILTHIS".to_string(),
-            status: 400,
-            message: "Ungültige E-Mail Adresse".to_string(),
+            data: serde_json::Value::Null,
+            status: 500,
+            message: "Interner Fehler, bitte versuche es später erneut".to_string(),
         });
     }
 
     Json(Response {
-        data: "SUCCESS".to_string(),
+        data: serde_json::to_value(config).unwrap_or_default(),
         status: 200,
-        message: "E-Mail wurde erfolgreich versendet".to_string(),
+        message: "Einstellungen gespeichert".to_string(),
     })
 }
 
-#[post("/verify/checkCode", format = "application/json", data = "<code>")]
-pub fn check_code(code: Json<Code>) -> Json<Response<String>> {
-    println!("Code: {}", code.code);
-    println!("Code == FAILTHIS: {}", code.code == "FAILTHIS");
-    if code.code == "FAILTHIS" {
-        Json(Response {
-            data: "FAILTHIS".to_string(),
-            status: 400,
-            message: "Ungültiger Code".to_string(),
-        })
-    } else {
-        Json(Response {
-            data: "SUCCESS".to_string(),
-            status: 200,
-            message: "Code is valid".to_string(),
-        })
-    }
+/// State of the bot's background tasks
+#[get("/admin/tasks")]
+pub fn get_tasks(
+    _user: AdminUser<'_>,
+    state: &State<WebState>,
+) -> Json<Response<Vec<TaskStatus>>> {
+    Json(Response {
+        data: state.tasks.list(),
+        status: 200,
+        message: "OK".to_string(),
+    })
 }
 
-
 #[get("/auth/discord")]
 pub fn discord_auth() -> Redirect {
     let client_id = std::env::var("DISCORD_CLIENT_ID").expect("DISCORD_CLIENT_ID must be set");
@@ -73,6 +73,26 @@ impl User {
             })
     }
 
+    /// Returns the discord user id stored in a valid, non-expired token
+    pub fn id_from_token(token: &str) -> Option<u64> {
+        let secret_key = std::env::var("SECRET_KEY").expect("SECRET_KEY must be set");
+        let key: Hmac<Sha256> = Hmac::new_from_slice(secret_key.as_bytes())
+            .expect("HMAC can take key of any size");
+
+        token.verify_with_key(&key)
+            .ok()
+            .and_then(|claims: BTreeMap<String, u64>| {
+                let exp = claims.get("exp")?;
+                let current_time = chrono::Utc::now().timestamp() as u64;
+
+                if current_time > *exp {
+                    None
+                } else {
+                    claims.get("id").copied()
+                }
+            })
+    }
+
     pub fn user_has_role(token: &str, role: Roles) -> bool {
         let secret_key = std::env::var("SECRET_KEY").expect("SECRET_KEY must be set");
         let key: Hmac<Sha256> = Hmac::new_from_slice(secret_key.as_bytes())
@@ -110,6 +130,13 @@ pub struct AdminUser<'r>(&'r str);
 #[allow(unused)]
 pub struct AuthenticatedUser<'r>(&'r str);
 
+impl AuthenticatedUser<'_> {
+    /// The discord user id of the logged in user
+    pub fn user_id(&self) -> Option<u64> {
+        User::id_from_token(self.0)
+    }
+}
+
 #[derive(Debug)]
 pub enum ApiKeyError {
     Missing,
@@ -2,8 +2,21 @@ pub mod api;
 pub mod auth;
 pub mod structs;
 use auth::{is_logged_in, AdminUser, AuthenticatedUser, Roles, User};
+use poise::serenity_prelude as serenity;
 use rocket_dyn_templates::Template;
 
+use crate::{config::ConfigStore, scheduler::TaskRegistry, utils::CurrentEmail};
+
+/// State shared between the bot and the web interface, managed by rocket
+pub struct WebState {
+    pub db: sqlx::PgPool,
+    pub config: ConfigStore,
+    pub email_task: tokio::sync::mpsc::Sender<CurrentEmail>,
+    pub tasks: TaskRegistry,
+    pub http: std::sync::Arc<serenity::Http>,
+    pub guild_id: serenity::GuildId,
+}
+
 
 
 
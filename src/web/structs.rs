@@ -19,5 +19,5 @@ pub struct Response<T> {
 #[allow(unused)]
 pub  struct Code {
     pub code: String,
-    pub email: String,
+    pub email: Option<String>,
 }
@@ -0,0 +1,488 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use dashmap::DashMap;
+use poise::serenity_prelude::{self as serenity, AttachmentType, Mentionable};
+use tracing::debug;
+
+use crate::{
+    config::{FacultyManagerConfig, FacultyManagerGeneralConfig, FacultyManagerLevelRolesConfig},
+    leveling,
+    prelude::Error,
+    structs, utils, Data,
+};
+
+/// The recent messages of a user, only kept in memory, so a restart resets cooldowns
+#[derive(Debug, Default)]
+struct Activity {
+    last_award: Option<Instant>,
+    /// Normalized content of the last messages, newest last
+    recent: VecDeque<String>,
+}
+
+/// Adds XP to a user, announces a level-up in the xp channel and grants the roles of the new level
+pub async fn award(
+    ctx: &serenity::Context,
+    data: &Data,
+    guild_id: serenity::GuildId,
+    config: &FacultyManagerConfig,
+    user: &serenity::User,
+    xp: f64,
+) -> Result<(), Error> {
+    // added up in the database, so messages and voice time awarded at once both count
+    let user_data = sqlx::query_as::<sqlx::Postgres, structs::UserXP>(
+        "INSERT INTO user_xp (user_id, user_xp) VALUES ($1, $2)
+        ON CONFLICT (user_id) DO UPDATE SET user_xp = user_xp.user_xp + $2
+        RETURNING *",
+    )
+    .bind(user.id.0 as i64)
+    .bind(xp)
+    .fetch_one(&data.db)
+    .await
+    .map_err(Error::Database)?;
+
+    debug!(
+        "{}: +{} -> {} | Level: {}",
+        user.name, xp, user_data.user_xp, user_data.user_level
+    );
+
+    // determine if level-up occurred
+    let new_level = data.config.leveling().level_for_xp(user_data.user_xp);
+    if new_level <= user_data.user_level {
+        return Ok(());
+    }
+    let updated = sqlx::query(
+        "UPDATE user_xp SET user_level = $2 WHERE user_id = $1 AND user_level < $2",
+    )
+    .bind(user.id.0 as i64)
+    .bind(new_level)
+    .execute(&data.db)
+    .await
+    .map_err(Error::Database)?;
+    if updated.rows_affected() == 0 {
+        // announced by a concurrent award already
+        return Ok(());
+    }
+
+    // the roles come first, a failed level-up message mustn't keep them from being granted, and
+    // missing permissions for a role shouldn't stop the next level-ups
+    if !config.level_roles.roles.is_empty() {
+        let synced = match guild_id.member(ctx, user.id).await {
+            Ok(mut member) => {
+                sync_level_roles(ctx, &mut member, new_level, &config.level_roles).await
+            }
+            Err(why) => Err(Error::Serenity(why)),
+        };
+        if let Err(why) = synced {
+            tracing::warn!("Failed to update level roles of {}: {}", user.name, why);
+        }
+    }
+
+    // generate level-up message
+    let banner = utils::levelup_banner().await?;
+    config
+        .channels
+        .xp
+        .send_message(ctx, |f| {
+            f.content(format!(
+                "congrats {}! you've levelled up to {}!",
+                user.mention(),
+                new_level
+            ))
+            .embed(|e| {
+                e.title(format!("{} has reached LEVEL {}", user.name, new_level))
+                    .image("attachment://levelup.png")
+                    .color(0xb00b69)
+            })
+            .add_file(AttachmentType::Bytes {
+                data: std::borrow::Cow::Borrowed(&banner),
+                filename: "levelup.png".to_string(),
+            })
+        })
+        .await
+        .map_err(Error::Serenity)?;
+
+    Ok(())
+}
+
+/// Grants a member the roles of their level and removes the level roles they shouldn't have,
+/// returns whether any role changed
+pub async fn sync_level_roles(
+    ctx: &serenity::Context,
+    member: &mut serenity::Member,
+    level: i32,
+    level_roles: &FacultyManagerLevelRolesConfig,
+) -> Result<bool, Error> {
+    let mut reached = level_roles
+        .roles
+        .iter()
+        .filter(|level_role| level_role.level <= level)
+        .collect::<Vec<_>>();
+    reached.sort_by_key(|level_role| level_role.level);
+
+    let wanted = if level_roles.remove_lower {
+        reached.last().map(|level_role| vec![level_role.role]).unwrap_or_default()
+    } else {
+        reached.iter().map(|level_role| level_role.role).collect()
+    };
+
+    let add = wanted
+        .iter()
+        .filter(|role| !member.roles.contains(role))
+        .copied()
+        .collect::<Vec<_>>();
+    let mut remove = level_roles
+        .roles
+        .iter()
+        .map(|level_role| level_role.role)
+        .filter(|role| !wanted.contains(role) && member.roles.contains(role))
+        .collect::<Vec<_>>();
+    remove.sort();
+    remove.dedup();
+
+    if !add.is_empty() {
+        member.add_roles(ctx, &add).await.map_err(Error::Serenity)?;
+    }
+    if !remove.is_empty() {
+        member.remove_roles(ctx, &remove).await.map_err(Error::Serenity)?;
+    }
+
+    Ok(!add.is_empty() || !remove.is_empty())
+}
+
+/// What the XP of a member's messages in a channel are multiplied with
+#[derive(Debug, Clone)]
+pub struct Multipliers {
+    pub channel: f64,
+    pub role: f64,
+    /// Names and multipliers of the events running today
+    pub events: Vec<(String, f64)>,
+}
+
+impl Multipliers {
+    /// Resolves the multipliers of a channel and a member's roles
+    ///
+    /// Channels without their own multiplier use the one of their category, threads the one of
+    /// their channel. Of the member's roles the highest multiplier counts, but a role with 0
+    /// excludes its members. Events running at the same time stack.
+    pub async fn resolve(
+        ctx: &serenity::Context,
+        channel_id: serenity::ChannelId,
+        roles: &[serenity::RoleId],
+        general: &FacultyManagerGeneralConfig,
+    ) -> Self {
+        let parents = parents(ctx, channel_id).await;
+        let channel = parents
+            .iter()
+            .find_map(|id| general.xp_channel_multipliers.get(id))
+            .or_else(|| {
+                parents
+                    .iter()
+                    .find_map(|id| general.xp_category_multipliers.get(id))
+            })
+            .copied()
+            .unwrap_or(1.0);
+
+        let role_multipliers = roles
+            .iter()
+            .filter_map(|role| general.xp_role_multipliers.get(role).copied())
+            .collect::<Vec<_>>();
+        let role = if role_multipliers.contains(&0.0) {
+            0.0
+        } else {
+            role_multipliers.into_iter().reduce(f64::max).unwrap_or(1.0)
+        };
+
+        let today = chrono::Local::now().date_naive();
+        let events = general
+            .xp_events
+            .iter()
+            .filter(|event| event.is_active(today))
+            .map(|event| (event.name.clone(), event.multiplier))
+            .collect();
+
+        Self {
+            channel,
+            role,
+            events,
+        }
+    }
+
+    pub fn total(&self) -> f64 {
+        self.channel * self.role * self.events.iter().map(|(_, m)| m).product::<f64>()
+    }
+}
+
+/// A channel followed by its parents, i.e. the channel of a thread and the category
+async fn parents(
+    ctx: &serenity::Context,
+    channel_id: serenity::ChannelId,
+) -> Vec<serenity::ChannelId> {
+    let mut chain = vec![channel_id];
+    // thread -> channel -> category
+    while chain.len() < 3 {
+        let current = chain[chain.len() - 1];
+        let parent = match current.to_channel(ctx).await {
+            Ok(serenity::Channel::Guild(channel)) => channel.parent_id,
+            _ => None,
+        };
+        match parent {
+            Some(parent) => chain.push(parent),
+            None => break,
+        }
+    }
+    chain
+}
+
+/// Decides how much XP a message earns
+///
+/// Users earn XP at most once per cooldown and messages repeating one of the user's last messages
+/// earn less with every repetition. The cap per message is applied by the caller, after the
+/// channel, role and event multipliers.
+#[derive(Clone)]
+pub struct XpTracker {
+    activity: Arc<DashMap<serenity::UserId, Activity>>,
+    code_blocks: regex::Regex,
+    links: regex::Regex,
+}
+
+impl XpTracker {
+    pub fn new() -> Self {
+        Self {
+            activity: Arc::new(DashMap::new()),
+            code_blocks: regex::Regex::new(r"(?s)```.*?```|`[^`\n]+`").expect("valid regex"),
+            links: regex::Regex::new(r"(?i)\b(?:https?|ftp)://\S+").expect("valid regex"),
+        }
+    }
+
+    /// The XP a message earns before multipliers and the cap, `None` while the user is on
+    /// cooldown or if nothing in it counts
+    pub fn message_xp(
+        &self,
+        user_id: serenity::UserId,
+        content: &str,
+        level: i32,
+        general: &FacultyManagerGeneralConfig,
+    ) -> Option<f64> {
+        let chars = self.countable_chars(content, general);
+        if chars == 0 {
+            return None;
+        }
+
+        let mut activity = self.activity.entry(user_id).or_default();
+
+        // messages sent during the cooldown still count as repeats of later ones
+        let normalized = normalize(content);
+        let repeats = activity
+            .recent
+            .iter()
+            .filter(|recent| similarity(recent, &normalized) >= general.xp_repeat_similarity)
+            .count();
+        activity.recent.push_back(normalized);
+        while activity.recent.len() > general.xp_repeat_window {
+            activity.recent.pop_front();
+        }
+
+        let cooldown = Duration::from_secs(general.xp_cooldown_seconds);
+        if activity
+            .last_award
+            .is_some_and(|last_award| last_award.elapsed() < cooldown)
+        {
+            return None;
+        }
+        activity.last_award = Some(Instant::now());
+
+        let base_xp = chars as f64 / general.chars_for_level as f64;
+        let xp = base_xp * leveling::xp_scaling(level, general.xp_scaling_factor);
+        Some(xp * general.xp_repeat_factor.powi(repeats as i32))
+    }
+
+    /// Characters of a message that earn XP, whitespace runs count once
+    fn countable_chars(&self, content: &str, general: &FacultyManagerGeneralConfig) -> usize {
+        let mut content = content.to_string();
+        if !general.xp_count_code_blocks {
+            content = self.code_blocks.replace_all(&content, " ").into_owned();
+        }
+        if !general.xp_count_links {
+            content = self.links.replace_all(&content, " ").into_owned();
+        }
+
+        content
+            .split_whitespace()
+            .collect::<Vec<_>>()
+            .join(" ")
+            .chars()
+            .count()
+    }
+}
+
+impl Default for XpTracker {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Lowercase words without punctuation, so `Hi!!` and `hi` are the same message
+fn normalize(content: &str) -> String {
+    content
+        .split(|c: char| !c.is_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .map(str::to_lowercase)
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// Dice coefficient of the character pairs of two messages, 1 for equal ones
+fn similarity(a: &str, b: &str) -> f64 {
+    if a == b {
+        return 1.0;
+    }
+
+    let bigrams = |s: &str| {
+        let chars = s.chars().collect::<Vec<_>>();
+        let mut counts = HashMap::new();
+        for pair in chars.windows(2) {
+            *counts.entry((pair[0], pair[1])).or_insert(0usize) += 1;
+        }
+        counts
+    };
+    let (a, b) = (bigrams(a), bigrams(b));
+    let total = a.values().sum::<usize>() + b.values().sum::<usize>();
+    if total == 0 {
+        return 0.0;
+    }
+
+    let shared = a
+        .iter()
+        .map(|(pair, count)| (*count).min(*b.get(pair).unwrap_or(&0)))
+        .sum::<usize>();
+    2.0 * shared as f64 / total as f64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const USER: serenity::UserId = serenity::UserId(1);
+
+    /// 10 characters per XP, no level scaling, no cooldown and repeats earning half as much
+    fn general() -> FacultyManagerGeneralConfig {
+        let mut general: FacultyManagerGeneralConfig = serde_json::from_value(serde_json::json!({
+            "adstimeout": 0,
+            "charsForLevel": 10,
+            "xpScalingFactor": 0.0,
+        }))
+        .unwrap();
+        general.xp_cooldown_seconds = 0;
+        general.xp_repeat_window = 3;
+        general.xp_repeat_similarity = 0.8;
+        general.xp_repeat_factor = 0.5;
+        general
+    }
+
+    #[test]
+    fn similarity_of_messages() {
+        assert_eq!(similarity("hallo zusammen", "hallo zusammen"), 1.0);
+        assert_eq!(similarity("", ""), 1.0);
+        assert_eq!(similarity("a", "b"), 0.0);
+        assert_eq!(similarity("abcd", "wxyz"), 0.0);
+        // ab, bc, cd against ab, bc, ce
+        assert!((similarity("abcd", "abce") - 2.0 / 3.0).abs() < 1e-9);
+
+        let a = normalize("Wann ist die Klausur in Mathe?");
+        assert!(similarity(&a, &normalize("wann ist die klausur in mathe")) == 1.0);
+        assert!(similarity(&a, &normalize("Wann ist die Klausur in Mathe 2?")) > 0.8);
+        assert!(similarity(&a, &normalize("Hat jemand die Folien von gestern?")) < 0.5);
+    }
+
+    #[test]
+    fn normalize_messages() {
+        assert_eq!(normalize("Hi!!"), "hi");
+        assert_eq!(normalize("  Gute   Nacht, Leute :)"), "gute nacht leute");
+        assert_eq!(normalize("???"), "");
+    }
+
+    #[test]
+    fn countable_chars_of_messages() {
+        let tracker = XpTracker::new();
+        let mut general = general();
+
+        assert_eq!(tracker.countable_chars("hallo   du\n\nda", &general), 11);
+        assert_eq!(tracker.countable_chars("   ", &general), 0);
+
+        let code = "schau mal ```rust\nfn main() {}\n``` und `x + 1`";
+        assert_eq!(tracker.countable_chars(code, &general), "schau mal und".len());
+        let link = "siehe https://www.hs-kempten.de/news?id=1 hier";
+        assert_eq!(tracker.countable_chars(link, &general), "siehe hier".len());
+        assert_eq!(tracker.countable_chars("HTTP://example.com", &general), 0);
+
+        general.xp_count_code_blocks = true;
+        general.xp_count_links = true;
+        assert_eq!(
+            tracker.countable_chars(code, &general),
+            code.split_whitespace().collect::<Vec<_>>().join(" ").len()
+        );
+        assert_eq!(tracker.countable_chars(link, &general), link.len());
+    }
+
+    #[test]
+    fn messages_without_countable_chars() {
+        let tracker = XpTracker::new();
+        let general = general();
+
+        assert_eq!(tracker.message_xp(USER, "https://example.com", 0, &general), None);
+        assert_eq!(tracker.message_xp(USER, "```\ncode\n```", 0, &general), None);
+        // they don't start the cooldown
+        assert!(tracker.activity.get(&USER).is_none());
+    }
+
+    #[test]
+    fn repeated_messages() {
+        let tracker = XpTracker::new();
+        let general = general();
+
+        let xp = |content: &str| tracker.message_xp(USER, content, 0, &general).unwrap();
+        assert_eq!(xp("Guten Morgen"), 1.2);
+        assert_eq!(xp("guten morgen"), 0.6);
+        assert_eq!(xp("GUTEN MORGEN"), 0.3);
+        assert_eq!(xp("Wie geht es euch?"), 1.7);
+        assert_eq!(xp("Guten Morgen"), 0.3);
+        // only the last three messages are compared
+        assert_eq!(xp("eins"), 0.4);
+        assert_eq!(xp("zwei"), 0.4);
+        assert_eq!(xp("drei"), 0.4);
+        assert_eq!(xp("Guten Morgen"), 1.2);
+
+        // other users are tracked on their own
+        let other = tracker.message_xp(serenity::UserId(2), "Guten Morgen", 0, &general);
+        assert_eq!(other, Some(1.2));
+    }
+
+    #[test]
+    fn cooldown() {
+        let tracker = XpTracker::new();
+        let mut general = general();
+        general.xp_cooldown_seconds = 60;
+
+        assert_eq!(tracker.message_xp(USER, "erste Nachricht", 0, &general), Some(1.5));
+        assert_eq!(tracker.message_xp(USER, "zweite Nachricht", 0, &general), None);
+
+        // once the cooldown passed messages earn XP again, repeats during it still count
+        tracker.activity.get_mut(&USER).unwrap().last_award =
+            Instant::now().checked_sub(Duration::from_secs(61));
+        assert_eq!(tracker.message_xp(USER, "zweite Nachricht", 0, &general), Some(0.8));
+    }
+
+    #[test]
+    fn level_scaling() {
+        let tracker = XpTracker::new();
+        let mut general = general();
+        general.xp_scaling_factor = 1.0;
+
+        let xp = tracker.message_xp(USER, "a".repeat(20).as_str(), 10, &general).unwrap();
+        assert!((xp - 2.0 / (1.0 + 11f64.ln())).abs() < 1e-9);
+    }
+}